@@ -0,0 +1,34 @@
+use std::error::Error;
+use rllvm::prelude::*;
+
+fn main() -> Result<(), Box<dyn Error>>{
+    let mut contxt = Context::new( Triple::host() )?;
+
+    let add = contxt.add_function("add", vec![Type::u32, Type::u32], Type::u32);
+    let asm = add.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+    let y = asm.arg(1).unwrap();
+
+    add.ir.push( Return::new(*(x + y)) );
+
+    let func = contxt.add_function("main", vec![Type::u32], Type::u32);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+    let out = asm.var(Type::u32).unwrap();
+
+    func.ir.push( Call::new("add", vec![x, x], Some(out)) );
+    func.ir.push( Return::new(out) );
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(u32) -> u32> = contxt.get_jit_function("main")?;
+        let out = func.call(5);
+
+        println!("main(5) -> {}", out);
+
+        assert_eq!(out, 10);
+    }
+
+    Ok(())
+}
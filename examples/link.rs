@@ -14,8 +14,8 @@ fn main() {
         0xc3,                           // ret
     ], false);
 
-    linker.add_reloc( Link { from: "main".into(), to: "test".into(), at: 1, size: 4, replace: false} );
-    linker.add_reloc( Link { from: "test".into(), to: "test_data".into(), at: 1, size: 4, replace: true} );
+    linker.add_reloc( Link { from: "main".into(), to: "test".into(), at: 1, size: 4, replace: false, kind: LinkKind::Relative} );
+    linker.add_reloc( Link { from: "test".into(), to: "test_data".into(), at: 1, size: 4, replace: true, kind: LinkKind::Relative} );
     linker.add_label("test_data", vec![5]);
 
     unsafe {
@@ -1,13 +1,28 @@
 use std::{error::Error, fmt::Display};
 
-use target_lexicon::{Architecture::{X86_32, X86_64}, CallingConvention::*, Triple, X86_32Architecture::*};
+use target_lexicon::{Architecture::{X86_32, X86_64}, Triple, X86_32Architecture::*};
 use crate::{func::Function, ir::r#type::Type, target::call_conv::TargetCallConv};
 use super::{jit::JitFunction, link::JitLinker};
+#[cfg(feature = "jit")]
+use super::link::{globals::JitGlobals, load_lib::SharedLibary};
+
+/// A function which is called by `Trap` and `Unreachable` instead of crashing with `ud2`
+/// 
+/// Gets the name of the trapping function (null terminated), the code of the trap
+/// and the index of the trap in the ir of the function (counted over all blocks).
+/// If the handler returns, the trapping function returns a zeroed value
+pub type TrapHandler = extern "C" fn(func: *const std::ffi::c_char, code: u32, at: usize);
+
+/// The name of the external function which is called by traps if a trap handler is set
+/// 
+/// In object files it is imported, so the program needs to define it
+pub const TRAP_HANDLER: &str = "rllvm_trap";
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ContextError {
     UnsuportedArch(String),
     UnsuportedCall(String),
+    UnresolvedExtern(String),
 }
 
 impl Display for ContextError {
@@ -15,6 +30,7 @@ impl Display for ContextError {
         let msg = match self {
             ContextError::UnsuportedArch(arch) => format!("given architecture {arch} isn't currently supported"),
             ContextError::UnsuportedCall(call) => format!("given calling convention {call} isn't currently supported"),
+            ContextError::UnresolvedExtern(name) => format!("couldn't find the external function {name}"),
         };
 
         write!(f, "{}", msg)
@@ -54,9 +70,24 @@ impl std::error::Error for ContextError {}
 /// ```
 pub struct Context {
     funcs: Vec<Function>,
+    externs: Vec<(String, Option<usize>)>,
+    globals: Vec<(String, Type, Option<Vec<u8>>, bool)>,
+
+    #[cfg(feature = "jit")]
+    libs: Vec<SharedLibary>,
+    #[cfg(feature = "jit")]
+    jit_globals: JitGlobals,
 
     pub call: TargetCallConv,
     triple: Triple,
+
+    avx: bool,
+    popcnt: bool,
+    lzcnt: bool,
+    bmi1: bool,
+    fma: bool,
+
+    trap_handler: Option<TrapHandler>,
 }
 
 impl Context {
@@ -72,28 +103,177 @@ impl Context {
                 return Err( ContextError::UnsuportedArch( format!("{}", arch)) );
         }
 
-        if  !(
-            call == WindowsFastcall ||
-            call == SystemV
-        ) {
-                return Err( ContextError::UnsuportedCall( "call".into() ) );
-        }
-
         Ok(Self { 
             funcs: vec![],
-            call: TargetCallConv::new(call),
+            externs: vec![],
+            globals: vec![],
+            #[cfg(feature = "jit")]
+            libs: vec![],
+            #[cfg(feature = "jit")]
+            jit_globals: JitGlobals::new(),
+            call: TargetCallConv::new(call)?,
             triple: target,
+            avx: false,
+            popcnt: false,
+            lzcnt: false,
+            bmi1: false,
+            fma: false,
+            trap_handler: None,
         })
     }
 
+    /// Allows the code generation to use AVX (needed for 256 bit vectors)
+    /// 
+    /// Only affects functions which are added afterwards
+    pub fn enable_avx(&mut self) {
+        self.avx = true;
+    }
+
+    /// Returns if the code generation can use AVX
+    pub fn avx(&self) -> bool {
+        self.avx
+    }
+
+    /// Allows the code generation to use `popcnt` (for the `ctpop` intrinsic)
+    /// 
+    /// Only affects functions which are added afterwards
+    pub fn enable_popcnt(&mut self) {
+        self.popcnt = true;
+    }
+
+    /// Returns if the code generation can use `popcnt`
+    pub fn popcnt(&self) -> bool {
+        self.popcnt
+    }
+
+    /// Allows the code generation to use `lzcnt` (for the `ctlz` intrinsic)
+    /// 
+    /// Only affects functions which are added afterwards
+    pub fn enable_lzcnt(&mut self) {
+        self.lzcnt = true;
+    }
+
+    /// Returns if the code generation can use `lzcnt`
+    pub fn lzcnt(&self) -> bool {
+        self.lzcnt
+    }
+
+    /// Allows the code generation to use the BMI1 instructions like `tzcnt` (for the `cttz` intrinsic)
+    /// 
+    /// Only affects functions which are added afterwards
+    pub fn enable_bmi1(&mut self) {
+        self.bmi1 = true;
+    }
+
+    /// Returns if the code generation can use the BMI1 instructions
+    pub fn bmi1(&self) -> bool {
+        self.bmi1
+    }
+
+    /// Allows the code generation to use the fused multiply add instructions (for the `fma` intrinsic,
+    /// which calls the libc otherwise)
+    /// 
+    /// Only affects functions which are added afterwards
+    pub fn enable_fma(&mut self) {
+        self.fma = true;
+    }
+
+    /// Returns if the code generation can use the fused multiply add instructions
+    pub fn fma(&self) -> bool {
+        self.fma
+    }
+
+    /// Makes traps call the handler instead of crashing the process (with `ud2`)
+    /// 
+    /// Only affects functions which are added afterwards
+    pub fn set_trap_handler(&mut self, handler: TrapHandler) {
+        self.trap_handler = Some(handler);
+    }
+
+    /// Returns if traps call a trap handler
+    pub fn trap_handler(&self) -> bool {
+        self.trap_handler.is_some()
+    }
+
     /// Adds a function to the context
     pub fn add_function(&mut self, name: &str, args: Vec<Type>, ret: Type) -> &mut Function {
-        let func = Function::new(name, &self, args, ret);
+        let func = Function::new(name, self, args, ret);
         self.funcs.push(func);
 
         self.funcs.last_mut().unwrap()
     }
 
+    /// Adds a global variable which can be accessed via `GlobalAddr`
+    /// 
+    /// The initializer is padded with zeros (or cut) to the size of the type, without one the global is zeroed.
+    /// In jit mode the globals are allocated by the context, so their values are kept between calls
+    /// (the context has to outlive the jit functions which use them).
+    /// In object files mutable globals are placed into `.data` (or `.bss` without initializer), others into `.rodata`
+    pub fn add_global(&mut self, name: &str, typ: Type, init: Option<Vec<u8>>, mutable: bool) {
+        #[cfg(feature = "jit")]
+        self.jit_globals.add(name, &Context::global_data(&typ, &init), typ.align());
+
+        self.globals.push((name.to_string(), typ, init, mutable));
+    }
+
+    #[cfg(feature = "jit")]
+    /// Returns the address of the global with the given name in jit mode
+    pub fn global_adr(&self, name: &str) -> Option<usize> {
+        self.jit_globals.get(name)
+    }
+
+    /// Returns the data of the global (`init` fit to the size of the type)
+    fn global_data(typ: &Type, init: &Option<Vec<u8>>) -> Vec<u8> {
+        let mut data = init.clone().unwrap_or_default();
+        data.resize(typ.size(), 0);
+
+        data
+    }
+
+    /// Declares a function which is defined outside of the context (e.g. `malloc` or `printf`)
+    /// so it can be called via `Call`
+    /// 
+    /// In jit mode it is searched in the libaries loaded by `load_lib` and in the running program.
+    /// In object files it becomes an imported symbol
+    pub fn add_extern(&mut self, name: &str) {
+        self.externs.push((name.to_string(), None));
+    }
+
+    /// Declares an external function which is located at the given address in jit mode
+    /// 
+    /// In object files it becomes an imported symbol
+    pub fn add_extern_at(&mut self, name: &str, adr: usize) {
+        self.externs.push((name.to_string(), Some(adr)));
+    }
+
+    #[cfg(feature = "jit")]
+    /// Loads a shared libary in which the external functions are searched in jit mode
+    pub fn load_lib(&mut self, path: &str) -> Result<(), Box<dyn Error>> {
+        self.libs.push(SharedLibary::new(path)?);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "jit")]
+    /// Returns the address of the external function with the given name
+    fn resolve(&self, name: &str) -> Result<usize, Box<dyn Error>> {
+        for lib in &self.libs {
+            if let Ok(adr) = unsafe { lib.get_address(name) } {
+                return Ok(adr);
+            }
+        }
+
+        if let Ok(adr) = unsafe { SharedLibary::this()?.get_address(name) } {
+            return Ok(adr);
+        }
+
+        if let Some(adr) = crate::ir::intrinsics::host_function(name) {
+            return Ok(adr);
+        }
+
+        Err(Box::from(ContextError::UnresolvedExtern(name.to_string())))
+    }
+
     #[cfg(feature = "jit")]
     /// Compiles the context and requests the given jit function
     /// 
@@ -124,9 +304,14 @@ impl Context {
     /// ```
     pub unsafe fn get_jit_function<T>(&mut self, name: &str) -> Result<JitFunction<T>, Box<dyn std::error::Error>> {
         let mut linker = JitLinker::new();
+        let mut externs = self.externs.clone();
+
+        if let Some(handler) = self.trap_handler {
+            externs.push((TRAP_HANDLER.to_string(), Some(handler as *const () as usize)));
+        }
 
         for func in self.funcs.iter_mut() {
-            let func = func.asm_func()?;
+            let func = func.compile_ir()?;
             let compiled = func.compile()?;
 
             let func_name = func.name();
@@ -142,8 +327,27 @@ impl Context {
             for (name, data) in func.data() {
                 linker.add_label(name, data);
             }
+
+            for name in func.externs() {
+                if !externs.iter().any(|(known, _)| *known == name) {
+                    externs.push((name, None));
+                }
+            }
         } 
 
+        for (name, adr) in self.jit_globals.iter() {
+            linker.add_global(name, *adr);
+        }
+
+        for (name, adr) in &externs {
+            let adr = match adr {
+                Some(adr) => *adr,
+                None => self.resolve(name)?,
+            };
+
+            linker.add_extern(name, adr);
+        }
+
         let func = linker.engine();
         Ok(func)
     }
@@ -176,6 +380,8 @@ impl Context {
 
         let mut renames: HashMap<String, String> = HashMap::new();
 
+        let mut externs: Vec<String> = self.externs.iter().map(|(name, _)| name.to_string()).collect();
+
         // Insert values
         for func in self.funcs.iter_mut() {
             if func.export {
@@ -184,38 +390,76 @@ impl Context {
                 renames.insert(old_name, func.name().to_string());
             }
 
-            let asm = func.asm_func()?;
+            let asm = func.compile_ir()?;
 
             let code = asm.compile()?;
             let data = asm.data();
             let relocs = asm.relocs();
             let name = asm.name.clone();
 
+            for name in asm.externs() {
+                if !externs.contains(&name) {
+                    externs.push(name);
+                }
+            }
+
             funcs.insert(name.to_string(), (code, relocs, data));
         }
 
+        for name in &externs {
+            obj.add_decl(name, Decl::Function(Scope::Import));
+        }
+
+        for (name, typ, init, mutable) in &self.globals {
+            let decl = match (init, mutable) {
+                (None, true) => Decl::UData(Scope::Private),
+                (Some(_), true) => Decl::Data(Scope::Private),
+                (_, false) => Decl::RData(Scope::Private),
+            };
+
+            obj.define(name, Context::global_data(typ, init));
+            obj.add_decl(name, decl);
+        }
+
         for func in funcs {
             obj.define(&func.0, func.1.0);
             obj.add_decl(&func.0, Decl::Function(Scope::Private));
 
+            let mut labels = vec![];
+
             for data in func.1.2 {
                 let name = format!(".L{}", data.0);
                 obj.define(&name, data.1);
                 obj.add_decl(&name, Decl::RData(Scope::Private));
+
+                labels.push(data.0.to_string());
             }
 
             for link in func.1.1 {
                 let mut link = link;
 
+                if labels.contains(&link.from) {
+                    link.from = format!(".L{}", link.from);
+                }
+
+                if labels.contains(&link.to) {
+                    link.to = format!(".L{}", link.to);
+                }
+
                 if renames.contains_key(&link.from) {
                     link.from = renames.get(&link.from).unwrap().to_string();
                 }
                 
                 if renames.contains_key(&link.to) {
-                    link.from = renames.get(&link.to).unwrap().to_string();
+                    link.to = renames.get(&link.to).unwrap().to_string();
                 }
 
-                let formatic_link = super::obj::Link {from: link.from, to: link.to, at: link.at};
+                let kind = match link.kind {
+                    crate::contxt::link::LinkKind::Relative => super::obj::LinkKind::Relative,
+                    crate::contxt::link::LinkKind::TableEntry(addend) => super::obj::LinkKind::TableEntry(addend as i64),
+                };
+
+                let formatic_link = super::obj::Link {from: link.from, to: link.to, at: link.at, kind};
                 obj.link(formatic_link);
             }
         }
@@ -224,4 +468,32 @@ impl Context {
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+impl Display for Context {
+    /// Writes the ir of all functions (with the external functions and globals in front of them)
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (name, _) in &self.externs {
+            writeln!(f, "declare @{}", name)?;
+        }
+
+        for (name, typ, init, mutable) in &self.globals {
+            let kind = if *mutable { "global" } else { "constant" };
+            let init = match init {
+                Some(init) => format!("{:?}", init),
+                None => "zeroinitializer".into(),
+            };
+
+            writeln!(f, "@{} = {} {} {}", name, kind, typ, init)?;
+        }
+
+        for (nr, func) in self.funcs.iter().enumerate() {
+            if nr > 0 || !self.externs.is_empty() || !self.globals.is_empty() {
+                writeln!(f)?;
+            }
+
+            writeln!(f, "{}", func)?;
+        }
+
+        Ok(())
+    }
+}
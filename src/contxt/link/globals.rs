@@ -0,0 +1,56 @@
+use std::{alloc::{self, Layout}, collections::HashMap, ptr};
+
+/// The memory of the global variables in jit mode
+///
+/// Every global gets its own writable allocation which lives as long as the `JitGlobals`,
+/// so the values are kept between calls of the jit functions
+#[derive(Debug, Default)]
+pub struct JitGlobals {
+    adrs: HashMap<String, usize>,
+    allocs: Vec<(*mut u8, Layout)>,
+}
+
+impl JitGlobals {
+    /// Creates an empty set of globals
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allocates the global, initializes it with the data and returns its address
+    ///
+    /// If there already is a global with the name, the name refers to the new one
+    /// (the old memory stays allocated, because linked code can still use it)
+    pub fn add(&mut self, name: &str, data: &[u8], align: usize) -> usize {
+        let layout = Layout::from_size_align(data.len().max(1), align.max(1)).unwrap();
+
+        let mem = unsafe { alloc::alloc_zeroed(layout) };
+        if mem.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+
+        unsafe { ptr::copy_nonoverlapping(data.as_ptr(), mem, data.len()) };
+
+        self.allocs.push((mem, layout));
+        self.adrs.insert(name.to_string(), mem as usize);
+
+        mem as usize
+    }
+
+    /// Returns the address of the global with the given name
+    pub fn get(&self, name: &str) -> Option<usize> {
+        self.adrs.get(name).copied()
+    }
+
+    /// Returns the names and addresses of all globals
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &usize)> {
+        self.adrs.iter()
+    }
+}
+
+impl Drop for JitGlobals {
+    fn drop(&mut self) {
+        for (mem, layout) in &self.allocs {
+            unsafe { alloc::dealloc(*mem, *layout) };
+        }
+    }
+}
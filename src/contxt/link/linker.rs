@@ -10,6 +10,16 @@ pub struct Link {
     pub at: usize,
     pub size: usize,
     pub replace: bool,
+    pub kind: LinkKind,
+}
+
+/// How the value of a (not replacing) link is calculated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkKind {
+    /// The offset of the target relative to the end of the link (e.g. for `call` or rip relative adresses)
+    Relative,
+    /// The offset of the target (+ addend) relative to the start of the `from` symbol (e.g. for jump table entries)
+    TableEntry(isize),
 }
 
 /// ### JitLinker - A runtime linker for JIT functions
@@ -18,7 +28,7 @@ pub struct Link {
 /// 
 /// Example usage:
 /// ```
-/// use rllvm::contxt::{jit::JitFunction, link::{JitLinker, Link}};
+/// use rllvm::contxt::{jit::JitFunction, link::{JitLinker, Link, LinkKind}};
 /// fn main() {
 ///     let mut linker = JitLinker::new();
 /// 
@@ -32,7 +42,7 @@ pub struct Link {
 ///         0xc3,                           // ret
 ///     ], false);
 /// 
-///     linker.relocs.push( Link { from: "main".into(), to: "test".into(), at: 1, size: 4, replace: false});
+///     linker.relocs.push( Link { from: "main".into(), to: "test".into(), at: 1, size: 4, replace: false, kind: LinkKind::Relative});
 ///
 ///     unsafe {
 ///         let mut func: JitFunction<unsafe extern "C" fn() -> u32> = linker.engine();
@@ -45,6 +55,7 @@ pub struct Link {
 pub struct JitLinker {
     funcs: HashMap<String, (Vec<u8>, bool)>,
     labels: HashMap<String, Vec<u8>>,
+    globals: HashMap<String, usize>,
     
     pub relocs: Vec<Link>,
 }
@@ -55,6 +66,7 @@ impl JitLinker {
         Self {
             funcs: HashMap::new(),
             labels: HashMap::new(),
+            globals: HashMap::new(),
 
             relocs: vec![],
         }
@@ -65,11 +77,32 @@ impl JitLinker {
         self.funcs.insert(name.to_string(), (code, entry));
     }
 
+    /// Adds a function which lives outside of the linked code at the given address
+    /// 
+    /// A jump to the address is linked in, so the function can be called like any other function
+    pub fn add_extern(&mut self, name: &str, adr: usize) {
+        let mut code = vec![
+            0xff, 0x25, 0x00, 0x00, 0x00, 0x00, // jmp [rip]
+        ];
+
+        code.extend_from_slice(&(adr as u64).to_le_bytes());
+
+        self.add_func(name, code, false);
+    }
+
     /// Adds a label
     pub fn add_label(&mut self, name: &str, data: Vec<u8>) {
         self.labels.insert(name.to_string(), data);
     }
 
+    /// Adds a global variable which lives outside of the linked code at the given address
+    /// 
+    /// The address is linked in as a pointer, so a `lea reg, [rip + global]` which is linked to the global
+    /// is turned into a `mov reg, [rip + pointer]`
+    pub fn add_global(&mut self, name: &str, adr: usize) {
+        self.globals.insert(name.to_string(), adr);
+    }
+
     /// Adds an relocation
     pub fn add_reloc(&mut self, link: Link) {
         self.relocs.push(link);
@@ -78,6 +111,9 @@ impl JitLinker {
     /// Links the code into a `Vec<u8>`
     pub fn link(&mut self) -> Vec<u8> {
         let mut ret: Vec<u8> = vec![];
+        let pointers: Vec<(&String, Vec<u8>)> = self.globals.iter()
+            .map(|(name, adr)| (name, (*adr as u64).to_le_bytes().to_vec()))
+            .collect();
         let mut ret_hash: HashMap<&String, Vec<u8>> = HashMap::new();
 
         let mut funcs_p: HashMap<&String, (&Vec<u8>, usize)> = HashMap::new();
@@ -132,6 +168,16 @@ impl JitLinker {
             funcs_p.insert(&label.0, (&label.1, offset));
         }
 
+        for (name, adr) in &pointers {
+            // the memory is page aligned, so aligning the offset aligns the address
+            ret.resize(ret.len().next_multiple_of(8), 0);
+
+            let offset = ret.len();
+            ret.extend_from_slice(adr);
+
+            funcs_p.insert(name, (adr, offset));
+        }
+
         for link in self.relocs.iter() {
             let offset = funcs_p.get(&link.from).unwrap().1;
             let target = funcs_p.get(&link.to).unwrap();
@@ -139,18 +185,28 @@ impl JitLinker {
 
             let at = offset + link.at;
 
+            // the global itself isn't in reach, so its address is loaded from the pointer
+            if self.globals.contains_key(&link.to) && link.kind == LinkKind::Relative && at >= 2 && ret[at - 2] == 0x8D {
+                ret[at - 2] = 0x8B;
+            }
+
             let mut pos: Vec<u8> = vec![];
 
             if link.replace {
                 let x = target.0;
 
-                for i in 0..(link.size - 1) {
+                for i in 0..link.size {
                     let given = x.get(i);
                     match given {
                         Some(x) => pos.push(*x),
                         None => pos.push(0),
                     }
                 }
+            } else if let LinkKind::TableEntry(addend) = link.kind {
+                let value = target.1 as isize + addend - offset as isize;
+
+                pos.extend_from_slice(&(value as i32).to_le_bytes());
+                pos.resize(link.size, 0);
             } else {
                 let _pos = target.1 as i32;
                 let _pos = _pos - link.size as i32;
@@ -159,7 +215,7 @@ impl JitLinker {
                 
                 let _pos = _pos.to_le_bytes();
 
-                for i in 0..(link.size - 1) {
+                for i in 0..link.size {
                     let given = _pos.get(i);
                     match given {
                         Some(x) => pos.push(*x),
@@ -168,7 +224,7 @@ impl JitLinker {
                 }
             }
             
-            for b in 0..(link.size - 1) {
+            for b in 0..link.size {
                 ret[(at + b) as usize] = pos[b];
             }
         }
@@ -13,10 +13,35 @@ impl SharedLibary {
         )
     }
 
+    /// Returns the currently running program as a libary
+    /// (so the symbols which are already loaded into the process can be used)
+    pub fn this() -> Result<Self, Box<dyn std::error::Error>> {
+        #[cfg(unix)]
+        let libary = libloading::os::unix::Library::this();
+        #[cfg(windows)]
+        let libary = libloading::os::windows::Library::this()?;
+
+        Ok(
+            Self {
+                libary: libary.into(),
+            }
+        )
+    }
+
     pub unsafe fn get_func_pointer<T: Copy>(&self, name: &str) -> Result<T, Box<dyn std::error::Error>> {
         let func: Symbol<*mut T> = self.libary.get(name.as_bytes().into())?;
         let casted: *mut T = func.cast();
 
         Ok(*casted)
     }
-}
\ No newline at end of file
+
+    /// Returns the address of the symbol with the given name
+    /// 
+    /// # Safety
+    /// The symbol is only looked up, but using the address requires knowing what the symbol really is
+    pub unsafe fn get_address(&self, name: &str) -> Result<usize, Box<dyn std::error::Error>> {
+        let sym: Symbol<*const u8> = self.libary.get(name.as_bytes())?;
+
+        Ok(*sym as usize)
+    }
+}
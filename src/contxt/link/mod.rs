@@ -1,4 +1,5 @@
 pub mod linker;
 pub mod load_lib;
+pub mod globals;
 
-pub use linker::*;
\ No newline at end of file
+pub use linker::*;
@@ -9,6 +9,9 @@ pub enum Decl {
 
     /// Read only data
     RData(Scope),
+
+    /// Writable data
+    Data(Scope),
 }
 
 /// The visibility of the decl 
@@ -4,4 +4,14 @@ pub struct Link {
     pub from: String,
     pub to: String,
     pub at: usize,
+    pub kind: LinkKind,
+}
+
+/// How the value of a link is calculated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LinkKind {
+    /// The offset of the target relative to the end of the link (e.g. for `call` or rip relative adresses)
+    Relative,
+    /// The offset of the target (+ addend) relative to the start of the `from` symbol (e.g. for jump table entries)
+    TableEntry(i64),
 }
\ No newline at end of file
@@ -4,7 +4,7 @@ use object::{
     write::{Relocation, SectionId, StandardSection, Symbol, SymbolId, SymbolSection}, Architecture, RelocationEncoding, RelocationFlags, RelocationKind, SymbolFlags, SymbolKind, SymbolScope
 };
 
-use super::{Decl, Link, ObjectError, Scope};
+use super::{Decl, Link, LinkKind, ObjectError, Scope};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 /// Enum which specifies the binary format
@@ -200,8 +200,52 @@ impl ObjectBuilder {
                             scope = SymbolScope::Compilation
                         }
 
+                        let section = obj.section_id(StandardSection::UninitializedData);
+                        let offset = obj.append_section_bss(section, data.len() as u64, 16);
+                        let symbol = obj.add_symbol(Symbol {
+                            name: name.as_bytes().into(),
+                            value: offset,
+                            size: data.len() as u64,
+                            kind: SymbolKind::Data,
+                            scope,
+                            weak: false,
+                            section: SymbolSection::Section(section),
+                            flags: SymbolFlags::None,
+                        });
+
+                        funcs.insert(name.into(), ((section, offset), symbol));
+                    }
+                },
+
+                Decl::Data(s) => match s {
+                    Scope::Import => {
+                        ids.insert(
+                            name.to_string(),
+                            obj.add_symbol(Symbol {
+                                name: name.as_bytes().into(),
+                                value: 0,
+                                size: 0,
+                                kind: SymbolKind::Data,
+                                scope: SymbolScope::Dynamic,
+                                weak: false,
+                                section: SymbolSection::Undefined,
+                                flags: SymbolFlags::None,
+                            }),
+                        );
+                    }
+                    _ => {
+                        let dat_opt = self.sym.get(&name.clone());
+
+                        if dat_opt.is_none() {
+                            return Err(Box::from(ObjectError::DeclWithoutSymbol));
+                        }
+
+                        let data = dat_opt.unwrap();
+
+                        let scope = if *s == Scope::Export { SymbolScope::Linkage } else { SymbolScope::Compilation };
+
                         let (section, offset) = obj.add_subsection(
-                            StandardSection::UninitializedData,
+                            StandardSection::Data,
                             name.as_bytes().into(),
                             data,
                             16,
@@ -211,7 +255,7 @@ impl ObjectBuilder {
                             value: offset,
                             size: data.len() as u64,
                             kind: SymbolKind::Data,
-                            scope: scope,
+                            scope,
                             weak: false,
                             section: SymbolSection::Section(section),
                             flags: SymbolFlags::None,
@@ -302,15 +346,25 @@ impl ObjectBuilder {
                 )));
             }
 
+            // calls go through the plt, data is addressed relative to rip
+            let is_func = self.decls.iter().any(|(name, decl)| name == &link.to && matches!(decl, Decl::Function(_)));
+
+            let (kind, encoding, addend) = match link.kind {
+                LinkKind::Relative if is_func => (RelocationKind::PltRelative, RelocationEncoding::X86Branch, -4),
+                LinkKind::Relative => (RelocationKind::Relative, RelocationEncoding::Generic, -4),
+                // the entry is relative to the start of the table (`from`), not to the entry itself
+                LinkKind::TableEntry(addend) => (RelocationKind::Relative, RelocationEncoding::Generic, addend + link.at as i64),
+            };
+
             obj.add_relocation(
                 id,
                 Relocation {
                     offset: off + link.at as u64,
                     symbol: sym.unwrap(),
-                    addend: -4,
+                    addend,
                     flags: RelocationFlags::Generic {
-                        kind: RelocationKind::PltRelative,
-                        encoding: RelocationEncoding::X86Branch,
+                        kind,
+                        encoding,
                         size: 32,
                     },
                 },
@@ -1,21 +1,53 @@
-use std::{collections::{HashMap, VecDeque}, error::Error};
+use std::{collections::{HashMap, HashSet}, error::Error};
 
-use iced_x86::code_asm::*;
-use crate::{contxt::{contxt::Context, link::Link}, ir::{r#type::Type, var::VarGen}, target::call_conv::TargetCallConv};
+use iced_x86::{code_asm::*, BlockEncoderOptions, Code, Instruction, MemoryOperand, Register};
+use target_lexicon::CallingConvention;
+use crate::{contxt::{contxt::Context, link::{Link, LinkKind}}, ir::{error::IrError, r#type::Type, var::VarGen}, target::{call_conv::TargetCallConv, reg::{full, sized, ymm}}};
 
 /// Stores the ir for function which can be compiled
 pub struct AsmFunction {
     pub name: String,
     pub asm: CodeAssembler,
-    gen: VecDeque<u8>,
     pub relocs: Vec<(Link, usize)>,
     pub data: HashMap<String, Vec<u8>>,
 
     pub call: TargetCallConv,
+    /// If 256 bit vectors (AVX) can be used
+    pub avx: bool,
+    /// If `popcnt` can be used
+    pub popcnt: bool,
+    /// If `lzcnt` can be used
+    pub lzcnt: bool,
+    /// If the BMI1 instructions (like `tzcnt`) can be used
+    pub bmi1: bool,
+    /// If the fused multiply add instructions (FMA3) can be used
+    pub fma: bool,
+    /// If traps call the trap handler of the context
+    pub trap_handler: bool,
+
+    /// The index of the ir which is compiled (counted over all blocks)
+    pub ir: usize,
+
+    externs: Vec<String>,
 
     req_names: usize,
+    req_relocs: Vec<(String, isize, usize, usize)>,
+    req_tables: Vec<(String, usize, usize)>,
+
+    labels: HashMap<String, CodeLabel>,
+    copies: HashMap<(String, String), Vec<(Register, Register)>>,
+    bound: HashSet<String>,
+    exit: CodeLabel,
+
+    /// The name of the block which is currently compiled
+    pub block: String,
 
     stack_safe: bool,
+    frame: usize,
+    slots: Vec<VarGen>,
+    saved: Vec<Register>,
+
+    vars: Vec<Register>,
 
     pub args: Vec<Type>,
     pub ret: Type,
@@ -24,15 +56,36 @@ pub struct AsmFunction {
 impl AsmFunction {
     /// Creates a function
     pub fn new(name: &str, contxt: &Context) -> Self {
+        let mut asm = CodeAssembler::new(64).unwrap(); // unwrap because i i made it just so it can't give error
+        let exit = asm.create_label();
+
         Self {
             name: name.to_string(),
-            asm: CodeAssembler::new(64).unwrap(), // unwrap because i i made it just so it can't give error
+            asm,
             relocs: vec![],
-            gen: VecDeque::new(),
             data: HashMap::new(),
             call: contxt.call.clone(),
+            avx: contxt.avx(),
+            popcnt: contxt.popcnt(),
+            lzcnt: contxt.lzcnt(),
+            bmi1: contxt.bmi1(),
+            fma: contxt.fma(),
+            trap_handler: contxt.trap_handler(),
+            ir: 0,
+            externs: vec![],
             req_names: 0,
+            req_relocs: vec![],
+            req_tables: vec![],
+            labels: HashMap::new(),
+            copies: HashMap::new(),
+            bound: HashSet::new(),
+            exit,
+            block: "entry".into(),
             stack_safe: false,
+            frame: 0,
+            slots: vec![],
+            saved: vec![],
+            vars: vec![],
             args: vec![],
             ret: Type::u32,
         }
@@ -44,69 +97,354 @@ impl AsmFunction {
     }
 
     /// Makes the function stack safe so you can use the stack
+    /// 
+    /// The prologue and epilogue are added when the function is compiled
     pub fn make_stack_safe(&mut self) -> Result<(), Box<dyn Error>> {
         self.stack_safe = true;
 
-        let mut asm = CodeAssembler::new(64)?;
+        Ok(())
+    }
 
-        asm.endbr64()?;
-        asm.push(rbp)?;
-        asm.mov(rbp, rsp)?;
-        asm.sub(rsp, self.call.shadow as i32)?;
+    /// Reserves a stack slot for a value of the given type and returns it
+    /// 
+    /// The slot is addressed relative to RBP, so the function is made stack safe
+    /// and the slot is added to the frame which is reserved by the prologue
+    pub fn alloca(&mut self, typ: Type) -> VarGen {
+        self.frame = (self.frame + typ.size()).next_multiple_of(typ.align());
+        self.stack_safe = true;
 
-        let gen = asm.assemble(0)?;
+        let slot = VarGen::new_stack(typ, self.frame);
+        self.slots.push(slot);
+
+        slot
+    }
 
-        for byte in gen {
-            self.gen.push_front( byte );
+    /// Returns if the var is a stack slot which was reserved by `alloca`
+    pub fn is_slot(&self, var: &VarGen) -> bool {
+        self.slots.contains(var)
+    }
+
+    /// Reserves the stack slots of the 128 bit arguments which are passed in registers
+    /// (the prologue stores them there)
+    pub fn reserve_arg_slots(&mut self) {
+        let size = self.wide_args().len() * 16;
+
+        if size > 0 {
+            self.stack_safe = true;
+            self.frame = self.frame.max(size);
+        }
+    }
+
+    /// Returns the number and register index of the 128 bit arguments which are passed in registers
+    fn wide_args(&self) -> Vec<(usize, usize)> {
+        let reg_args = self.call.reg_args(&self.args);
+
+        self.args.iter().zip(reg_args).enumerate()
+            .filter_map(|(nr, (typ, index))| if typ.wide() { index.map(|index| (nr, index)) } else { None })
+            .collect()
+    }
+
+    /// Returns the size of the stack frame which is used by the stack slots
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    /// Saves the callee saved register in the prologue and restores it when the function is left
+    /// 
+    /// Needs to be called before the function is compiled (e.g. in `Compile::prepare`), so every exit restores it
+    pub fn save_reg(&mut self, reg: Register) {
+        // only the lower 128 bits of the vector registers are callee saved
+        let reg = if reg.is_vector_register() { Register::XMM0 + reg.number() as u32 } else { reg.full_register() };
+
+        if !self.saved.contains(&reg) {
+            self.saved.push(reg);
+            self.stack_safe = true;
+        }
+    }
+
+    /// Returns the saved registers with the place where they are saved (below the stack slots)
+    /// and the size of the space which is needed for them
+    fn saved_regs(&self) -> (Vec<(Register, MemoryOperand)>, usize) {
+        let start = self.frame.next_multiple_of(16);
+        let mut off = start;
+
+        let regs = self.saved.iter().map(|reg| {
+            let size = if reg.is_xmm() { 16 } else { 8 };
+            off = (off + size).next_multiple_of(size);
+
+            (*reg, MemoryOperand::new(Register::RBP, Register::None, 1, -(off as i64), 1, false, Register::None))
+        }).collect();
+
+        (regs, off.next_multiple_of(16) - start)
+    }
+
+    /// Restores the saved registers (see `save_reg`), so the frame can be left
+    pub fn restore_saved(&mut self) -> Result<(), Box<dyn Error>> {
+        for (reg, mem) in self.saved_regs().0 {
+            let code = if reg.is_xmm() { Code::Movups_xmm_xmmm128 } else { Code::Mov_r64_rm64 };
+
+            self.asm.add_instruction(Instruction::with2(code, reg, mem)?)?;
         }
 
         Ok(())
     }
 
+    /// Returns the label of the block with the given name
+    /// 
+    /// The label is created if it doesn't exist yet, so it can be used for forward jumps
+    pub fn label(&mut self, name: &str) -> CodeLabel {
+        if let Some(label) = self.labels.get(name) {
+            return *label;
+        }
+
+        let label = self.asm.create_label();
+        self.labels.insert(name.to_string(), label);
+
+        label
+    }
+
+    /// Places the label of the block with the given name at the current position
+    pub fn bind(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
+        if self.bound.contains(name) {
+            return Err(Box::from(IrError::DuplicatedBlock(name.to_string())));
+        }
+
+        let mut label = self.label(name);
+        self.asm.set_label(&mut label)?;
+        self.asm.zero_bytes()?;
+
+        self.labels.insert(name.to_string(), label);
+        self.bound.insert(name.to_string());
+        self.block = name.to_string();
+
+        Ok(())
+    }
+
+    /// Returns the label of the epilogue
+    /// 
+    /// Jumping to it returns from the function
+    pub fn exit(&self) -> CodeLabel {
+        self.exit
+    }
+
+    /// Adds a copy (`dst = src`) which is done when control flows from the block `from` into the current block
+    pub fn add_edge_copy(&mut self, from: &str, dst: Register, src: Register) {
+        let edge = (from.to_string(), self.block.clone());
+
+        self.copies.entry(edge).or_default().push((dst, src));
+    }
+
+    /// Returns if copies need to be done when control flows from the current block into the block `to`
+    pub fn has_edge_copies(&self, to: &str) -> bool {
+        self.copies.contains_key(&(self.block.clone(), to.to_string()))
+    }
+
+    /// Emits the copies for the control flow from the current block into the block `to`
+    pub fn edge_copies(&mut self, to: &str) -> Result<(), Box<dyn Error>> {
+        let copies = self.copies.get(&(self.block.clone(), to.to_string())).cloned();
+
+        if let Some(copies) = copies {
+            self.parallel_copy(copies)?;
+        }
+
+        Ok(())
+    }
+
+    /// Copies all sources into their destinations (`(dst, src)`) like they would be copied at the same time
+    /// 
+    /// Cycles (e.g. swaps) are broken up with the scratch registers
+    pub fn parallel_copy(&mut self, copies: Vec<(Register, Register)>) -> Result<(), Box<dyn Error>> {
+        // ymm registers stay ymm registers, so the whole vector is copied
+        let whole = |reg: Register| if reg.is_ymm() { reg } else { full(reg) };
+
+        let mut pending: Vec<(Register, Register)> = copies.into_iter()
+            .map(|(dst, src)| (whole(dst), whole(src)))
+            .filter(|(dst, src)| full(*dst) != full(*src))
+            .collect();
+
+        while !pending.is_empty() {
+            let free = pending.iter().position(|(dst, _)| {
+                !pending.iter().any(|(_, src)| full(*src) == full(*dst))
+            });
+
+            match free {
+                Some(index) => {
+                    let (dst, src) = pending.remove(index);
+                    self.mov_reg(dst, src)?;
+                },
+                None => {
+                    // every destination is still needed as a source, so one source is moved out of the way
+                    let src = pending[0].1;
+                    let tmp = match src {
+                        src if src.is_gpr() => self.call.tmp_reg(),
+                        src if src.is_ymm() => ymm(self.call.tmpf_reg()),
+                        _ => self.call.tmpf_reg(),
+                    };
+
+                    self.mov_reg(tmp, src)?;
+
+                    for copy in pending.iter_mut() {
+                        if copy.1 == src {
+                            copy.1 = tmp;
+                        }
+                    }
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves the whole content of the register `src` into `dst`
+    fn mov_reg(&mut self, dst: Register, src: Register) -> Result<(), Box<dyn Error>> {
+        if dst.is_ymm() || src.is_ymm() {
+            self.asm.add_instruction(Instruction::with2(Code::VEX_Vmovaps_ymm_ymmm256, ymm(dst), ymm(src))?)?;
+            return Ok(());
+        }
+
+        let code = if dst.is_gpr() { Code::Mov_rm64_r64 } else { Code::Movaps_xmm_xmmm128 };
+
+        self.asm.add_instruction(Instruction::with2(code, full(dst), full(src))?)?;
+
+        Ok(())
+    }
+
+    /// Returns if the function uses ymm registers
+    /// 
+    /// Their upper halfs need to be cleared (with `vzeroupper`) before returning,
+    /// so the caller doesn't get slowed down by mixing AVX and SSE
+    pub fn uses_ymm(&self) -> bool {
+        self.vars.iter().any(|reg| reg.is_ymm()) || self.args.iter().any(|typ| typ.vector() && typ.size() == 32)
+    }
+
     /// Compiles the function (a return will automaticly be added)
     pub fn compile(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
-        self.gen_current().unwrap();
-        let mut ret: Vec<u8> = self.gen.clone().into();
+        for name in self.labels.keys() {
+            if !self.bound.contains(name) {
+                return Err(Box::from(IrError::UnknownBlock(name.to_string())));
+            }
+        }
+
+        self.asm.set_label(&mut self.exit)?;
+        self.asm.zero_bytes()?;
+
+        if self.uses_ymm() && !(self.ret.vector() && self.ret.size() == 32) {
+            self.asm.vzeroupper()?;
+        }
+
+        if self.stack_safe {
+            self.restore_saved()?;
+            self.asm.mov(rsp, rbp)?;
+            self.asm.pop(rbp)?;
+        }
+
+        self.asm.ret()?;
+
+        let result = self.asm.assemble_options(0, BlockEncoderOptions::RETURN_NEW_INSTRUCTION_OFFSETS)?;
+        let body = result.inner.code_buffer;
+        let offsets = result.inner.new_instruction_offsets;
+
+        let mut ret = vec![];
 
         if self.stack_safe {
             let mut asm = CodeAssembler::new(64)?;
 
-            asm.add(rsp, self.call.shadow as i32)?;
-            asm.pop(rbp)?;
-            asm.ret()?;
+            asm.endbr64()?;
+            asm.push(rbp)?;
+            asm.mov(rbp, rsp)?;
+            let (saved, saved_size) = self.saved_regs();
+
+            asm.sub(rsp, (self.call.shadow + self.frame.next_multiple_of(16) + saved_size) as i32)?;
 
-            let gen = asm.assemble(0)?;
+            for (reg, mem) in saved {
+                let code = if reg.is_xmm() { Code::Movups_xmmm128_xmm } else { Code::Mov_rm64_r64 };
 
-            for byte in gen {
-                ret.push( byte );
+                asm.add_instruction(Instruction::with2(code, mem, reg)?)?;
             }
+
+            for (slot, (_, index)) in self.wide_args().iter().enumerate() {
+                let reg = self.call.arg64_reg(*index).unwrap(); // index is from the call conv
+                let adr = 16 * (slot as i64 + 1);
+
+                let lo = MemoryOperand::new(Register::RBP, Register::None, 1, -adr, 1, false, Register::None);
+                let hi = MemoryOperand::new(Register::RBP, Register::None, 1, -adr + 8, 1, false, Register::None);
+
+                if self.call.conv() == CallingConvention::WindowsFastcall {
+                    // the argument is a pointer to the value, so it is copied
+                    let tmp = self.call.tmp_reg();
+
+                    asm.add_instruction(Instruction::with2(Code::Mov_r64_rm64, tmp, MemoryOperand::new(reg, Register::None, 1, 0, 0, false, Register::None))?)?;
+                    asm.add_instruction(Instruction::with2(Code::Mov_rm64_r64, lo, tmp)?)?;
+                    asm.add_instruction(Instruction::with2(Code::Mov_r64_rm64, tmp, MemoryOperand::new(reg, Register::None, 1, 8, 1, false, Register::None))?)?;
+                    asm.add_instruction(Instruction::with2(Code::Mov_rm64_r64, hi, tmp)?)?;
+                } else {
+                    let upper = self.call.arg64_reg(index + 1).unwrap(); // both halfs are in registers
+
+                    asm.add_instruction(Instruction::with2(Code::Mov_rm64_r64, lo, reg)?)?;
+                    asm.add_instruction(Instruction::with2(Code::Mov_rm64_r64, hi, upper)?)?;
+                }
+            }
+
+            ret = asm.assemble(0)?;
         }
 
-        Ok(ret)
-    }
+        let prolog = ret.len();
+        ret.extend_from_slice(&body);
+
+        self.relocs.clear();
+
+        for (to, rel, size, index) in &self.req_relocs {
+            let pos = prolog + match offsets.get(*index) {
+                Some(off) => *off as usize,
+                None => body.len(),
+            };
 
-    fn gen_current(&mut self) -> Result<(), Box<dyn Error>>{
-        let gen = self.asm.assemble(0)?;
+            let link = Link { from: self.name.clone(), to: to.to_string(), at: (pos as isize + rel) as usize, size: *size, replace: false, kind: LinkKind::Relative };
 
-        for byte in gen {
-            self.gen.push_back( byte );
+            self.relocs.push((link, pos));
+        }
+
+        for (table, at, index) in &self.req_tables {
+            let pos = prolog + match offsets.get(*index) {
+                Some(off) => *off as usize,
+                None => body.len(),
+            };
+
+            let link = Link { from: table.to_string(), to: self.name.clone(), at: *at, size: 4, replace: false, kind: LinkKind::TableEntry(pos as isize) };
+
+            self.relocs.push((link, pos));
         }
 
         self.asm.reset();
+        self.req_relocs.clear();
+        self.req_tables.clear();
+        self.labels.clear();
+        self.copies.clear();
+        self.bound.clear();
+        self.exit = self.asm.create_label();
 
-        Ok(())
+        Ok(ret)
     }
 
+    /// Adds a relocation to the symbol `to` at the end of the last added instruction (+ `rel`)
+    /// 
+    /// The position is resolved when the function is compiled
     pub fn reloc_at_current_pos(&mut self, to: &str, rel: isize, size: usize) -> Result<(), Box<dyn Error>> {
-        self.gen_current()?;
+        let index = self.asm.instructions().len();
 
-        let pos = self.gen.len();
+        self.req_relocs.push((to.to_string(), rel, size, index));
 
-        let link = Link { from: self.name.clone(), to: to.to_string(), at: (pos as isize + rel) as usize, size: size, replace: false };
+        Ok(())
+    }
 
-        self.relocs.push((link, pos));
+    /// Makes the entry at the offset `at` of the data `table` point to the current position
+    /// (the entry is the offset relative to the start of the table)
+    /// 
+    /// The position is resolved when the function is compiled
+    pub fn table_entry_at_current_pos(&mut self, table: &str, at: usize) {
+        let index = self.asm.instructions().len();
 
-        Ok(())
+        self.req_tables.push((table.to_string(), at, index));
     }
 
     /// Returns the relocs of the function
@@ -131,15 +469,48 @@ impl AsmFunction {
         ret
     }
 
+    /// Returns the external functions which are called by the compiled code itself (like the libc functions of intrinsics)
+    pub fn externs(&self) -> Vec<String> {
+        self.externs.clone()
+    }
+
+    /// Adds an external function which is called by the compiled code itself
+    pub fn require_extern(&mut self, name: &str) {
+        if !self.externs.iter().any(|known| known == name) {
+            self.externs.push(name.to_string());
+        }
+    }
+
+    /// Places the constant in the data of the function and returns the name of it
+    pub fn constant(&mut self, bytes: Vec<u8>) -> String {
+        let name = self.req_name();
+        self.data.insert(name.clone(), bytes);
+
+        name
+    }
+
+    /// Removes everything which was generated by compiling the ir (the constants, the requested names,
+    /// the required external functions and the saved registers), so the ir can be compiled again
+    /// 
+    /// The stack slots, the variables and the arguments are kept, because they are used by the ir
+    pub fn reset_ir(&mut self) {
+        self.data.clear();
+        self.req_names = 0;
+        self.externs.clear();
+        self.saved.clear();
+        self.relocs.clear();
+    }
+
     /// Requests a new name for a label
     pub fn req_name(&mut self) -> String {
-        let req = format!("{}", self.req_names);
+        // prefixed with the function name, so the names of different functions don't collide
+        let req = format!("{}.{}", self.name, self.req_names);
         self.req_names += 1;
 
         req
     }
 
-    /// Returns the argument as a variable (or None if the index isn't found)
+    /// Returns the argument as a variable (or None if the index isn't found or the argument is passed on the stack)
     /// 
     /// **IMPORTANT:** maybe argument registers get overwritten so it points to an invalid value
     pub fn arg(&self, nr: usize) -> Option<VarGen> {
@@ -149,35 +520,41 @@ impl AsmFunction {
             return None;
         }
 
-        let mut index = 0;
-        let mut reg_args = 0;
         let mut adr = 0;
 
-        for arg in &self.args {
-
-            if index == nr { break; }
-
-            if arg.reg() {
-                reg_args += 1;
-            }
-
+        for arg in &self.args[..nr] {
             if arg.stack() {
                 adr += arg.size();
             }
-
-            index += 1;
         }
 
+        let reg_args = self.call.reg_args(&self.args)[nr];
+
         let typ = get.unwrap();
 
+        if typ.wide() {
+            // stored in a stack slot by the prologue
+            let slot = self.wide_args().iter().position(|(arg, _)| *arg == nr)?; // passed on the stack
+
+            return Some(VarGen::new_stack(*typ, (slot + 1) * 16));
+        }
+
         if typ.reg() {
+            let reg_args = reg_args?; // passed on the stack
+
             let reg = {
                 match typ {
                     Type::u64 | Type::i64 => self.call.arg64_reg(reg_args),
+                    Type::usize | Type::isize => self.call.arg64_reg(reg_args),
+                    Type::ptr | Type::typed_ptr(_) => self.call.arg64_reg(reg_args),
                     Type::u32 | Type::i32 => self.call.arg32_reg(reg_args),
                     Type::u16 | Type::i16 => self.call.arg16_reg(reg_args),
-                    Type::u8  | Type::i8  => self.call.arg16_reg(reg_args),
+                    Type::u8  | Type::i8  => self.call.arg8_reg(reg_args),
+                    Type::bool => self.call.arg8_reg(reg_args),
                     Type::f64 | Type::f32 => self.call.argf_reg(reg_args),
+                    Type::vector(..) if typ.size() == 32 => self.call.argf_reg(reg_args).map(ymm),
+                    Type::vector(..) => self.call.argf_reg(reg_args),
+                    _ => None, // never stored in registers
                 }
             };
 
@@ -188,4 +565,68 @@ impl AsmFunction {
             None // invalid type or dummy type
         }
     }
+
+    /// Returns a new variable which is stored in a free register (or None if all registers are used)
+    /// 
+    /// The register isn't used by any argument or other variable.
+    /// 256 bit vectors are stored in ymm registers, so they need AVX (else None is returned).
+    /// Types which don't fit into a register (void, 128 bit integers, structs and arrays) return None, they need a stack slot (see `alloca`)
+    pub fn var(&mut self, typ: Type) -> Option<VarGen> {
+        let used = self.live_regs();
+        let simd = typ.float() || typ.vector();
+
+        if !typ.reg() || (typ.vector() && typ.size() == 32 && !self.avx) {
+            return None;
+        }
+
+        let mut nr = 0;
+
+        loop {
+            let reg = {
+                if simd {
+                    self.call.varf_reg(nr)?
+                } else {
+                    self.call.var_reg(nr)?
+                }
+            };
+
+            nr += 1;
+
+            if used.iter().any(|used| full(*used) == reg) {
+                continue;
+            }
+
+            let reg = {
+                if typ.vector() && typ.size() == 32 {
+                    ymm(reg)
+                } else if simd {
+                    reg
+                } else {
+                    sized(reg, typ.size())
+                }
+            };
+
+            // ymm registers are kept, so their whole content is saved around calls
+            self.vars.push(if reg.is_ymm() { reg } else { full(reg) });
+
+            return Some(VarGen::new_reg(typ, reg));
+        }
+    }
+
+    /// Returns the (full) registers of all arguments and variables
+    /// 
+    /// Registers of 256 bit vectors are returned as ymm registers
+    pub fn live_regs(&self) -> Vec<Register> {
+        let mut regs = self.vars.clone();
+
+        for nr in 0..self.args.len() {
+            if let Some(arg) = self.arg(nr) {
+                if arg.in_reg {
+                    regs.push(if arg.reg.is_ymm() { arg.reg } else { full(arg.reg) });
+                }
+            }
+        }
+
+        regs
+    }
 }
\ No newline at end of file
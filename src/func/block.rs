@@ -0,0 +1,19 @@
+use crate::ir::compile::Compile;
+
+/// A named basic block
+/// 
+/// The ir of a block should end with a terminator (like `Br`, `CondBr` or `Return`)
+pub struct Block {
+    pub name: String,
+    pub ir: Vec<Box<dyn Compile>>,
+}
+
+impl Block {
+    /// Creates a new empty block
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ir: vec![],
+        }
+    }
+}
@@ -1,14 +1,18 @@
-use std::error::Error;
+use std::{error::Error, fmt};
 
-use crate::{contxt::contxt::Context, ir::{compile::Compile, r#type::Type}, naming::NamingGenerator};
+use crate::{contxt::contxt::Context, ir::{compile::{Compile, IntoIr}, error::IrError, r#type::Type}, naming::NamingGenerator};
 
-use super::AsmFunction;
+use super::{AsmFunction, Block};
 
 /// Stores function data
+/// 
+/// `ir` is the entry block, more blocks can be added via `add_block`
 pub struct Function {
     name: String,
     asm: AsmFunction,
     pub ir: Vec<Box<dyn Compile>>,
+    blocks: Vec<Block>,
+    current: Option<usize>,
 
     args: Vec<Type>,
     ret: Type,
@@ -19,12 +23,19 @@ pub struct Function {
 impl Function {
     /// Creates a function
     pub fn new(name: &str, contxt: &Context, args: Vec<Type>, ret: Type) -> Self {
+        // the arguments are known from the start, so they can be printed
+        let mut asm = AsmFunction::new(name, contxt);
+        asm.args = args.clone();
+        asm.ret = ret;
+
         Self {
             name: name.to_string(),
-            asm: AsmFunction::new(name, &contxt),
+            asm,
             ir: vec![],
-            args: args,
-            ret: ret,
+            blocks: vec![],
+            current: None,
+            args,
+            ret,
             export: false,
         }
     }
@@ -35,17 +46,100 @@ impl Function {
     }
 
     /// Returns the function as a compilable version
+    /// 
+    /// The ir isn't compiled here (see `compile_ir`), so instructions which are added
+    /// directly to the assembler come before the ir. Use `InlineAsm` to mix them with the ir.
+    /// 
+    /// The assembler is emptied by `AsmFunction::compile`, so these instructions are only part of
+    /// the next compilation (the `Context` compiles every function again for every `get_jit_function` and `write`)
     pub fn asm_func(&mut self) -> Result<&mut AsmFunction, Box<dyn Error>> {
         self.asm.args = self.args.clone();
         self.asm.ret = self.ret;        
+        self.asm.reserve_arg_slots();
+
+        Ok( &mut self.asm )
+    }
+
+    /// Compiles the ir of all blocks and returns the compilable version of the function
+    /// 
+    /// Can be called multiple times: the state of the last compilation (like constants) is removed first
+    pub fn compile_ir(&mut self) -> Result<&mut AsmFunction, Box<dyn Error>> {
+        self.asm_func()?;
+        self.asm.reset_ir();
+
+        self.asm.block = "entry".into();
+
+        for ir in &self.ir {
+            ir.prepare(&mut self.asm)?;
+        }
+
+        for block in &self.blocks {
+            self.asm.block = block.name.clone();
+
+            for ir in &block.ir {
+                ir.prepare(&mut self.asm)?;
+            }
+        }
+
+        self.asm.block = "entry".into();
+        self.asm.ir = 0;
 
         for ir in &self.ir {
             ir.compile(&mut self.asm)?;
+            self.asm.ir += 1;
+        }
+
+        for block in &self.blocks {
+            self.asm.bind(&block.name)?;
+
+            for ir in &block.ir {
+                ir.compile(&mut self.asm)?;
+                self.asm.ir += 1;
+            }
         }
 
         Ok( &mut self.asm )
     }
 
+    /// Adds a new block to the end of the function
+    /// 
+    /// The name `entry` is reserved for the first block (`ir`)
+    pub fn add_block(&mut self, name: &str) -> &mut Block {
+        self.blocks.push(Block::new(name));
+
+        self.blocks.last_mut().unwrap()
+    }
+
+    /// Returns the blocks of the function (without the entry block)
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    /// Makes `push` add the ir to the end of the block with the given name
+    pub fn position_at_end(&mut self, name: &str) -> Result<(), IrError> {
+        if name == "entry" {
+            self.current = None;
+            return Ok(());
+        }
+
+        match self.blocks.iter().position(|block| block.name == name) {
+            Some(index) => self.current = Some(index),
+            None => return Err(IrError::UnknownBlock(name.to_string())),
+        }
+
+        Ok(())
+    }
+
+    /// Adds the ir to the end of the current block
+    pub fn push(&mut self, ir: impl IntoIr) {
+        let ir = ir.into_ir();
+
+        match self.current {
+            Some(index) => self.blocks[index].ir.push(ir),
+            None => self.ir.push(ir),
+        }
+    }
+
     /// Makes the function public
     pub fn public(&mut self)  {
         self.export = true 
@@ -82,4 +176,31 @@ impl Function {
 
         self.name = new_name;
     }
-}
\ No newline at end of file
+}
+
+impl fmt::Display for Function {
+    /// Writes the ir of the function (like a function in llvm's `.ll` files)
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let args: Vec<String> = (0..self.args.len())
+            .filter_map(|nr| self.asm.arg(nr))
+            .map(|arg| format!("{} {}", arg.typ, arg))
+            .collect();
+
+        writeln!(f, "define {} @{}({}) {{", self.ret, self.name, args.join(", "))?;
+
+        let blocks = [("entry", &self.ir)].into_iter()
+            .chain(self.blocks.iter().map(|block| (block.name.as_str(), &block.ir)));
+
+        for (name, ir) in blocks {
+            writeln!(f, "{}:", name)?;
+
+            for ir in ir {
+                for line in ir.display().lines() {
+                    writeln!(f, "  {}", line)?;
+                }
+            }
+        }
+
+        write!(f, "}}")
+    }
+}
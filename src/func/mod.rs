@@ -27,7 +27,9 @@
 //! ```
 
 pub mod asmfunc;
+pub mod block;
 pub mod func;
 
 pub use func::Function;
-pub use asmfunc::AsmFunction;
\ No newline at end of file
+pub use asmfunc::AsmFunction;
+pub use block::Block;
\ No newline at end of file
@@ -1,13 +1,20 @@
-use iced_x86::{Code, Instruction, MemoryOperand, Register};
+use iced_x86::{code_asm::CodeLabel, Code, Instruction, MemoryOperand, Register};
 
-use crate::func::AsmFunction;
+use target_lexicon::CallingConvention;
 
-use self::{ir::*, r#type::Type, var::VarGen};
+use crate::{contxt::contxt::TRAP_HANDLER, func::AsmFunction, target::reg::{full, sized, ymm}};
+
+use self::{intrinsics::Intrinsic, ir::*, r#type::Type, var::VarGen};
 
 use super::{*};
 
 
 pub trait Compile {
+    /// Gets called for every ir of the function before any ir is compiled
+    fn prepare(&self, _asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+
     fn compile(&self, _asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
         Ok(())
     }
@@ -15,50 +22,122 @@ pub trait Compile {
     fn out_reg(&self) -> Option<Register> {
         None
     }
+
+    /// Returns the ir as text (like in llvm's `.ll` files), used when printing functions
+    fn display(&self) -> String {
+        "<unknown ir>".into()
+    }
+}
+
+/// Converts the boxed ir into a `Box<dyn Compile>` (used by `Function::push`)
+/// 
+/// Taking any boxed ir lets literals without a suffix fall back to `i32`/`f64`, like in `func.push( x + 5 )`
+pub trait IntoIr {
+    fn into_ir(self) -> Box<dyn Compile>;
+}
+
+impl<T: Compile + 'static> IntoIr for Box<T> {
+    fn into_ir(self) -> Box<dyn Compile> {
+        self
+    }
+}
+
+impl IntoIr for Box<dyn Compile> {
+    fn into_ir(self) -> Box<dyn Compile> {
+        self
+    }
+}
+
+/// Returns `target = op type target, src` (the math ir stores the result into the first operand)
+fn display_math(ir: &str, target: &VarGen, src: String) -> String {
+    format!("{0} = {1} {2} {0}, {3}", target, ir.to_lowercase(), target.typ, src)
+}
+
+/// Returns `target = op type value, target` for the math ir with the constant on the left side
+fn display_math_rev(ir: &str, value: String, target: &VarGen) -> String {
+    format!("{0} = {1} {2} {3}, {0}", target, ir.to_lowercase(), target.typ, value)
+}
+
+fn display_cast(ir: &str, out: &VarGen, value: &VarGen) -> String {
+    format!("{} = {} {} {} to {}", out, ir, value.typ, value, out.typ)
+}
+
+fn display_args(args: &[VarGen]) -> String {
+    let args: Vec<String> = args.iter().map(|arg| format!("{} {}", arg.typ, arg)).collect();
+
+    args.join(", ")
+}
+
+fn display_ordering(ordering: AtomicOrdering) -> &'static str {
+    match ordering {
+        AtomicOrdering::Relaxed => "monotonic",
+        AtomicOrdering::Acquire => "acquire",
+        AtomicOrdering::Release => "release",
+        AtomicOrdering::AcqRel => "acq_rel",
+        AtomicOrdering::SeqCst => "seq_cst",
+    }
+}
+
+/// Returns the ir and the return of its output
+fn display_expr_return(ir: &impl Compile) -> String {
+    match ir.out_reg() {
+        Some(reg) => format!("{}\nret %{}", ir.display(), format!("{:?}", reg).to_lowercase()),
+        None => format!("{}\nret void", ir.display()),
+    }
+}
+
+/// Returns the error if one of the values isn't in a register (the node only supports registers)
+fn check_regs(ir: &str, values: &[&VarGen]) -> Result<(), Box<dyn std::error::Error>> {
+    match values.iter().find(|value| !value.in_reg) {
+        Some(value) => Err(Box::from(error::IrError::UnsupportedType(format!("{} (in {})", vector_name(&value.typ), ir)))),
+        None => Ok(()),
+    }
 }
+
 macro_rules! MathStructVarGenAdd {
     ($name:tt, $_64:expr, $_32:expr, $_16:expr, $_8:expr, $_f64:expr, $_f32:expr) => {
         impl Compile for $name<VarGen, VarGen> {
             fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {     
                 let target = &self.inner1;   
                 let src = &self.inner2;   
+
+                check_regs(stringify!($name), &[target, src])?;
+
+                if target.typ.vector() {
+                    return compile_vector_math(asm, stringify!($name), target, src);
+                }
         
-                if target.in_reg && src.in_reg {
-                    let target_reg = target.reg;
+                let target_reg = target.reg;
         
-                    if target_reg.is_gpr64() {
-                        asm.asm.add_instruction(
-                            Instruction::with2($_64, target.reg, src.reg)?
-                        )?;
-                    } else if target_reg.is_gpr32() {
-                        asm.asm.add_instruction(
-                            Instruction::with2($_32, target.reg, src.reg)?
-                        )?;
-                    } else if target_reg.is_gpr16() {
-                        asm.asm.add_instruction(
-                            Instruction::with2($_16, target.reg, src.reg)?
-                        )?;
-                    } else if target_reg.is_gpr8() {
-                        asm.asm.add_instruction(
-                            Instruction::with2($_8, target.reg, src.reg)?
-                        )?;
-                    } else if target_reg.is_xmm() {
-                        let code = {
-                            if target.typ == Type::f64 {
-                                $_f64
-                            } else {
-                                $_f32
-                            }
-                        };
+                if target_reg.is_gpr64() {
+                    asm.asm.add_instruction(
+                        Instruction::with2($_64, target.reg, src.reg)?
+                    )?;
+                } else if target_reg.is_gpr32() {
+                    asm.asm.add_instruction(
+                        Instruction::with2($_32, target.reg, src.reg)?
+                    )?;
+                } else if target_reg.is_gpr16() {
+                    asm.asm.add_instruction(
+                        Instruction::with2($_16, target.reg, src.reg)?
+                    )?;
+                } else if target_reg.is_gpr8() {
+                    asm.asm.add_instruction(
+                        Instruction::with2($_8, target.reg, src.reg)?
+                    )?;
+                } else if target_reg.is_xmm() {
+                    let code = {
+                        if target.typ == Type::f64 {
+                            $_f64
+                        } else {
+                            $_f32
+                        }
+                    };
         
-                        asm.asm.add_instruction(
-                            Instruction::with2(code, target.reg, src.reg)?
-                        )?;
-                    } 
-                } else if target.on_stack && src.on_stack {
-                    asm.make_stack_safe()?;
-                    println!("stack");
-                }
+                    asm.asm.add_instruction(
+                        Instruction::with2(code, target.reg, src.reg)?
+                    )?;
+                } 
                 
                 Ok(())
             }
@@ -66,6 +145,10 @@ macro_rules! MathStructVarGenAdd {
             fn out_reg(&self) -> Option<Register> {
                 Some(self.inner1.reg)
             }
+
+            fn display(&self) -> String {
+                display_math(stringify!($name), &self.inner1, self.inner2.to_string())
+            }
         }
     }
 }
@@ -99,88 +182,3721 @@ MathStructVarGenAdd!(Mul,
     Code::Mulss_xmm_xmmm32
 );
 
-impl Compile for Return<i32> {
+MathStructVarGenAdd!(And,
+    Code::And_rm64_r64, 
+    Code::And_rm32_r32, 
+    Code::And_rm16_r16, 
+    Code::And_rm8_r8, 
+    Code::Andpd_xmm_xmmm128, 
+    Code::Andps_xmm_xmmm128
+);
+
+MathStructVarGenAdd!(Or,
+    Code::Or_rm64_r64, 
+    Code::Or_rm32_r32, 
+    Code::Or_rm16_r16, 
+    Code::Or_rm8_r8, 
+    Code::Orpd_xmm_xmmm128, 
+    Code::Orps_xmm_xmmm128
+);
+
+MathStructVarGenAdd!(Xor,
+    Code::Xor_rm64_r64, 
+    Code::Xor_rm32_r32, 
+    Code::Xor_rm16_r16, 
+    Code::Xor_rm8_r8, 
+    Code::Xorpd_xmm_xmmm128, 
+    Code::Xorps_xmm_xmmm128
+);
+
+/// Returns the memory operand `[rip]` (the displacement gets patched by a relocation)
+fn rip_mem() -> MemoryOperand {
+    MemoryOperand::new(Register::RIP, Register::None, 1, 0, 1, false, Register::None)
+}
+
+/// Emits `code reg, [rip + constant]` where the constant is placed in the data of the function
+fn compile_const_op(asm: &mut AsmFunction, code: Code, reg: Register, bytes: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
+    let name = asm.constant(bytes);
+
+    asm.asm.add_instruction(Instruction::with2(code, reg, rip_mem())?)?;
+    asm.reloc_at_current_pos(&name, -4, 4)?;
+
+    Ok(())
+}
+
+/// Loads the float constant (in the precision of `typ`) into the register
+fn compile_fconst(asm: &mut AsmFunction, reg: Register, typ: Type, value: f64) -> Result<(), Box<dyn std::error::Error>> {
+    if typ == Type::f32 {
+        compile_const_op(asm, Code::Movss_xmm_xmmm32, reg, (value as f32).to_le_bytes().to_vec())
+    } else {
+        compile_const_op(asm, Code::Movsd_xmm_xmmm64, reg, value.to_le_bytes().to_vec())
+    }
+}
+
+/// Compiles `target = target (op) imm` for integers
+/// 
+/// `codes` are the forms with an immediate (64, 32, 16 and 8 bit) and the 64 bit form with a memory operand,
+/// which is used with a constant if the immediate doesn't fit into 32 bits.
+/// If `imul` is set the forms take three operands (`imul r, r/m, imm`)
+fn compile_int_imm(asm: &mut AsmFunction, target: &VarGen, imm: i64, codes: [Code; 5], imul: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let reg = target.reg;
+
+    let (code, reg, imm) = {
+        if reg.is_gpr64() {
+            if i32::try_from(imm).is_err() {
+                return compile_const_op(asm, codes[4], reg, imm.to_le_bytes().to_vec());
+            }
+
+            (codes[0], reg, imm as i32)
+        } else if reg.is_gpr32() {
+            (codes[1], reg, imm as i32)
+        } else if reg.is_gpr16() {
+            (codes[2], reg, imm as i16 as i32)
+        } else if imul {
+            // there is no 8 bit form, but the lower 8 bits of the 32 bit product are the same
+            (codes[1], sized(reg, 4), imm as i8 as i32)
+        } else {
+            (codes[3], reg, imm as i8 as i32)
+        }
+    };
+
+    if imul {
+        asm.asm.add_instruction(Instruction::with3(code, reg, reg, imm)?)?;
+    } else {
+        asm.asm.add_instruction(Instruction::with2(code, reg, imm)?)?;
+    }
+
+    Ok(())
+}
+
+/// Compiles `target = target (op) constant` for floats
+fn compile_float_const(asm: &mut AsmFunction, ir: &str, target: &VarGen, value: f64, _f64: Code, _f32: Code) -> Result<(), Box<dyn std::error::Error>> {
+    if !target.typ.float() {
+        return Err(Box::from(error::IrError::InvalidCast(ir.to_string(), Type::f64.name().to_string(), target.typ.name().to_string())));
+    }
+
+    let tmp = asm.call.tmpf_reg();
+    compile_fconst(asm, tmp, target.typ, value)?;
+
+    let code = if target.typ == Type::f64 { _f64 } else { _f32 };
+    asm.asm.add_instruction(Instruction::with2(code, target.reg, tmp)?)?;
+
+    Ok(())
+}
+
+macro_rules! MathStructImm {
+    ($name:tt, $imul:expr, [$_64:expr, $_32:expr, $_16:expr, $_8:expr, $_mem64:expr], $_f64:expr, $_f32:expr) => {
+        impl Compile for $name<VarGen, i64> {
+            fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+                if self.inner1.typ.float() {
+                    return compile_float_const(asm, stringify!($name), &self.inner1, self.inner2 as f64, $_f64, $_f32);
+                }
+
+                compile_int_imm(asm, &self.inner1, self.inner2, [$_64, $_32, $_16, $_8, $_mem64], $imul)
+            }
+
+            fn out_reg(&self) -> Option<Register> {
+                Some(self.inner1.reg)
+            }
+
+            fn display(&self) -> String {
+                display_math(stringify!($name), &self.inner1, self.inner2.to_string())
+            }
+        }
+
+        impl Compile for $name<VarGen, i32> {
+            fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+                $name::new(self.inner1, self.inner2 as i64).compile(asm)
+            }
+
+            fn out_reg(&self) -> Option<Register> {
+                Some(self.inner1.reg)
+            }
+
+            fn display(&self) -> String {
+                display_math(stringify!($name), &self.inner1, self.inner2.to_string())
+            }
+        }
+
+        impl Compile for $name<VarGen, f64> {
+            fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+                compile_float_const(asm, stringify!($name), &self.inner1, self.inner2, $_f64, $_f32)
+            }
+
+            fn out_reg(&self) -> Option<Register> {
+                Some(self.inner1.reg)
+            }
+
+            fn display(&self) -> String {
+                display_math(stringify!($name), &self.inner1, format!("{:?}", self.inner2))
+            }
+        }
+
+        impl Compile for $name<VarGen, f32> {
+            fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+                compile_float_const(asm, stringify!($name), &self.inner1, self.inner2 as f64, $_f64, $_f32)
+            }
+
+            fn out_reg(&self) -> Option<Register> {
+                Some(self.inner1.reg)
+            }
+
+            fn display(&self) -> String {
+                display_math(stringify!($name), &self.inner1, format!("{:?}", self.inner2))
+            }
+        }
+    };
+}
+
+/// Implements the constant on the left side for commutative operations (the result is stored in the variable)
+macro_rules! MathStructImmCommutative {
+    ($name:tt, $($imm:ty),*) => {
+        $(
+            impl Compile for $name<$imm, VarGen> {
+                fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+                    $name::new(self.inner2, self.inner1).compile(asm)
+                }
+
+                fn out_reg(&self) -> Option<Register> {
+                    Some(self.inner2.reg)
+                }
+
+                fn display(&self) -> String {
+                    display_math(stringify!($name), &self.inner2, format!("{:?}", self.inner1))
+                }
+            }
+        )*
+    };
+}
+
+MathStructImm!(Add, false,
+    [Code::Add_rm64_imm32, Code::Add_rm32_imm32, Code::Add_rm16_imm16, Code::Add_rm8_imm8, Code::Add_r64_rm64],
+    Code::Addsd_xmm_xmmm64,
+    Code::Addss_xmm_xmmm32
+);
+
+MathStructImm!(Sub, false,
+    [Code::Sub_rm64_imm32, Code::Sub_rm32_imm32, Code::Sub_rm16_imm16, Code::Sub_rm8_imm8, Code::Sub_r64_rm64],
+    Code::Subsd_xmm_xmmm64,
+    Code::Subss_xmm_xmmm32
+);
+
+MathStructImm!(Mul, true,
+    [Code::Imul_r64_rm64_imm32, Code::Imul_r32_rm32_imm32, Code::Imul_r16_rm16_imm16, Code::Imul_r32_rm32_imm32, Code::Imul_r64_rm64],
+    Code::Mulsd_xmm_xmmm64,
+    Code::Mulss_xmm_xmmm32
+);
+
+MathStructImm!(And, false,
+    [Code::And_rm64_imm32, Code::And_rm32_imm32, Code::And_rm16_imm16, Code::And_rm8_imm8, Code::And_r64_rm64],
+    Code::Andpd_xmm_xmmm128,
+    Code::Andps_xmm_xmmm128
+);
+
+MathStructImm!(Or, false,
+    [Code::Or_rm64_imm32, Code::Or_rm32_imm32, Code::Or_rm16_imm16, Code::Or_rm8_imm8, Code::Or_r64_rm64],
+    Code::Orpd_xmm_xmmm128,
+    Code::Orps_xmm_xmmm128
+);
+
+MathStructImm!(Xor, false,
+    [Code::Xor_rm64_imm32, Code::Xor_rm32_imm32, Code::Xor_rm16_imm16, Code::Xor_rm8_imm8, Code::Xor_r64_rm64],
+    Code::Xorpd_xmm_xmmm128,
+    Code::Xorps_xmm_xmmm128
+);
+
+MathStructImmCommutative!(Add, i64, i32, f64, f32);
+MathStructImmCommutative!(Mul, i64, i32, f64, f32);
+MathStructImmCommutative!(And, i64, i32);
+MathStructImmCommutative!(Or, i64, i32);
+MathStructImmCommutative!(Xor, i64, i32);
+
+/// Compiles `target = value - target` and `target = value / target` for floats
+fn compile_float_const_rev(asm: &mut AsmFunction, ir: &str, value: f64, target: &VarGen, _f64: Code, _f32: Code) -> Result<(), Box<dyn std::error::Error>> {
+    if !target.typ.float() {
+        return Err(Box::from(error::IrError::InvalidCast(ir.to_string(), Type::f64.name().to_string(), target.typ.name().to_string())));
+    }
+
+    let tmp = asm.call.tmpf_reg();
+    compile_fconst(asm, tmp, target.typ, value)?;
+
+    let code = if target.typ == Type::f64 { _f64 } else { _f32 };
+    asm.asm.add_instruction(Instruction::with2(code, tmp, target.reg)?)?;
+    asm.asm.add_instruction(Instruction::with2(Code::Movaps_xmm_xmmm128, target.reg, tmp)?)?;
+
+    Ok(())
+}
+
+impl Compile for Sub<i64, VarGen> {
     fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
-        asm.asm.mov(asm.call.ret32(), self.inner1)?;
-        
-        Ok(())
+        if self.inner2.typ.float() {
+            return compile_float_const_rev(asm, "Sub", self.inner1 as f64, &self.inner2, Code::Subsd_xmm_xmmm64, Code::Subss_xmm_xmmm32);
+        }
+
+        // value - target = -target + value
+        Neg::new(self.inner2).compile(asm)?;
+        Add::new(self.inner2, self.inner1).compile(asm)
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.inner2.reg)
+    }
+
+    fn display(&self) -> String {
+        display_math_rev("Sub", self.inner1.to_string(), &self.inner2)
     }
 }
 
-impl Compile for Return<i64> {
+impl Compile for Sub<i32, VarGen> {
     fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
-        asm.asm.mov(asm.call.ret64(), self.inner1)?;
+        Sub::new(self.inner1 as i64, self.inner2).compile(asm)
+    }
 
-        Ok(())
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.inner2.reg)
+    }
+
+    fn display(&self) -> String {
+        display_math_rev("Sub", self.inner1.to_string(), &self.inner2)
     }
 }
 
-impl Compile for Return<f32> {
+macro_rules! FloatConstRev {
+    ($name:tt, $imm:ty, $_f64:expr, $_f32:expr) => {
+        impl Compile for $name<$imm, VarGen> {
+            fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+                compile_float_const_rev(asm, stringify!($name), self.inner1 as f64, &self.inner2, $_f64, $_f32)
+            }
+
+            fn out_reg(&self) -> Option<Register> {
+                Some(self.inner2.reg)
+            }
+
+            fn display(&self) -> String {
+                display_math_rev(stringify!($name), format!("{:?}", self.inner1), &self.inner2)
+            }
+        }
+    };
+}
+
+FloatConstRev!(Sub, f64, Code::Subsd_xmm_xmmm64, Code::Subss_xmm_xmmm32);
+FloatConstRev!(Sub, f32, Code::Subsd_xmm_xmmm64, Code::Subss_xmm_xmmm32);
+FloatConstRev!(Div, f64, Code::Divsd_xmm_xmmm64, Code::Divss_xmm_xmmm32);
+FloatConstRev!(Div, f32, Code::Divsd_xmm_xmmm64, Code::Divss_xmm_xmmm32);
+
+impl Compile for Div<VarGen, f64> {
     fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
-        let mem = MemoryOperand::new(Register::RIP, Register::None, 1, 8, 1, false, Register::None);
-        let instr = Instruction::with2(Code::Movd_xmm_rm32, asm.call.retf().into(), mem)?;
+        compile_float_const(asm, "Div", &self.inner1, self.inner2, Code::Divsd_xmm_xmmm64, Code::Divss_xmm_xmmm32)
+    }
 
-        asm.asm.add_instruction(instr)?;
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.inner1.reg)
+    }
 
-        let req = asm.req_name();
-        asm.reloc_at_current_pos(&req, 4, 4)?;
-        asm.data.insert(req, self.inner1.to_be_bytes().into());
-        Ok(())
+    fn display(&self) -> String {
+        display_math("Div", &self.inner1, format!("{:?}", self.inner2))
     }
 }
 
-impl Compile for Return<f64> {
+impl Compile for Div<VarGen, f32> {
     fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
-        let mem = MemoryOperand::new(Register::RIP, Register::None, 1, 8, 1, false, Register::None);
-        let instr = Instruction::with2(Code::Movd_xmm_rm32, asm.call.retf().into(), mem)?;
+        compile_float_const(asm, "Div", &self.inner1, self.inner2 as f64, Code::Divsd_xmm_xmmm64, Code::Divss_xmm_xmmm32)
+    }
 
-        asm.asm.add_instruction(instr)?;
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.inner1.reg)
+    }
 
-        let req = asm.req_name();
-        asm.reloc_at_current_pos(&req, 5, 4)?;
-        asm.data.insert(req, self.inner1.to_be_bytes().into());
-        
-        Ok(())
+    fn display(&self) -> String {
+        display_math("Div", &self.inner1, format!("{:?}", self.inner2))
     }
 }
 
-macro_rules! ExprReturn {
-    ($name:tt) => {
-        impl<T, U> Compile for Return<$name<T, U>> where $name<T, U>: Compile {
+macro_rules! ShiftStructVarGen {
+    ($name:tt, $_64:expr, $_32:expr, $_16:expr, $_8:expr) => {
+        impl Compile for $name<VarGen, VarGen> {
             fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
-                self.inner1.compile(asm)?;
-                let reg = self.inner1.out_reg().unwrap(); // Implemented for add so it won't panic
-        
-                if reg.is_gpr64() {
-                    if reg != asm.call.ret64_reg() {
-                        asm.asm.add_instruction(Instruction::with2(Code::Mov_rm64_r64, asm.call.ret64_reg(), reg)?)?;
-                    }
-                } else if reg.is_gpr32() {
-                    if reg.is_gpr32() {
-                        if reg != asm.call.ret64_reg() {
-                            asm.asm.add_instruction(Instruction::with2(Code::Mov_rm32_r32, asm.call.ret32_reg(), reg)?)?;
-                        }
-                    }
-                } else if reg.is_gpr16() {
-                    if reg != asm.call.ret16_reg() {
-                        asm.asm.add_instruction(Instruction::with2(Code::Mov_rm16_r16, asm.call.ret16_reg(), reg)?)?;
-                    }
-                } else if reg.is_gpr8() {
-                    if reg != asm.call.ret8_reg() {
-                        asm.asm.add_instruction(Instruction::with2(Code::Mov_rm64_r64, asm.call.ret8_reg(), reg)?)?;
-                    }
-                }
-        
+                let target = &self.inner1;
+                let src = &self.inner2;
+
+                check_regs(stringify!($name), &[target, src])?;
+
+                let code = match target.typ.size() {
+                    8 => $_64,
+                    4 => $_32,
+                    2 => $_16,
+                    _ => $_8,
+                };
+
+                compile_shift(asm, code, target, src)?;
+
                 Ok(())
             }
-            
+
             fn out_reg(&self) -> Option<Register> {
-                None
+                Some(self.inner1.reg)
+            }
+
+            fn display(&self) -> String {
+                display_math(stringify!($name), &self.inner1, self.inner2.to_string())
             }
         }
-    };
+    }
 }
 
-ExprReturn!(Add);
-ExprReturn!(Sub);
-ExprReturn!(Mul);
\ No newline at end of file
+ShiftStructVarGen!(Shl,
+    Code::Shl_rm64_CL, 
+    Code::Shl_rm32_CL, 
+    Code::Shl_rm16_CL, 
+    Code::Shl_rm8_CL
+);
+
+ShiftStructVarGen!(LShr,
+    Code::Shr_rm64_CL, 
+    Code::Shr_rm32_CL, 
+    Code::Shr_rm16_CL, 
+    Code::Shr_rm8_CL
+);
+
+ShiftStructVarGen!(AShr,
+    Code::Sar_rm64_CL, 
+    Code::Sar_rm32_CL, 
+    Code::Sar_rm16_CL, 
+    Code::Sar_rm8_CL
+);
+
+/// Emits a shift of `target` by `src`
+/// 
+/// The shift count gets moved into cl (rcx is saved if it is used)
+fn compile_shift(asm: &mut AsmFunction, code: Code, target: &VarGen, src: &VarGen) -> Result<(), Box<dyn std::error::Error>> {
+    if full(src.reg) == Register::RCX {
+        asm.asm.add_instruction(Instruction::with2(code, target.reg, Register::CL)?)?;
+    } else if full(target.reg) == Register::RCX {
+        let tmp = asm.call.tmp_reg();
+
+        asm.asm.add_instruction(Instruction::with2(Code::Mov_rm64_r64, tmp, Register::RCX)?)?;
+        asm.asm.add_instruction(Instruction::with2(Code::Mov_rm64_r64, Register::RCX, full(src.reg))?)?;
+        asm.asm.add_instruction(Instruction::with2(code, sized(tmp, target.typ.size()), Register::CL)?)?;
+        asm.asm.add_instruction(Instruction::with2(Code::Mov_rm64_r64, Register::RCX, tmp)?)?;
+    } else {
+        asm.asm.add_instruction(Instruction::with1(Code::Push_r64, Register::RCX)?)?;
+        asm.asm.add_instruction(Instruction::with2(Code::Mov_rm64_r64, Register::RCX, full(src.reg))?)?;
+        asm.asm.add_instruction(Instruction::with2(code, target.reg, Register::CL)?)?;
+        asm.asm.add_instruction(Instruction::with1(Code::Pop_r64, Register::RCX)?)?;
+    }
+
+    Ok(())
+}
+
+impl Compile for Not<VarGen> {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let target = &self.inner1;
+
+        if !target.in_reg || !target.reg.is_gpr() {
+            return Err(Box::from(error::IrError::UnsupportedType(format!("{} (in Not)", target.typ.name()))));
+        }
+
+        // a bool is 0 or 1, so only the lowest bit is flipped
+        if target.typ == Type::bool {
+            asm.asm.add_instruction(Instruction::with2(Code::Xor_rm8_imm8, sized(target.reg, 1), 1)?)?;
+            return Ok(());
+        }
+
+        let code = match target.typ.size() {
+            8 => Code::Not_rm64,
+            4 => Code::Not_rm32,
+            2 => Code::Not_rm16,
+            _ => Code::Not_rm8,
+        };
+
+        asm.asm.add_instruction(Instruction::with1(code, target.reg)?)?;
+
+        Ok(())
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.inner1.reg)
+    }
+
+    fn display(&self) -> String {
+        format!("{0} = not {1} {0}", self.inner1, self.inner1.typ)
+    }
+}
+
+impl Compile for Neg<VarGen> {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let target = &self.inner1;
+
+        let elem = match target.typ {
+            Type::vector(elem, _) => *elem,
+            typ => typ,
+        };
+
+        // integer vectors and 256 bit vectors aren't supported
+        if !target.in_reg || target.reg.is_ymm() || (target.typ.vector() && !elem.float()) {
+            return Err(Box::from(error::IrError::UnsupportedType(format!("{} (in Neg)", vector_name(&target.typ)))));
+        }
+
+        if target.reg.is_xmm() {
+            // flips the sign bits (0.0 - x would give +0.0 for 0.0), the mask is all ones shifted to the sign bit
+            let tmp = asm.call.tmpf_reg();
+            let shift = if elem == Type::f64 { Code::Psllq_xmm_imm8 } else { Code::Pslld_xmm_imm8 };
+
+            asm.asm.add_instruction(Instruction::with2(Code::Pcmpeqd_xmm_xmmm128, tmp, tmp)?)?;
+            asm.asm.add_instruction(Instruction::with2(shift, tmp, elem.size() as u32 * 8 - 1)?)?;
+            asm.asm.add_instruction(Instruction::with2(Code::Xorps_xmm_xmmm128, target.reg, tmp)?)?;
+        } else {
+            let code = match target.typ.size() {
+                8 => Code::Neg_rm64,
+                4 => Code::Neg_rm32,
+                2 => Code::Neg_rm16,
+                _ => Code::Neg_rm8,
+            };
+
+            asm.asm.add_instruction(Instruction::with1(code, target.reg)?)?;
+        }
+
+        Ok(())
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.inner1.reg)
+    }
+
+    fn display(&self) -> String {
+        format!("{0} = neg {1} {0}", self.inner1, self.inner1.typ)
+    }
+}
+
+/// Stores the condition flag into the `Type::bool` variable
+fn compile_setcc(asm: &mut AsmFunction, set: Code, out: &VarGen) -> Result<(), Box<dyn std::error::Error>> {
+    let out8 = sized(out.reg, 1);
+
+    asm.asm.add_instruction(Instruction::with1(set, out8)?)?;
+
+    if out.reg != out8 {
+        asm.asm.add_instruction(Instruction::with2(Code::Movzx_r32_rm8, sized(out.reg, 4), out8)?)?;
+    }
+
+    Ok(())
+}
+
+impl Compile for ICmp<VarGen, VarGen> {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let ls = &self.inner1;
+        let rs = &self.inner2;
+
+        check_regs("ICmp", &[ls, rs, &self.out])?;
+
+        let cmp = match ls.typ.size() {
+            8 => Code::Cmp_rm64_r64,
+            4 => Code::Cmp_rm32_r32,
+            2 => Code::Cmp_rm16_r16,
+            _ => Code::Cmp_rm8_r8,
+        };
+
+        let set = match self.cond {
+            ICmpCond::eq => Code::Sete_rm8,
+            ICmpCond::ne => Code::Setne_rm8,
+            ICmpCond::slt => Code::Setl_rm8,
+            ICmpCond::sle => Code::Setle_rm8,
+            ICmpCond::sgt => Code::Setg_rm8,
+            ICmpCond::sge => Code::Setge_rm8,
+            ICmpCond::ult => Code::Setb_rm8,
+            ICmpCond::ule => Code::Setbe_rm8,
+            ICmpCond::ugt => Code::Seta_rm8,
+            ICmpCond::uge => Code::Setae_rm8,
+        };
+
+        asm.asm.add_instruction(Instruction::with2(cmp, ls.reg, rs.reg)?)?;
+        compile_setcc(asm, set, &self.out)?;
+
+        Ok(())
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.out.reg)
+    }
+
+    fn display(&self) -> String {
+        format!("{} = icmp {:?} {} {}, {}", self.out, self.cond, self.inner1.typ, self.inner1, self.inner2)
+    }
+}
+
+impl Compile for FCmp<VarGen, VarGen> {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let ls = &self.inner1;
+        let rs = &self.inner2;
+
+        check_regs("FCmp", &[ls, rs, &self.out])?;
+
+        let cmp = if ls.typ == Type::f64 { Code::Ucomisd_xmm_xmmm64 } else { Code::Ucomiss_xmm_xmmm32 };
+
+        // unordered sets ZF, PF and CF so less conditions are checked with swapped operands
+        let (swap, set) = match self.cond {
+            FCmpCond::ogt => (false, Code::Seta_rm8),
+            FCmpCond::oge => (false, Code::Setae_rm8),
+            FCmpCond::olt => (true, Code::Seta_rm8),
+            FCmpCond::ole => (true, Code::Setae_rm8),
+            FCmpCond::one => (false, Code::Setne_rm8),
+            FCmpCond::ord => (false, Code::Setnp_rm8),
+            FCmpCond::ueq => (false, Code::Sete_rm8),
+            FCmpCond::ult => (false, Code::Setb_rm8),
+            FCmpCond::ule => (false, Code::Setbe_rm8),
+            FCmpCond::ugt => (true, Code::Setb_rm8),
+            FCmpCond::uge => (true, Code::Setbe_rm8),
+            FCmpCond::uno => (false, Code::Setp_rm8),
+            FCmpCond::oeq | FCmpCond::une => (false, Code::Zero_bytes),
+        };
+
+        if swap {
+            asm.asm.add_instruction(Instruction::with2(cmp, rs.reg, ls.reg)?)?;
+        } else {
+            asm.asm.add_instruction(Instruction::with2(cmp, ls.reg, rs.reg)?)?;
+        }
+
+        if set != Code::Zero_bytes {
+            compile_setcc(asm, set, &self.out)?;
+        } else {
+            // oeq: ZF and not PF, une: not ZF or PF
+            let tmp = sized(asm.call.tmp_reg(), 1);
+            let out8 = sized(self.out.reg, 1);
+
+            let (set, set_parity, combine) = if self.cond == FCmpCond::oeq {
+                (Code::Sete_rm8, Code::Setnp_rm8, Code::And_rm8_r8)
+            } else {
+                (Code::Setne_rm8, Code::Setp_rm8, Code::Or_rm8_r8)
+            };
+
+            asm.asm.add_instruction(Instruction::with1(set_parity, tmp)?)?;
+            asm.asm.add_instruction(Instruction::with1(set, out8)?)?;
+            asm.asm.add_instruction(Instruction::with2(combine, out8, tmp)?)?;
+
+            if self.out.reg != out8 {
+                asm.asm.add_instruction(Instruction::with2(Code::Movzx_r32_rm8, sized(self.out.reg, 4), out8)?)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.out.reg)
+    }
+
+    fn display(&self) -> String {
+        format!("{} = fcmp {:?} {} {}, {}", self.out, self.cond, self.inner1.typ, self.inner1, self.inner2)
+    }
+}
+
+impl Compile for Br {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        asm.edge_copies(&self.block)?;
+
+        let label = asm.label(&self.block);
+        asm.asm.jmp(label)?;
+
+        Ok(())
+    }
+
+    fn display(&self) -> String {
+        format!("br label %{}", self.block)
+    }
+}
+
+impl Compile for CondBr {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let cond = sized(self.cond.reg, 1);
+
+        let then = asm.label(&self.then);
+        let otherwise = asm.label(&self.otherwise);
+
+        asm.asm.add_instruction(Instruction::with2(Code::Test_rm8_r8, cond, cond)?)?;
+
+        if !asm.has_edge_copies(&self.then) {
+            asm.asm.jne(then)?;
+            asm.edge_copies(&self.otherwise)?;
+            asm.asm.jmp(otherwise)?;
+        } else {
+            // the copies of the then edge need their own path
+            let mut edge = asm.asm.create_label();
+
+            asm.asm.jne(edge)?;
+            asm.edge_copies(&self.otherwise)?;
+            asm.asm.jmp(otherwise)?;
+
+            asm.asm.set_label(&mut edge)?;
+            asm.asm.zero_bytes()?;
+            asm.edge_copies(&self.then)?;
+            asm.asm.jmp(then)?;
+        }
+
+        Ok(())
+    }
+
+    fn display(&self) -> String {
+        format!("br {} {}, label %{}, label %{}", self.cond.typ, self.cond, self.then, self.otherwise)
+    }
+}
+
+/// Switches with less cases are lowered to a chain of compares
+const SWITCH_CHAIN: usize = 4;
+/// How many entries a jump table may have per case
+const SWITCH_DENSITY: u64 = 3;
+/// The maximal number of entries of a jump table
+const SWITCH_TABLE_MAX: u64 = 4096;
+
+/// Returns the case value like it looks after the switch value is extended to 64 bits
+fn normalize_key(key: i64, bits: usize, signed: bool) -> u64 {
+    if bits >= 64 {
+        key as u64
+    } else if signed {
+        ((key << (64 - bits)) >> (64 - bits)) as u64
+    } else {
+        (key as u64) & ((1 << bits) - 1)
+    }
+}
+
+/// Returns the label which is jumped to for the given block
+/// 
+/// If copies need to be done on the edge, a label is created which is bound later (in `trampolines`)
+fn switch_target(asm: &mut AsmFunction, block: &str, trampolines: &mut Vec<(CodeLabel, String)>) -> CodeLabel {
+    if !asm.has_edge_copies(block) {
+        return asm.label(block);
+    }
+
+    if let Some((label, _)) = trampolines.iter().find(|(_, name)| name == block) {
+        return *label;
+    }
+
+    let label = asm.asm.create_label();
+    trampolines.push((label, block.to_string()));
+
+    label
+}
+
+/// Compares the switch value (in the scratch register) with the key
+fn compile_cmp_key(asm: &mut AsmFunction, key: u64) -> Result<(), Box<dyn std::error::Error>> {
+    let tmp = asm.call.tmp_reg();
+
+    if i32::try_from(key as i64).is_ok() {
+        asm.asm.add_instruction(Instruction::with2(Code::Cmp_rm64_imm32, tmp, key as i64 as i32)?)?;
+    } else {
+        // the key needs a register, so rax is used and saved on the stack
+        asm.asm.add_instruction(Instruction::with1(Code::Push_r64, Register::RAX)?)?;
+        asm.asm.add_instruction(Instruction::with2(Code::Mov_r64_imm64, Register::RAX, key)?)?;
+        asm.asm.add_instruction(Instruction::with2(Code::Cmp_rm64_r64, tmp, Register::RAX)?)?;
+        asm.asm.add_instruction(Instruction::with1(Code::Pop_r64, Register::RAX)?)?;
+    }
+
+    Ok(())
+}
+
+/// Emits a binary search over the sorted cases (small parts are searched with a chain of compares)
+fn compile_switch_search(asm: &mut AsmFunction, cases: &[(u64, String)], signed: bool, default: CodeLabel, trampolines: &mut Vec<(CodeLabel, String)>) -> Result<(), Box<dyn std::error::Error>> {
+    if cases.len() < SWITCH_CHAIN {
+        for (key, block) in cases {
+            compile_cmp_key(asm, *key)?;
+
+            let target = switch_target(asm, block, trampolines);
+            asm.asm.je(target)?;
+        }
+
+        asm.asm.jmp(default)?;
+
+        return Ok(());
+    }
+
+    let mid = cases.len() / 2;
+    let (key, block) = &cases[mid];
+
+    compile_cmp_key(asm, *key)?;
+
+    let target = switch_target(asm, block, trampolines);
+    asm.asm.je(target)?;
+
+    let mut upper = asm.asm.create_label();
+
+    if signed {
+        asm.asm.jg(upper)?;
+    } else {
+        asm.asm.ja(upper)?;
+    }
+
+    compile_switch_search(asm, &cases[..mid], signed, default, trampolines)?;
+
+    asm.asm.set_label(&mut upper)?;
+    compile_switch_search(asm, &cases[mid + 1..], signed, default, trampolines)?;
+
+    Ok(())
+}
+
+/// Emits a jump through a table in the data of the function
+/// 
+/// The table has an entry for every value from the first to the last case,
+/// which is the offset of the target relative to the start of the table
+fn compile_jump_table(asm: &mut AsmFunction, cases: &[(u64, String)], default: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let tmp = asm.call.tmp_reg();
+
+    let min = cases[0].0;
+    let len = cases[cases.len() - 1].0.wrapping_sub(min) as usize + 1;
+
+    let table = asm.req_name();
+
+    if min != 0 {
+        asm.asm.add_instruction(Instruction::with2(Code::Sub_rm64_imm32, tmp, min as i64 as i32)?)?;
+    }
+
+    let default_label = asm.asm.create_label();
+
+    // values below the first case wrapped around, so one unsigned compare is enough
+    asm.asm.add_instruction(Instruction::with2(Code::Cmp_rm64_imm32, tmp, len as i32 - 1)?)?;
+    asm.asm.ja(default_label)?;
+
+    let entry = MemoryOperand::new(Register::RAX, tmp, 4, 0, 0, false, Register::None);
+
+    asm.asm.add_instruction(Instruction::with1(Code::Push_r64, Register::RAX)?)?;
+    asm.asm.add_instruction(Instruction::with2(Code::Lea_r64_m, Register::RAX, rip_mem())?)?;
+    asm.reloc_at_current_pos(&table, -4, 4)?;
+    asm.asm.add_instruction(Instruction::with2(Code::Movsxd_r64_rm32, tmp, entry)?)?;
+    asm.asm.add_instruction(Instruction::with2(Code::Add_rm64_r64, tmp, Register::RAX)?)?;
+    asm.asm.add_instruction(Instruction::with1(Code::Pop_r64, Register::RAX)?)?;
+    asm.asm.add_instruction(Instruction::with1(Code::Jmp_rm64, tmp)?)?;
+
+    let mut entries: Vec<String> = vec![default.to_string(); len];
+
+    for (key, block) in cases {
+        entries[key.wrapping_sub(min) as usize] = block.to_string();
+    }
+
+    // every target gets its own label in the function, so the table entries can point to it
+    let mut targets: Vec<String> = vec![default.to_string()];
+
+    for block in &entries {
+        if !targets.contains(block) {
+            targets.push(block.to_string());
+        }
+    }
+
+    for block in targets {
+        let mut label = if block == default { default_label } else { asm.asm.create_label() };
+
+        asm.asm.set_label(&mut label)?;
+
+        for (index, entry) in entries.iter().enumerate() {
+            if *entry == block {
+                asm.table_entry_at_current_pos(&table, index * 4);
+            }
+        }
+
+        asm.asm.zero_bytes()?;
+
+        asm.edge_copies(&block)?;
+
+        let target = asm.label(&block);
+        asm.asm.jmp(target)?;
+    }
+
+    asm.data.insert(table, vec![0; len * 4]);
+
+    Ok(())
+}
+
+impl Compile for Switch {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let bits = self.value.typ.size() * 8;
+        let signed = self.value.typ.signed();
+
+        let mut cases: Vec<(u64, String)> = self.cases.iter()
+            .map(|(key, block)| (normalize_key(*key, bits, signed), block.to_string()))
+            .collect();
+
+        if signed {
+            cases.sort_by_key(|(key, _)| *key as i64);
+        } else {
+            cases.sort_by_key(|(key, _)| *key);
+        }
+
+        // the first case with a value wins
+        cases.dedup_by_key(|(key, _)| *key);
+
+        let tmp = asm.call.tmp_reg();
+
+        if signed {
+            compile_sext(asm, tmp, &self.value)?;
+        } else {
+            compile_zext(asm, tmp, &self.value)?;
+        }
+
+        let dense = match (cases.first(), cases.last()) {
+            (Some((min, _)), Some((max, _))) => {
+                let len = max.wrapping_sub(*min).saturating_add(1);
+
+                cases.len() >= SWITCH_CHAIN && len <= cases.len() as u64 * SWITCH_DENSITY && len <= SWITCH_TABLE_MAX && i32::try_from(*min as i64).is_ok()
+            },
+            _ => false,
+        };
+
+        if dense {
+            return compile_jump_table(asm, &cases, &self.default);
+        }
+
+        let mut trampolines = vec![];
+
+        let default = switch_target(asm, &self.default, &mut trampolines);
+        compile_switch_search(asm, &cases, signed, default, &mut trampolines)?;
+
+        for (mut label, block) in trampolines {
+            asm.asm.set_label(&mut label)?;
+            asm.asm.zero_bytes()?;
+
+            asm.edge_copies(&block)?;
+
+            let target = asm.label(&block);
+            asm.asm.jmp(target)?;
+        }
+
+        Ok(())
+    }
+
+    fn display(&self) -> String {
+        let cases: Vec<String> = self.cases.iter().map(|(value, block)| format!("{}: label %{}", value, block)).collect();
+
+        format!("switch {} {}, label %{} [{}]", self.value.typ, self.value, self.default, cases.join(", "))
+    }
+}
+
+impl Compile for Phi {
+    fn prepare(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        for (block, value) in &self.incoming {
+            asm.add_edge_copy(block, self.out.reg, value.reg);
+        }
+
+        Ok(())
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.out.reg)
+    }
+
+    fn display(&self) -> String {
+        let incoming: Vec<String> = self.incoming.iter().map(|(block, value)| format!("[{}, %{}]", value, block)).collect();
+
+        format!("{} = phi {} {}", self.out, self.out.typ, incoming.join(", "))
+    }
+}
+
+/// Returns the memory operand of a stack slot (`[rbp - adr]`)
+fn stack_mem(slot: &VarGen) -> MemoryOperand {
+    MemoryOperand::new(Register::RBP, Register::None, 1, -(slot.stack_adr as i64), 1, false, Register::None)
+}
+
+/// Returns the memory operand of the value the pointer points to (`[ptr]`)
+fn ptr_mem(ptr: &VarGen) -> MemoryOperand {
+    MemoryOperand::new(full(ptr.reg), Register::None, 1, 0, 0, false, Register::None)
+}
+
+/// Returns the memory operand of the upper half of a 128 bit value in a stack slot
+fn stack_mem_hi(slot: &VarGen) -> MemoryOperand {
+    MemoryOperand::new(Register::RBP, Register::None, 1, -(slot.stack_adr as i64) + 8, 1, false, Register::None)
+}
+
+/// Copies the 128 bit value from `src` to `dst` (through the float scratch register)
+fn compile_copy_wide(asm: &mut AsmFunction, dst: MemoryOperand, src: MemoryOperand) -> Result<(), Box<dyn std::error::Error>> {
+    let tmp = asm.call.tmpf_reg();
+
+    asm.asm.add_instruction(Instruction::with2(Code::Movdqu_xmm_xmmm128, tmp, src)?)?;
+    asm.asm.add_instruction(Instruction::with2(Code::Movdqu_xmmm128_xmm, dst, tmp)?)?;
+
+    Ok(())
+}
+
+/// Loads a value of the type `typ` from `mem` into `dst`
+fn compile_load(asm: &mut AsmFunction, dst: Register, typ: Type, mem: MemoryOperand) -> Result<(), Box<dyn std::error::Error>> {
+    let code = match typ {
+        Type::f64 => Code::Movsd_xmm_xmmm64,
+        Type::f32 => Code::Movss_xmm_xmmm32,
+        Type::vector(..) if typ.size() == 32 => Code::VEX_Vmovups_ymm_ymmm256,
+        Type::vector(..) => Code::Movups_xmm_xmmm128,
+        _ => match typ.size() {
+            8 => Code::Mov_r64_rm64,
+            4 => Code::Mov_r32_rm32,
+            2 => Code::Mov_r16_rm16,
+            _ => Code::Mov_r8_rm8,
+        },
+    };
+
+    asm.asm.add_instruction(Instruction::with2(code, dst, mem)?)?;
+
+    Ok(())
+}
+
+/// Stores `src` which holds a value of the type `typ` into `mem`
+fn compile_store(asm: &mut AsmFunction, mem: MemoryOperand, src: Register, typ: Type) -> Result<(), Box<dyn std::error::Error>> {
+    let code = match typ {
+        Type::f64 => Code::Movsd_xmmm64_xmm,
+        Type::f32 => Code::Movss_xmmm32_xmm,
+        Type::vector(..) if typ.size() == 32 => Code::VEX_Vmovups_ymmm256_ymm,
+        Type::vector(..) => Code::Movups_xmmm128_xmm,
+        _ => match typ.size() {
+            8 => Code::Mov_rm64_r64,
+            4 => Code::Mov_rm32_r32,
+            2 => Code::Mov_rm16_r16,
+            _ => Code::Mov_rm8_r8,
+        },
+    };
+
+    asm.asm.add_instruction(Instruction::with2(code, mem, src)?)?;
+
+    Ok(())
+}
+
+impl Compile for Alloca<VarGen> {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        // the slot itself is reserved by `AsmFunction::alloca`, the frame by the prologue
+        if !asm.is_slot(&self.inner1) {
+            return Err(Box::from(error::IrError::UnknownSlot(self.inner1.to_string())));
+        }
+
+        asm.make_stack_safe()?;
+
+        Ok(())
+    }
+
+    fn display(&self) -> String {
+        format!("{} = alloca {}", self.inner1, self.inner1.typ)
+    }
+}
+
+/// Returns the memory which is accessed by `Load`/`Store` through the slot
+/// (a pointer in a register or a stack slot from `AsmFunction::alloca`)
+/// 
+/// The value needs to have the type of the stack slot (or the one a typed pointer points to)
+fn slot_mem(asm: &mut AsmFunction, ir: &str, slot: &VarGen, typ: Type) -> Result<MemoryOperand, Box<dyn std::error::Error>> {
+    let (mem, expected) = if slot.in_reg && slot.typ.pointer() {
+        (ptr_mem(slot), slot.typ.pointee())
+    } else if asm.is_slot(slot) {
+        asm.make_stack_safe()?;
+        (stack_mem(slot), Some(slot.typ))
+    } else {
+        return Err(Box::from(error::IrError::UnknownSlot(slot.to_string())));
+    };
+
+    match expected {
+        Some(expected) if expected != typ => Err(Box::from(error::IrError::TypeMismatch(ir.to_string(), expected.to_string(), typ.to_string()))),
+        _ => Ok(mem),
+    }
+}
+
+impl Compile for Load<VarGen, VarGen> {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let out = &self.inner1;
+        let src = slot_mem(asm, "Load", &self.inner2, out.typ)?;
+
+        if out.typ.wide() {
+            // 128 bit values are stored in stack slots
+            asm.make_stack_safe()?;
+            compile_copy_wide(asm, stack_mem(out), src)?;
+        } else {
+            check_regs("Load", &[out])?;
+            compile_load(asm, out.reg, out.typ, src)?;
+        }
+
+        Ok(())
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.inner1.reg)
+    }
+
+    fn display(&self) -> String {
+        format!("{} = load {}, ptr {}", self.inner1, self.inner1.typ, self.inner2)
+    }
+}
+
+impl Compile for Store<VarGen, VarGen> {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let value = &self.inner2;
+        let dst = slot_mem(asm, "Store", &self.inner1, value.typ)?;
+
+        if value.typ.wide() {
+            asm.make_stack_safe()?;
+            compile_copy_wide(asm, dst, stack_mem(value))?;
+        } else {
+            check_regs("Store", &[value])?;
+            compile_store(asm, dst, value.reg, value.typ)?;
+        }
+
+        Ok(())
+    }
+
+    fn display(&self) -> String {
+        format!("store {} {}, ptr {}", self.inner2.typ, self.inner2, self.inner1)
+    }
+}
+
+impl Compile for GetElementPtr {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let (base, mut displ) = if self.base.in_reg {
+            (full(self.base.reg), 0)
+        } else {
+            asm.make_stack_safe()?;
+            (Register::RBP, -(self.base.stack_adr as i64))
+        };
+
+        displ += self.offset as i64;
+
+        let (index, scale) = match self.index {
+            Some(index) => {
+                let tmp = asm.call.tmp_reg();
+
+                // the index needs to be a 64 bit register
+                if index.typ.signed() {
+                    compile_sext(asm, tmp, &index)?;
+                } else {
+                    compile_zext(asm, tmp, &index)?;
+                }
+
+                if matches!(self.scale, 1 | 2 | 4 | 8) {
+                    (tmp, self.scale as u32)
+                } else {
+                    asm.asm.add_instruction(Instruction::with3(Code::Imul_r64_rm64_imm32, tmp, tmp, self.scale as i32)?)?;
+                    (tmp, 1)
+                }
+            },
+            None => (Register::None, 1),
+        };
+
+        let mem = MemoryOperand::new(base, index, scale, displ, 1, false, Register::None);
+
+        asm.asm.add_instruction(Instruction::with2(Code::Lea_r64_m, full(self.out.reg), mem)?)?;
+
+        Ok(())
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.out.reg)
+    }
+
+    fn display(&self) -> String {
+        let index = match self.index {
+            Some(index) => format!(", {} {} * {}", index.typ, index, self.scale),
+            None => String::new(),
+        };
+
+        format!("{} = getelementptr ptr {}{}, {}", self.out, self.base, index, self.offset)
+    }
+}
+
+impl Compile for GlobalAddr {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        asm.asm.add_instruction(Instruction::with2(Code::Lea_r64_m, full(self.out.reg), rip_mem())?)?;
+        asm.reloc_at_current_pos(&self.name, -4, 4)?;
+
+        Ok(())
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.out.reg)
+    }
+
+    fn display(&self) -> String {
+        format!("{} = globaladdr @{}", self.out, self.name)
+    }
+}
+
+/// Returns the size which is needed to save the register around a call
+fn saved_size(reg: Register) -> usize {
+    match reg {
+        reg if reg.is_gpr() => 8,
+        reg if reg.is_ymm() => 32,
+        _ => 16,
+    }
+}
+
+/// Returns the memory operand `[rsp + off]`
+fn rsp_mem(off: usize) -> MemoryOperand {
+    MemoryOperand::new(Register::RSP, Register::None, 1, off as i64, 1, false, Register::None)
+}
+
+/// Stores the registers to `[rsp + off]` and the following slots
+fn compile_spill(asm: &mut AsmFunction, regs: &[Register], mut off: usize) -> Result<(), Box<dyn std::error::Error>> {
+    for reg in regs {
+        let code = match reg {
+            reg if reg.is_gpr() => Code::Mov_rm64_r64,
+            reg if reg.is_ymm() => Code::VEX_Vmovdqu_ymmm256_ymm,
+            _ => Code::Movdqu_xmmm128_xmm,
+        };
+
+        asm.asm.add_instruction(Instruction::with2(code, rsp_mem(off), *reg)?)?;
+        off += saved_size(*reg);
+    }
+
+    Ok(())
+}
+
+/// Loads the registers which were stored by `compile_spill`
+fn compile_reload(asm: &mut AsmFunction, regs: &[Register], mut off: usize) -> Result<(), Box<dyn std::error::Error>> {
+    for reg in regs {
+        let code = match reg {
+            reg if reg.is_gpr() => Code::Mov_r64_rm64,
+            reg if reg.is_ymm() => Code::VEX_Vmovdqu_ymm_ymmm256,
+            _ => Code::Movdqu_xmm_xmmm128,
+        };
+
+        asm.asm.add_instruction(Instruction::with2(code, *reg, rsp_mem(off))?)?;
+        off += saved_size(*reg);
+    }
+
+    Ok(())
+}
+
+impl Compile for Call {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(intrinsic) = Intrinsic::from_name(&self.func) {
+            return compile_intrinsic(asm, intrinsic, &self.args, self.out);
+        }
+
+        // the stack needs to be aligned for the call
+        asm.make_stack_safe()?;
+
+        let types: Vec<Type> = self.args.iter().map(|arg| arg.typ).collect();
+        let reg_args = asm.call.reg_args(&types);
+
+        let out = self.out.filter(|out| out.in_reg).map(|out| full(out.reg));
+
+        let saved: Vec<Register> = asm.live_regs().into_iter()
+            .filter(|reg| Some(full(*reg)) != out)
+            .collect();
+
+        let (stack_args, outgoing) = stack_arg_offsets(asm, &types, &reg_args);
+
+        let save_size: usize = saved.iter().map(|reg| saved_size(*reg)).sum();
+        let frame = (outgoing + save_size).next_multiple_of(16);
+
+        if frame > 0 {
+            asm.asm.add_instruction(Instruction::with2(Code::Sub_rm64_imm32, Register::RSP, frame as i32)?)?;
+        }
+
+        compile_spill(asm, &saved, outgoing)?;
+
+        let variadic = self.variadic.map(|fixed| (fixed, reg_args.clone(), stack_args.clone()));
+
+        compile_call_args(asm, &self.args, reg_args, stack_args, rsp_mem)?;
+
+        if let Some((fixed, reg_args, stack_args)) = variadic {
+            compile_variadic_args(asm, &self.args, fixed, &reg_args, &stack_args)?;
+        }
+
+        asm.asm.call(0)?;
+        asm.reloc_at_current_pos(&self.func, -4, 4)?;
+
+        if let Some(out) = self.out {
+            if out.typ.wide() {
+                compile_store_wide(asm, &out)?;
+            } else {
+                let ret = if out.typ.float() || out.typ.vector() { asm.call.retf_reg() } else { asm.call.ret64_reg() };
+
+                asm.parallel_copy(vec![(out.reg, ret)])?;
+            }
+        }
+
+        compile_reload(asm, &saved, outgoing)?;
+
+        if frame > 0 {
+            asm.asm.add_instruction(Instruction::with2(Code::Add_rm64_imm32, Register::RSP, frame as i32)?)?;
+        }
+
+        Ok(())
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        self.out.map(|out| out.reg)
+    }
+
+    fn display(&self) -> String {
+        let ret = self.out.map(|out| out.typ).unwrap_or(Type::void);
+
+        // the types of the fixed arguments like in llvm (`call i32 (ptr, ...) @printf(...)`)
+        let signature = match self.variadic {
+            Some(fixed) => {
+                let types: Vec<String> = self.args.iter().take(fixed).map(|arg| arg.typ.to_string()).chain(["...".to_string()]).collect();
+                format!(" ({})", types.join(", "))
+            },
+            None => String::new(),
+        };
+
+        let call = format!("call {}{} @{}({})", ret, signature, self.func, display_args(&self.args));
+
+        match self.out {
+            Some(out) => format!("{} = {}", out, call),
+            None => call,
+        }
+    }
+}
+
+/// Returns the offsets of the arguments which are passed on the stack (relative to the stack pointer at the call)
+/// and the size of the area which is needed for them (including the home space)
+fn stack_arg_offsets(asm: &AsmFunction, types: &[Type], reg_args: &[Option<usize>]) -> (Vec<usize>, usize) {
+    let mut stack_args = vec![];
+    let mut outgoing = asm.call.home();
+
+    for (typ, index) in types.iter().zip(reg_args) {
+        if index.is_some() {
+            stack_args.push(0);
+            continue;
+        }
+
+        // on windows 128 bit integers are passed as pointers
+        let size = if typ.wide() && asm.call.conv() != CallingConvention::WindowsFastcall { 16 } else { 8 };
+
+        outgoing = outgoing.next_multiple_of(size);
+        stack_args.push(outgoing);
+        outgoing += size;
+    }
+
+    (stack_args, outgoing)
+}
+
+/// Moves the arguments into their registers and stack slots (`stack` returns the memory of a stack offset)
+fn compile_call_args(asm: &mut AsmFunction, args: &[VarGen], reg_args: Vec<Option<usize>>, stack_args: Vec<usize>, stack: fn(usize) -> MemoryOperand) -> Result<(), Box<dyn std::error::Error>> {
+    let mut copies = vec![];
+    let mut loads = vec![];
+    let mut wide = vec![];
+
+    for ((arg, index), off) in args.iter().zip(reg_args).zip(stack_args) {
+        if arg.typ.wide() {
+            asm.make_stack_safe()?;
+
+            match index {
+                Some(index) => wide.push((index, arg)),
+                None if asm.call.conv() == CallingConvention::WindowsFastcall => {
+                    let tmp = asm.call.tmp_reg();
+
+                    asm.asm.add_instruction(Instruction::with2(Code::Lea_r64_m, tmp, stack_mem(arg))?)?;
+                    asm.asm.add_instruction(Instruction::with2(Code::Mov_rm64_r64, stack(off), tmp)?)?;
+                },
+                None => compile_copy_wide(asm, stack(off), stack_mem(arg))?,
+            }
+
+            continue;
+        }
+
+        match index {
+            Some(index) => {
+                let reg = if arg.typ.float() { asm.call.argf_reg(index) } else { asm.call.arg64_reg(index) }.unwrap(); // index is from the call conv
+
+                if arg.in_reg {
+                    copies.push((reg, arg.reg));
+                } else {
+                    loads.push((reg, arg));
+                }
+            },
+            None => {
+                let mem = stack(off);
+
+                if arg.in_reg {
+                    compile_store(asm, mem, arg.reg, arg.typ)?;
+                } else {
+                    let tmp = if arg.typ.float() { asm.call.tmpf_reg() } else { sized(asm.call.tmp_reg(), arg.typ.size()) };
+
+                    compile_load(asm, tmp, arg.typ, stack_mem(arg))?;
+                    compile_store(asm, mem, tmp, arg.typ)?;
+                }
+            },
+        }
+    }
+
+    asm.parallel_copy(copies)?;
+
+    for (reg, arg) in loads {
+        let reg = if arg.typ.float() { reg } else { sized(reg, arg.typ.size()) };
+
+        compile_load(asm, reg, arg.typ, stack_mem(arg))?;
+    }
+
+    for (index, arg) in wide {
+        let reg = asm.call.arg64_reg(index).unwrap(); // index is from the call conv
+
+        if asm.call.conv() == CallingConvention::WindowsFastcall {
+            // a pointer to the value (the callee gets the slot itself, not a copy)
+            asm.asm.add_instruction(Instruction::with2(Code::Lea_r64_m, reg, stack_mem(arg))?)?;
+        } else {
+            let upper = asm.call.arg64_reg(index + 1).unwrap();
+
+            asm.asm.add_instruction(Instruction::with2(Code::Mov_r64_rm64, reg, stack_mem(arg))?)?;
+            asm.asm.add_instruction(Instruction::with2(Code::Mov_r64_rm64, upper, stack_mem_hi(arg))?)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Promotes the variadic arguments (after the `fixed` ones) in their registers and stack slots
+/// and passes the additional information which variadic functions need
+fn compile_variadic_args(asm: &mut AsmFunction, args: &[VarGen], fixed: usize, reg_args: &[Option<usize>], stack_args: &[usize]) -> Result<(), Box<dyn std::error::Error>> {
+    for ((arg, index), off) in args.iter().zip(reg_args).zip(stack_args).skip(fixed) {
+        let promoted = asm.call.variadic_type(arg.typ);
+
+        let extend = match (arg.typ.size(), arg.typ.signed()) {
+            (1, true) => Code::Movsx_r32_rm8,
+            (1, false) => Code::Movzx_r32_rm8,
+            (2, true) => Code::Movsx_r32_rm16,
+            _ => Code::Movzx_r32_rm16,
+        };
+
+        match index {
+            Some(index) if arg.typ.float() => {
+                let reg = asm.call.argf_reg(*index).unwrap(); // index is from the call conv
+
+                if promoted != arg.typ {
+                    asm.asm.add_instruction(Instruction::with2(Code::Cvtss2sd_xmm_xmmm32, reg, reg)?)?;
+                }
+
+                if asm.call.variadic_int_copies() {
+                    let int = asm.call.arg64_reg(*index).unwrap(); // windows has as many integer as float argument registers
+
+                    asm.asm.add_instruction(Instruction::with2(Code::Movq_rm64_xmm, int, reg)?)?;
+                }
+            },
+            Some(index) => {
+                let reg = asm.call.arg64_reg(*index).unwrap(); // index is from the call conv
+
+                if promoted != arg.typ {
+                    asm.asm.add_instruction(Instruction::with2(extend, sized(reg, 4), sized(reg, arg.typ.size()))?)?;
+                }
+            },
+            None if promoted == arg.typ => {},
+            None if arg.typ.float() => {
+                let tmp = asm.call.tmpf_reg();
+
+                asm.asm.add_instruction(Instruction::with2(Code::Cvtss2sd_xmm_xmmm32, tmp, rsp_mem(*off))?)?;
+                asm.asm.add_instruction(Instruction::with2(Code::Movsd_xmmm64_xmm, rsp_mem(*off), tmp)?)?;
+            },
+            None => {
+                let tmp = sized(asm.call.tmp_reg(), 4);
+
+                asm.asm.add_instruction(Instruction::with2(extend, tmp, rsp_mem(*off))?)?;
+                asm.asm.add_instruction(Instruction::with2(Code::Mov_rm32_r32, rsp_mem(*off), tmp)?)?;
+            },
+        }
+    }
+
+    let floats = args.iter().zip(reg_args).filter(|(arg, index)| arg.typ.float() && index.is_some()).count();
+
+    if let Some(al) = asm.call.variadic_al(floats) {
+        asm.asm.add_instruction(Instruction::with2(Code::Mov_r32_imm32, Register::EAX, al as u32)?)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the error for a conversion of `value` into `out` which isn't possible
+fn invalid_cast(ir: &str, out: &VarGen, value: &VarGen) -> Box<dyn std::error::Error> {
+    Box::from(error::IrError::InvalidCast(ir.to_string(), value.typ.name().to_string(), out.typ.name().to_string()))
+}
+
+/// Zero extends the integer `value` into the 64 bit register `dst`
+fn compile_zext(asm: &mut AsmFunction, dst: Register, value: &VarGen) -> Result<(), Box<dyn std::error::Error>> {
+    let (code, dst) = match value.typ.size() {
+        1 => (Code::Movzx_r32_rm8, sized(dst, 4)),
+        2 => (Code::Movzx_r32_rm16, sized(dst, 4)),
+        4 => (Code::Mov_r32_rm32, sized(dst, 4)), // writing the 32 bit register clears the upper half
+        _ => (Code::Mov_r64_rm64, dst),
+    };
+
+    asm.asm.add_instruction(Instruction::with2(code, dst, value.reg)?)?;
+
+    Ok(())
+}
+
+/// Sign extends the integer `value` into the 64 bit register `dst`
+fn compile_sext(asm: &mut AsmFunction, dst: Register, value: &VarGen) -> Result<(), Box<dyn std::error::Error>> {
+    let code = match value.typ.size() {
+        1 => Code::Movsx_r64_rm8,
+        2 => Code::Movsx_r64_rm16,
+        4 => Code::Movsxd_r64_rm32,
+        _ => Code::Mov_r64_rm64,
+    };
+
+    asm.asm.add_instruction(Instruction::with2(code, dst, value.reg)?)?;
+
+    Ok(())
+}
+
+/// Converts the 32 or 64 bit signed integer in `src` into the float `out`
+fn compile_cvtsi(asm: &mut AsmFunction, out: &VarGen, src: Register) -> Result<(), Box<dyn std::error::Error>> {
+    let code = match (out.typ, src.is_gpr64()) {
+        (Type::f64, true) => Code::Cvtsi2sd_xmm_rm64,
+        (Type::f64, false) => Code::Cvtsi2sd_xmm_rm32,
+        (_, true) => Code::Cvtsi2ss_xmm_rm64,
+        (_, false) => Code::Cvtsi2ss_xmm_rm32,
+    };
+
+    asm.asm.add_instruction(Instruction::with2(code, out.reg, src)?)?;
+
+    Ok(())
+}
+
+/// Converts (with truncation) the float `src` into the 32 or 64 bit signed integer in `dst`
+fn compile_cvttsi(asm: &mut AsmFunction, dst: Register, src: Register, typ: Type) -> Result<(), Box<dyn std::error::Error>> {
+    let code = match (typ, dst.is_gpr64()) {
+        (Type::f64, true) => Code::Cvttsd2si_r64_xmmm64,
+        (Type::f64, false) => Code::Cvttsd2si_r32_xmmm64,
+        (_, true) => Code::Cvttss2si_r64_xmmm32,
+        (_, false) => Code::Cvttss2si_r32_xmmm32,
+    };
+
+    asm.asm.add_instruction(Instruction::with2(code, dst, src)?)?;
+
+    Ok(())
+}
+
+impl Compile for ZExt<VarGen, VarGen> {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let out = &self.inner1;
+        let value = &self.inner2;
+
+        if out.typ.float() || value.typ.float() || out.typ.size() < value.typ.size() {
+            return Err(invalid_cast("zext", out, value));
+        }
+
+        compile_zext(asm, full(out.reg), value)?;
+
+        Ok(())
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.inner1.reg)
+    }
+
+    fn display(&self) -> String {
+        display_cast("zext", &self.inner1, &self.inner2)
+    }
+}
+
+impl Compile for SExt<VarGen, VarGen> {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let out = &self.inner1;
+        let value = &self.inner2;
+
+        if out.typ.float() || value.typ.float() || out.typ.size() < value.typ.size() {
+            return Err(invalid_cast("sext", out, value));
+        }
+
+        compile_sext(asm, full(out.reg), value)?;
+
+        Ok(())
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.inner1.reg)
+    }
+
+    fn display(&self) -> String {
+        display_cast("sext", &self.inner1, &self.inner2)
+    }
+}
+
+impl Compile for Trunc<VarGen, VarGen> {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let out = &self.inner1;
+        let value = &self.inner2;
+
+        if out.typ.float() || value.typ.float() || out.typ.size() > value.typ.size() {
+            return Err(invalid_cast("trunc", out, value));
+        }
+
+        // the upper bits of a variable are ignored, so the lower bits are just copied
+        let (code, size) = if out.typ.size() == 8 { (Code::Mov_r64_rm64, 8) } else { (Code::Mov_r32_rm32, 4) };
+
+        asm.asm.add_instruction(Instruction::with2(code, sized(out.reg, size), sized(value.reg, size))?)?;
+
+        Ok(())
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.inner1.reg)
+    }
+
+    fn display(&self) -> String {
+        display_cast("trunc", &self.inner1, &self.inner2)
+    }
+}
+
+impl Compile for SIToFP<VarGen, VarGen> {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let out = &self.inner1;
+        let value = &self.inner2;
+
+        if !out.typ.float() || value.typ.float() {
+            return Err(invalid_cast("sitofp", out, value));
+        }
+
+        let src = if value.typ.size() >= 4 {
+            value.reg
+        } else {
+            let tmp = asm.call.tmp_reg();
+            compile_sext(asm, tmp, value)?;
+
+            tmp
+        };
+
+        compile_cvtsi(asm, out, src)?;
+
+        Ok(())
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.inner1.reg)
+    }
+
+    fn display(&self) -> String {
+        display_cast("sitofp", &self.inner1, &self.inner2)
+    }
+}
+
+impl Compile for UIToFP<VarGen, VarGen> {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let out = &self.inner1;
+        let value = &self.inner2;
+
+        if !out.typ.float() || value.typ.float() {
+            return Err(invalid_cast("uitofp", out, value));
+        }
+
+        let tmp = asm.call.tmp_reg();
+
+        if value.typ.size() < 8 {
+            // every zero extended value fits into a signed 64 bit integer
+            compile_zext(asm, tmp, value)?;
+            compile_cvtsi(asm, out, tmp)?;
+
+            return Ok(());
+        }
+
+        let mut big = asm.asm.create_label();
+        let mut even = asm.asm.create_label();
+        let mut done = asm.asm.create_label();
+
+        asm.asm.add_instruction(Instruction::with2(Code::Test_rm64_r64, value.reg, value.reg)?)?;
+        asm.asm.js(big)?;
+        compile_cvtsi(asm, out, value.reg)?;
+        asm.asm.jmp(done)?;
+
+        // the value is halved (keeping the lowest bit for the rounding) and doubled after the conversion
+        asm.asm.set_label(&mut big)?;
+        asm.asm.add_instruction(Instruction::with2(Code::Mov_r64_rm64, tmp, value.reg)?)?;
+        asm.asm.add_instruction(Instruction::with2(Code::Shr_rm64_1, tmp, 1)?)?;
+        asm.asm.jnc(even)?;
+        asm.asm.add_instruction(Instruction::with2(Code::Or_rm64_imm8, tmp, 1)?)?;
+
+        asm.asm.set_label(&mut even)?;
+        compile_cvtsi(asm, out, tmp)?;
+
+        let add = if out.typ == Type::f64 { Code::Addsd_xmm_xmmm64 } else { Code::Addss_xmm_xmmm32 };
+        asm.asm.add_instruction(Instruction::with2(add, out.reg, out.reg)?)?;
+
+        asm.asm.set_label(&mut done)?;
+        asm.asm.zero_bytes()?;
+
+        Ok(())
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.inner1.reg)
+    }
+
+    fn display(&self) -> String {
+        display_cast("uitofp", &self.inner1, &self.inner2)
+    }
+}
+
+impl Compile for FPToSI<VarGen, VarGen> {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let out = &self.inner1;
+        let value = &self.inner2;
+
+        if out.typ.float() || !value.typ.float() {
+            return Err(invalid_cast("fptosi", out, value));
+        }
+
+        let dst = if out.typ.size() == 8 { full(out.reg) } else { sized(out.reg, 4) };
+
+        compile_cvttsi(asm, dst, value.reg, value.typ)?;
+
+        Ok(())
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.inner1.reg)
+    }
+
+    fn display(&self) -> String {
+        display_cast("fptosi", &self.inner1, &self.inner2)
+    }
+}
+
+impl Compile for FPToUI<VarGen, VarGen> {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let out = &self.inner1;
+        let value = &self.inner2;
+
+        if out.typ.float() || !value.typ.float() {
+            return Err(invalid_cast("fptoui", out, value));
+        }
+
+        let dst = full(out.reg);
+
+        if out.typ.size() < 8 {
+            // every unsigned value of the type fits into a signed 64 bit integer
+            compile_cvttsi(asm, dst, value.reg, value.typ)?;
+
+            return Ok(());
+        }
+
+        let tmp = asm.call.tmp_reg();
+        let tmpf = asm.call.tmpf_reg();
+
+        // 2^63
+        let (bits, mov, cmp, sub) = match value.typ {
+            Type::f64 => (0x43E0000000000000_u64, Code::Movq_xmm_rm64, Code::Ucomisd_xmm_xmmm64, Code::Subsd_xmm_xmmm64),
+            _ => (0x5F000000_u64, Code::Movd_xmm_rm32, Code::Ucomiss_xmm_xmmm32, Code::Subss_xmm_xmmm32),
+        };
+
+        let tmp_mov = if value.typ == Type::f64 { tmp } else { sized(tmp, 4) };
+
+        asm.asm.add_instruction(Instruction::with2(Code::Mov_r64_imm64, tmp, bits)?)?;
+        asm.asm.add_instruction(Instruction::with2(mov, tmpf, tmp_mov)?)?;
+
+        let mut big = asm.asm.create_label();
+        let mut done = asm.asm.create_label();
+
+        asm.asm.add_instruction(Instruction::with2(cmp, value.reg, tmpf)?)?;
+        asm.asm.jae(big)?;
+        compile_cvttsi(asm, dst, value.reg, value.typ)?;
+        asm.asm.jmp(done)?;
+
+        // values above 2^63 are converted as `-(2^63 - value)` and the highest bit is set afterwards
+        asm.asm.set_label(&mut big)?;
+        asm.asm.add_instruction(Instruction::with2(sub, tmpf, value.reg)?)?;
+        compile_cvttsi(asm, dst, tmpf, value.typ)?;
+        asm.asm.add_instruction(Instruction::with1(Code::Neg_rm64, dst)?)?;
+        asm.asm.add_instruction(Instruction::with2(Code::Btc_rm64_imm8, dst, 63)?)?;
+
+        asm.asm.set_label(&mut done)?;
+        asm.asm.zero_bytes()?;
+
+        Ok(())
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.inner1.reg)
+    }
+
+    fn display(&self) -> String {
+        display_cast("fptoui", &self.inner1, &self.inner2)
+    }
+}
+
+impl Compile for FPExt<VarGen, VarGen> {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let out = &self.inner1;
+        let value = &self.inner2;
+
+        if out.typ != Type::f64 || value.typ != Type::f32 {
+            return Err(invalid_cast("fpext", out, value));
+        }
+
+        asm.asm.add_instruction(Instruction::with2(Code::Cvtss2sd_xmm_xmmm32, out.reg, value.reg)?)?;
+
+        Ok(())
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.inner1.reg)
+    }
+
+    fn display(&self) -> String {
+        display_cast("fpext", &self.inner1, &self.inner2)
+    }
+}
+
+impl Compile for FPTrunc<VarGen, VarGen> {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let out = &self.inner1;
+        let value = &self.inner2;
+
+        if out.typ != Type::f32 || value.typ != Type::f64 {
+            return Err(invalid_cast("fptrunc", out, value));
+        }
+
+        asm.asm.add_instruction(Instruction::with2(Code::Cvtsd2ss_xmm_xmmm64, out.reg, value.reg)?)?;
+
+        Ok(())
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.inner1.reg)
+    }
+
+    fn display(&self) -> String {
+        display_cast("fptrunc", &self.inner1, &self.inner2)
+    }
+}
+
+impl Compile for Bitcast<VarGen, VarGen> {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let out = &self.inner1;
+        let value = &self.inner2;
+
+        let size = out.typ.size();
+
+        if size != value.typ.size() {
+            return Err(invalid_cast("bitcast", out, value));
+        }
+
+        let (code, dst, src) = match (out.typ.float(), value.typ.float()) {
+            (true, true) => (Code::Movaps_xmm_xmmm128, out.reg, value.reg),
+            (true, false) if size == 8 => (Code::Movq_xmm_rm64, out.reg, value.reg),
+            (true, false) => (Code::Movd_xmm_rm32, out.reg, value.reg),
+            (false, true) if size == 8 => (Code::Movq_rm64_xmm, out.reg, value.reg),
+            (false, true) => (Code::Movd_rm32_xmm, out.reg, value.reg),
+            (false, false) => (Code::Mov_r64_rm64, full(out.reg), full(value.reg)),
+        };
+
+        if dst != src {
+            asm.asm.add_instruction(Instruction::with2(code, dst, src)?)?;
+        }
+
+        Ok(())
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.inner1.reg)
+    }
+
+    fn display(&self) -> String {
+        display_cast("bitcast", &self.inner1, &self.inner2)
+    }
+}
+
+/// Emits an integer division of `target` by `src`
+/// 
+/// The quotient (or the remainder if `rem` is set) is stored in `target`.
+/// RAX and RDX are saved if they don't hold the target
+fn compile_int_div(asm: &mut AsmFunction, target: &VarGen, src: &VarGen, rem: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let size = target.typ.size();
+    let signed = target.typ.signed();
+
+    let rax = sized(Register::RAX, size);
+    let rdx = sized(Register::RDX, size);
+
+    let mut src_reg = src.reg;
+
+    if full(src_reg) == Register::RAX || full(src_reg) == Register::RDX {
+        let tmp = asm.call.tmp_reg();
+        asm.asm.add_instruction(Instruction::with2(Code::Mov_rm64_r64, tmp, full(src_reg))?)?;
+        src_reg = sized(tmp, size);
+    }
+
+    let save_rax = full(target.reg) != Register::RAX;
+    let save_rdx = full(target.reg) != Register::RDX && size != 1; // 8bit division only uses ax
+
+    if save_rax { asm.asm.add_instruction(Instruction::with1(Code::Push_r64, Register::RAX)?)?; }
+    if save_rdx { asm.asm.add_instruction(Instruction::with1(Code::Push_r64, Register::RDX)?)?; }
+
+    if target.reg != rax {
+        let mov = match size {
+            8 => Code::Mov_rm64_r64,
+            4 => Code::Mov_rm32_r32,
+            2 => Code::Mov_rm16_r16,
+            _ => Code::Mov_rm8_r8,
+        };
+        asm.asm.add_instruction(Instruction::with2(mov, rax, target.reg)?)?;
+    }
+
+    if signed {
+        let ext = match size {
+            8 => Code::Cqo,
+            4 => Code::Cdq,
+            2 => Code::Cwd,
+            _ => Code::Cbw,
+        };
+        asm.asm.add_instruction(Instruction::with(ext))?;
+    } else if size == 1 {
+        asm.asm.add_instruction(Instruction::with2(Code::Movzx_r32_rm8, Register::EAX, Register::AL)?)?;
+    } else {
+        asm.asm.add_instruction(Instruction::with2(Code::Xor_rm32_r32, Register::EDX, Register::EDX)?)?;
+    }
+
+    let div = match (size, signed) {
+        (8, true) => Code::Idiv_rm64,
+        (4, true) => Code::Idiv_rm32,
+        (2, true) => Code::Idiv_rm16,
+        (_, true) => Code::Idiv_rm8,
+        (8, false) => Code::Div_rm64,
+        (4, false) => Code::Div_rm32,
+        (2, false) => Code::Div_rm16,
+        (_, false) => Code::Div_rm8,
+    };
+    asm.asm.add_instruction(Instruction::with1(div, src_reg)?)?;
+
+    let out = if rem && size == 1 {
+        // remainder is stored in ah
+        asm.asm.add_instruction(Instruction::with2(Code::Shr_rm16_imm8, Register::AX, 8)?)?;
+        Register::AL
+    } else if rem {
+        rdx
+    } else {
+        rax
+    };
+
+    if out != target.reg {
+        let mov = match size {
+            8 => Code::Mov_rm64_r64,
+            4 => Code::Mov_rm32_r32,
+            2 => Code::Mov_rm16_r16,
+            _ => Code::Mov_rm8_r8,
+        };
+        asm.asm.add_instruction(Instruction::with2(mov, target.reg, out)?)?;
+    }
+
+    if save_rdx { asm.asm.add_instruction(Instruction::with1(Code::Pop_r64, Register::RDX)?)?; }
+    if save_rax { asm.asm.add_instruction(Instruction::with1(Code::Pop_r64, Register::RAX)?)?; }
+
+    Ok(())
+}
+
+impl Compile for Div<VarGen, VarGen> {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let target = &self.inner1;
+        let src = &self.inner2;
+
+        check_regs("Div", &[target, src])?;
+
+        if target.typ.vector() {
+            return compile_vector_math(asm, "Div", target, src);
+        }
+
+        if target.reg.is_xmm() {
+            let code = if target.typ == Type::f64 { Code::Divsd_xmm_xmmm64 } else { Code::Divss_xmm_xmmm32 };
+
+            asm.asm.add_instruction(
+                Instruction::with2(code, target.reg, src.reg)?
+            )?;
+        } else {
+            compile_int_div(asm, target, src, false)?;
+        }
+
+        Ok(())
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.inner1.reg)
+    }
+
+    fn display(&self) -> String {
+        display_math("Div", &self.inner1, self.inner2.to_string())
+    }
+}
+
+impl Compile for Rem<VarGen, VarGen> {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let target = &self.inner1;
+        let src = &self.inner2;
+
+        check_regs("Rem", &[target, src])?;
+
+        if target.typ.vector() {
+            return Err(Box::from(error::IrError::UnsupportedType(format!("{} (in Rem)", vector_name(&target.typ)))));
+        }
+
+        if target.reg.is_xmm() {
+            // x - trunc(x / y) * y
+            let tmp = asm.call.tmpf_reg();
+            let int = asm.call.tmp_reg();
+
+            let (div, trunc, conv, mul, sub) = if target.typ == Type::f64 {
+                (Code::Divsd_xmm_xmmm64, Code::Cvttsd2si_r64_xmmm64, Code::Cvtsi2sd_xmm_rm64, Code::Mulsd_xmm_xmmm64, Code::Subsd_xmm_xmmm64)
+            } else {
+                (Code::Divss_xmm_xmmm32, Code::Cvttss2si_r64_xmmm32, Code::Cvtsi2ss_xmm_rm64, Code::Mulss_xmm_xmmm32, Code::Subss_xmm_xmmm32)
+            };
+
+            let mut integral = asm.asm.create_label();
+
+            asm.asm.add_instruction(Instruction::with2(Code::Movaps_xmm_xmmm128, tmp, target.reg)?)?;
+            asm.asm.add_instruction(Instruction::with2(div, tmp, src.reg)?)?;
+
+            // truncated by converting to an integer and back (only needs SSE2), quotients out of the i64 range
+            // (and NaN) convert to i64::MIN, they are already integral, so they are kept (`cmp i64::MIN, 1` overflows)
+            asm.asm.add_instruction(Instruction::with2(trunc, int, tmp)?)?;
+            asm.asm.add_instruction(Instruction::with2(Code::Cmp_rm64_imm8, int, 1)?)?;
+            asm.asm.jo(integral)?;
+            asm.asm.add_instruction(Instruction::with2(conv, tmp, int)?)?;
+            asm.asm.set_label(&mut integral)?;
+            asm.asm.zero_bytes()?;
+
+            asm.asm.add_instruction(Instruction::with2(mul, tmp, src.reg)?)?;
+            asm.asm.add_instruction(Instruction::with2(sub, target.reg, tmp)?)?;
+        } else {
+            compile_int_div(asm, target, src, true)?;
+        }
+
+        Ok(())
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.inner1.reg)
+    }
+
+    fn display(&self) -> String {
+        display_math("Rem", &self.inner1, self.inner2.to_string())
+    }
+}
+
+impl Compile for Return<i32> {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        asm.asm.mov(asm.call.ret32(), self.inner1)?;
+        asm.asm.jmp(asm.exit())?;
+        
+        Ok(())
+    }
+
+    fn display(&self) -> String {
+        format!("ret i32 {}", self.inner1)
+    }
+}
+
+impl Compile for Return<i64> {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        asm.asm.mov(asm.call.ret64(), self.inner1)?;
+        asm.asm.jmp(asm.exit())?;
+
+        Ok(())
+    }
+
+    fn display(&self) -> String {
+        format!("ret i64 {}", self.inner1)
+    }
+}
+
+impl Compile for Return<f32> {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let mem = MemoryOperand::new(Register::RIP, Register::None, 1, 0, 1, false, Register::None);
+        let instr = Instruction::with2(Code::Movss_xmm_xmmm32, asm.call.retf_reg(), mem)?;
+
+        asm.asm.add_instruction(instr)?;
+
+        let req = asm.req_name();
+        asm.reloc_at_current_pos(&req, -4, 4)?;
+        asm.data.insert(req, self.inner1.to_le_bytes().into());
+
+        asm.asm.jmp(asm.exit())?;
+
+        Ok(())
+    }
+
+    fn display(&self) -> String {
+        format!("ret f32 {:?}", self.inner1)
+    }
+}
+
+impl Compile for Return<f64> {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let mem = MemoryOperand::new(Register::RIP, Register::None, 1, 0, 1, false, Register::None);
+        let instr = Instruction::with2(Code::Movsd_xmm_xmmm64, asm.call.retf_reg(), mem)?;
+
+        asm.asm.add_instruction(instr)?;
+
+        let req = asm.req_name();
+        asm.reloc_at_current_pos(&req, -4, 4)?;
+        asm.data.insert(req, self.inner1.to_le_bytes().into());
+
+        asm.asm.jmp(asm.exit())?;
+        
+        Ok(())
+    }
+
+    fn display(&self) -> String {
+        format!("ret f64 {:?}", self.inner1)
+    }
+}
+
+/// Moves the value of the register into the return register
+fn compile_ret_reg(asm: &mut AsmFunction, reg: Register) -> Result<(), Box<dyn std::error::Error>> {
+    let (code, ret) = {
+        if reg.is_gpr64() {
+            (Code::Mov_rm64_r64, asm.call.ret64_reg())
+        } else if reg.is_gpr32() {
+            (Code::Mov_rm32_r32, asm.call.ret32_reg())
+        } else if reg.is_gpr16() {
+            (Code::Mov_rm16_r16, asm.call.ret16_reg())
+        } else if reg.is_gpr8() {
+            (Code::Mov_rm8_r8, asm.call.ret8_reg())
+        } else if reg.is_xmm() {
+            (Code::Movaps_xmm_xmmm128, asm.call.retf_reg())
+        } else if reg.is_ymm() {
+            (Code::VEX_Vmovaps_ymm_ymmm256, ymm(asm.call.retf_reg()))
+        } else {
+            return Ok(());
+        }
+    };
+
+    if reg != ret {
+        asm.asm.add_instruction(Instruction::with2(code, ret, reg)?)?;
+    }
+
+    Ok(())
+}
+
+/// Loads the 128 bit value into the return registers (RDX:RAX, on windows XMM0)
+fn compile_ret_wide(asm: &mut AsmFunction, value: &VarGen) -> Result<(), Box<dyn std::error::Error>> {
+    if asm.call.conv() == CallingConvention::WindowsFastcall {
+        asm.asm.add_instruction(Instruction::with2(Code::Movdqu_xmm_xmmm128, asm.call.retf_reg(), stack_mem(value))?)?;
+    } else {
+        asm.asm.add_instruction(Instruction::with2(Code::Mov_r64_rm64, asm.call.ret64_reg(), stack_mem(value))?)?;
+        asm.asm.add_instruction(Instruction::with2(Code::Mov_r64_rm64, Register::RDX, stack_mem_hi(value))?)?;
+    }
+
+    Ok(())
+}
+
+/// Stores the returned 128 bit value into the stack slot of `out`
+fn compile_store_wide(asm: &mut AsmFunction, out: &VarGen) -> Result<(), Box<dyn std::error::Error>> {
+    if asm.call.conv() == CallingConvention::WindowsFastcall {
+        asm.asm.add_instruction(Instruction::with2(Code::Movdqu_xmmm128_xmm, stack_mem(out), asm.call.retf_reg())?)?;
+    } else {
+        asm.asm.add_instruction(Instruction::with2(Code::Mov_rm64_r64, stack_mem(out), asm.call.ret64_reg())?)?;
+        asm.asm.add_instruction(Instruction::with2(Code::Mov_rm64_r64, stack_mem_hi(out), Register::RDX)?)?;
+    }
+
+    Ok(())
+}
+
+impl Compile for Return<VarGen> {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        if self.inner1.typ.wide() {
+            compile_ret_wide(asm, &self.inner1)?;
+        } else {
+            compile_ret_reg(asm, self.inner1.reg)?;
+        }
+
+        asm.asm.jmp(asm.exit())?;
+
+        Ok(())
+    }
+
+    fn display(&self) -> String {
+        format!("ret {} {}", self.inner1.typ, self.inner1)
+    }
+}
+
+/// Returns from a function without a return value (`Type::void`)
+impl Compile for Return<()> {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        asm.asm.jmp(asm.exit())?;
+
+        Ok(())
+    }
+
+    fn display(&self) -> String {
+        "ret void".into()
+    }
+}
+
+impl Compile for Return<Call> {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        self.inner1.compile(asm)?;
+
+        match self.inner1.out {
+            Some(out) if out.typ.wide() => compile_ret_wide(asm, &out)?,
+            Some(out) => compile_ret_reg(asm, out.reg)?,
+            None => {},
+        }
+
+        asm.asm.jmp(asm.exit())?;
+
+        Ok(())
+    }
+
+    fn display(&self) -> String {
+        match self.inner1.out {
+            Some(out) => format!("{}\nret {} {}", self.inner1.display(), out.typ, out),
+            None => format!("{}\nret void", self.inner1.display()),
+        }
+    }
+}
+
+macro_rules! ExprReturn {
+    ($name:tt) => {
+        impl<T, U> Compile for Return<$name<T, U>> where $name<T, U>: Compile {
+            fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+                self.inner1.compile(asm)?;
+                let reg = self.inner1.out_reg().unwrap(); // Implemented for add so it won't panic
+        
+                compile_ret_reg(asm, reg)?;
+                asm.asm.jmp(asm.exit())?;
+
+                Ok(())
+            }
+            
+            fn out_reg(&self) -> Option<Register> {
+                None
+            }
+
+            fn display(&self) -> String {
+                display_expr_return(&self.inner1)
+            }
+        }
+    };
+}
+
+macro_rules! ExprReturn1 {
+    ($name:tt) => {
+        impl<T> Compile for Return<$name<T>> where $name<T>: Compile {
+            fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+                self.inner1.compile(asm)?;
+                let reg = self.inner1.out_reg().unwrap();
+        
+                compile_ret_reg(asm, reg)?;
+                asm.asm.jmp(asm.exit())?;
+
+                Ok(())
+            }
+            
+            fn out_reg(&self) -> Option<Register> {
+                None
+            }
+
+            fn display(&self) -> String {
+                display_expr_return(&self.inner1)
+            }
+        }
+    };
+}
+
+ExprReturn!(Add);
+ExprReturn!(Sub);
+ExprReturn!(Mul);
+ExprReturn!(Div);
+ExprReturn!(Rem);
+ExprReturn!(ICmp);
+ExprReturn!(FCmp);
+ExprReturn!(And);
+ExprReturn!(Or);
+ExprReturn!(Xor);
+ExprReturn!(Shl);
+ExprReturn!(LShr);
+ExprReturn!(AShr);
+ExprReturn1!(Not);
+ExprReturn1!(Neg);
+ExprReturn!(Load);
+ExprReturn!(ZExt);
+ExprReturn!(SExt);
+ExprReturn!(Trunc);
+ExprReturn!(SIToFP);
+ExprReturn!(UIToFP);
+ExprReturn!(FPToSI);
+ExprReturn!(FPToUI);
+ExprReturn!(FPExt);
+ExprReturn!(FPTrunc);
+ExprReturn!(Bitcast);
+/// Returns the name of a vector type like `<4 x f32>` (used for errors)
+fn vector_name(typ: &Type) -> String {
+    match typ {
+        Type::vector(elem, lanes) => format!("<{} x {}>", lanes, elem.name()),
+        _ => typ.name().to_string(),
+    }
+}
+
+/// Returns the instruction of the element-wise operation (`ir`) for vectors with the given element type
+/// 
+/// 128 bit vectors use SSE (`op xmm, xmm`), 256 bit vectors AVX (`vop ymm, ymm, ymm`)
+fn vector_code(ir: &str, elem: Type, wide: bool) -> Option<Code> {
+    let kind = match elem {
+        Type::f32 => "ps",
+        Type::f64 => "pd",
+        _ => match elem.size() {
+            1 => "b",
+            2 => "w",
+            4 => "d",
+            _ => "q",
+        },
+    };
+
+    let code = match (ir, kind, wide) {
+        ("Add", "ps", false) => Code::Addps_xmm_xmmm128,
+        ("Add", "pd", false) => Code::Addpd_xmm_xmmm128,
+        ("Add", "b", false) => Code::Paddb_xmm_xmmm128,
+        ("Add", "w", false) => Code::Paddw_xmm_xmmm128,
+        ("Add", "d", false) => Code::Paddd_xmm_xmmm128,
+        ("Add", "q", false) => Code::Paddq_xmm_xmmm128,
+        ("Sub", "ps", false) => Code::Subps_xmm_xmmm128,
+        ("Sub", "pd", false) => Code::Subpd_xmm_xmmm128,
+        ("Sub", "b", false) => Code::Psubb_xmm_xmmm128,
+        ("Sub", "w", false) => Code::Psubw_xmm_xmmm128,
+        ("Sub", "d", false) => Code::Psubd_xmm_xmmm128,
+        ("Sub", "q", false) => Code::Psubq_xmm_xmmm128,
+        ("Mul", "ps", false) => Code::Mulps_xmm_xmmm128,
+        ("Mul", "pd", false) => Code::Mulpd_xmm_xmmm128,
+        ("Mul", "w", false) => Code::Pmullw_xmm_xmmm128,
+        ("Mul", "d", false) => Code::Pmulld_xmm_xmmm128,
+        ("Div", "ps", false) => Code::Divps_xmm_xmmm128,
+        ("Div", "pd", false) => Code::Divpd_xmm_xmmm128,
+        ("And", "ps", false) => Code::Andps_xmm_xmmm128,
+        ("And", "pd", false) => Code::Andpd_xmm_xmmm128,
+        ("And", _, false) => Code::Pand_xmm_xmmm128,
+        ("Or", "ps", false) => Code::Orps_xmm_xmmm128,
+        ("Or", "pd", false) => Code::Orpd_xmm_xmmm128,
+        ("Or", _, false) => Code::Por_xmm_xmmm128,
+        ("Xor", "ps", false) => Code::Xorps_xmm_xmmm128,
+        ("Xor", "pd", false) => Code::Xorpd_xmm_xmmm128,
+        ("Xor", _, false) => Code::Pxor_xmm_xmmm128,
+
+        ("Add", "ps", true) => Code::VEX_Vaddps_ymm_ymm_ymmm256,
+        ("Add", "pd", true) => Code::VEX_Vaddpd_ymm_ymm_ymmm256,
+        ("Add", "b", true) => Code::VEX_Vpaddb_ymm_ymm_ymmm256,
+        ("Add", "w", true) => Code::VEX_Vpaddw_ymm_ymm_ymmm256,
+        ("Add", "d", true) => Code::VEX_Vpaddd_ymm_ymm_ymmm256,
+        ("Add", "q", true) => Code::VEX_Vpaddq_ymm_ymm_ymmm256,
+        ("Sub", "ps", true) => Code::VEX_Vsubps_ymm_ymm_ymmm256,
+        ("Sub", "pd", true) => Code::VEX_Vsubpd_ymm_ymm_ymmm256,
+        ("Sub", "b", true) => Code::VEX_Vpsubb_ymm_ymm_ymmm256,
+        ("Sub", "w", true) => Code::VEX_Vpsubw_ymm_ymm_ymmm256,
+        ("Sub", "d", true) => Code::VEX_Vpsubd_ymm_ymm_ymmm256,
+        ("Sub", "q", true) => Code::VEX_Vpsubq_ymm_ymm_ymmm256,
+        ("Mul", "ps", true) => Code::VEX_Vmulps_ymm_ymm_ymmm256,
+        ("Mul", "pd", true) => Code::VEX_Vmulpd_ymm_ymm_ymmm256,
+        ("Mul", "w", true) => Code::VEX_Vpmullw_ymm_ymm_ymmm256,
+        ("Mul", "d", true) => Code::VEX_Vpmulld_ymm_ymm_ymmm256,
+        ("Div", "ps", true) => Code::VEX_Vdivps_ymm_ymm_ymmm256,
+        ("Div", "pd", true) => Code::VEX_Vdivpd_ymm_ymm_ymmm256,
+        ("And", "ps", true) => Code::VEX_Vandps_ymm_ymm_ymmm256,
+        ("And", "pd", true) => Code::VEX_Vandpd_ymm_ymm_ymmm256,
+        ("And", _, true) => Code::VEX_Vpand_ymm_ymm_ymmm256,
+        ("Or", "ps", true) => Code::VEX_Vorps_ymm_ymm_ymmm256,
+        ("Or", "pd", true) => Code::VEX_Vorpd_ymm_ymm_ymmm256,
+        ("Or", _, true) => Code::VEX_Vpor_ymm_ymm_ymmm256,
+        ("Xor", "ps", true) => Code::VEX_Vxorps_ymm_ymm_ymmm256,
+        ("Xor", "pd", true) => Code::VEX_Vxorpd_ymm_ymm_ymmm256,
+        ("Xor", _, true) => Code::VEX_Vpxor_ymm_ymm_ymmm256,
+
+        _ => return None,
+    };
+
+    Some(code)
+}
+
+/// Compiles the element-wise operation `target = target (ir) src` for vectors
+/// 
+/// Integer vectors with 256 bits need AVX2
+fn compile_vector_math(asm: &mut AsmFunction, ir: &str, target: &VarGen, src: &VarGen) -> Result<(), Box<dyn std::error::Error>> {
+    let elem = target.typ.field(0).unwrap_or(target.typ);
+    let wide = target.reg.is_ymm();
+
+    let code = match vector_code(ir, elem, wide) {
+        Some(code) => code,
+        None => return Err(Box::from(error::IrError::UnsupportedType(format!("{} (in {})", vector_name(&target.typ), ir)))),
+    };
+
+    if wide {
+        asm.asm.add_instruction(Instruction::with3(code, target.reg, target.reg, src.reg)?)?;
+    } else {
+        asm.asm.add_instruction(Instruction::with2(code, target.reg, src.reg)?)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the unsigned integer type with the given size (used to move lanes through general purpose registers)
+fn int_of_size(size: usize) -> Type {
+    match size {
+        1 => Type::u8,
+        2 => Type::u16,
+        4 => Type::u32,
+        _ => Type::u64,
+    }
+}
+
+/// Returns the error for a lane which isn't part of the vector
+fn invalid_lane(index: usize, vector: &VarGen) -> Box<dyn std::error::Error> {
+    Box::from(error::IrError::InvalidLane(index, vector_name(&vector.typ)))
+}
+
+impl Compile for Shuffle {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let lanes = self.a.typ.size() / self.a.typ.field(0).unwrap_or(self.a.typ).size();
+        let elem = self.out.typ.field(0).unwrap_or(self.out.typ);
+        let size = self.out.typ.size();
+
+        if let Some(index) = self.mask.iter().find(|index| **index >= 2 * lanes) {
+            return Err(invalid_lane(*index, &self.a));
+        }
+
+        if self.mask.len() * elem.size() != size {
+            return Err(Box::from(error::IrError::UnsupportedType(vector_name(&self.out.typ))));
+        }
+
+        // only lanes of `a` with 32 or 64 bit elements: one pshufd
+        if size == 16 && self.a.typ.size() == 16 && self.mask.iter().all(|index| *index < lanes) && elem.size() >= 4 {
+            let dwords: Vec<usize> = match elem.size() {
+                4 => self.mask.clone(),
+                _ => self.mask.iter().flat_map(|index| [2 * index, 2 * index + 1]).collect(),
+            };
+
+            let imm = dwords.iter().enumerate().fold(0, |imm, (pos, dword)| imm | (dword << (2 * pos)));
+
+            asm.asm.add_instruction(Instruction::with3(Code::Pshufd_xmm_xmmm128_imm8, self.out.reg, self.a.reg, imm as i32)?)?;
+
+            return Ok(());
+        }
+
+        // else the lanes are copied through the stack
+        let input = self.a.typ.size();
+        let frame = (2 * input + size).next_multiple_of(16);
+        let tmp = sized(asm.call.tmp_reg(), elem.size());
+        let typ = int_of_size(elem.size());
+
+        asm.asm.add_instruction(Instruction::with2(Code::Sub_rm64_imm32, Register::RSP, frame as i32)?)?;
+
+        compile_store(asm, rsp_mem(0), self.a.reg, self.a.typ)?;
+        compile_store(asm, rsp_mem(input), self.b.reg, self.b.typ)?;
+
+        for (pos, index) in self.mask.iter().enumerate() {
+            compile_load(asm, tmp, typ, rsp_mem(index * elem.size()))?;
+            compile_store(asm, rsp_mem(2 * input + pos * elem.size()), tmp, typ)?;
+        }
+
+        compile_load(asm, self.out.reg, self.out.typ, rsp_mem(2 * input))?;
+
+        asm.asm.add_instruction(Instruction::with2(Code::Add_rm64_imm32, Register::RSP, frame as i32)?)?;
+
+        Ok(())
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.out.reg)
+    }
+
+    fn display(&self) -> String {
+        format!("{} = shufflevector {} {}, {} {}, {:?}", self.out, self.a.typ, self.a, self.b.typ, self.b, self.mask)
+    }
+}
+
+/// Returns the lanes per 128 bit half of the vector and the xmm register which holds the lane
+/// 
+/// Lanes in the upper half of a ymm register are extracted into the float scratch register first
+fn compile_vector_half(asm: &mut AsmFunction, vector: &VarGen, index: usize) -> Result<(usize, Register), Box<dyn std::error::Error>> {
+    let elem = vector.typ.field(0).unwrap_or(vector.typ);
+    let half = 16 / elem.size();
+
+    if index >= half {
+        let tmp = asm.call.tmpf_reg();
+        asm.asm.add_instruction(Instruction::with3(Code::VEX_Vextractf128_xmmm128_ymm_imm8, tmp, vector.reg, 1)?)?;
+
+        return Ok((index - half, tmp));
+    }
+
+    Ok((index, full(vector.reg)))
+}
+
+impl Compile for ExtractElement {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let elem = self.vector.typ.field(self.index).ok_or_else(|| invalid_lane(self.index, &self.vector))?;
+
+        let (index, src) = compile_vector_half(asm, &self.vector, self.index)?;
+        let index = index as i32;
+
+        match elem {
+            Type::f32 => {
+                asm.asm.add_instruction(Instruction::with3(Code::Pshufd_xmm_xmmm128_imm8, self.out.reg, src, index * 0x55)?)?;
+            },
+            Type::f64 => {
+                asm.asm.add_instruction(Instruction::with3(Code::Pshufd_xmm_xmmm128_imm8, self.out.reg, src, if index == 0 { 0x44 } else { 0xEE })?)?;
+            },
+            _ => {
+                let (code, out) = match elem.size() {
+                    1 => (Code::Pextrb_r32m8_xmm_imm8, sized(self.out.reg, 4)),
+                    2 => (Code::Pextrw_r32_xmm_imm8, sized(self.out.reg, 4)),
+                    4 => (Code::Pextrd_rm32_xmm_imm8, sized(self.out.reg, 4)),
+                    _ => (Code::Pextrq_rm64_xmm_imm8, sized(self.out.reg, 8)),
+                };
+
+                asm.asm.add_instruction(Instruction::with3(code, out, src, index)?)?;
+            },
+        }
+
+        Ok(())
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.out.reg)
+    }
+
+    fn display(&self) -> String {
+        format!("{} = extractelement {} {}, {}", self.out, self.vector.typ, self.vector, self.index)
+    }
+}
+
+impl Compile for InsertElement {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let elem = self.vector.typ.field(self.index).ok_or_else(|| invalid_lane(self.index, &self.vector))?;
+
+        let (index, dst) = compile_vector_half(asm, &self.vector, self.index)?;
+        let value = self.value.reg;
+
+        // the sse instructions keep the upper half of ymm registers
+        match elem {
+            Type::f32 => {
+                asm.asm.add_instruction(Instruction::with3(Code::Insertps_xmm_xmmm32_imm8, dst, value, (index << 4) as i32)?)?;
+            },
+            Type::f64 if index == 0 => {
+                asm.asm.add_instruction(Instruction::with2(Code::Movsd_xmm_xmmm64, dst, value)?)?;
+            },
+            Type::f64 => {
+                asm.asm.add_instruction(Instruction::with2(Code::Unpcklpd_xmm_xmmm128, dst, value)?)?;
+            },
+            _ => {
+                let (code, value) = match elem.size() {
+                    1 => (Code::Pinsrb_xmm_r32m8_imm8, sized(value, 4)),
+                    2 => (Code::Pinsrw_xmm_r32m16_imm8, sized(value, 4)),
+                    4 => (Code::Pinsrd_xmm_rm32_imm8, sized(value, 4)),
+                    _ => (Code::Pinsrq_xmm_rm64_imm8, sized(value, 8)),
+                };
+
+                asm.asm.add_instruction(Instruction::with3(code, dst, value, index as i32)?)?;
+            },
+        }
+
+        if dst != full(self.vector.reg) {
+            asm.asm.add_instruction(Instruction::with4(Code::VEX_Vinsertf128_ymm_ymm_xmmm128_imm8, self.vector.reg, self.vector.reg, dst, 1)?)?;
+        }
+
+        Ok(())
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.vector.reg)
+    }
+
+    fn display(&self) -> String {
+        format!("{0} = insertelement {1} {0}, {2} {3}, {4}", self.vector, self.vector.typ, self.value.typ, self.value, self.index)
+    }
+}
+
+impl Compile for Reduce {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let elem = self.vector.typ.field(0).unwrap_or(self.vector.typ);
+        let lanes = self.vector.typ.size() / elem.size();
+        let size = elem.size();
+
+        let unsupported = || Box::from(error::IrError::UnsupportedType(format!("{} (in {:?} reduction)", vector_name(&self.vector.typ), self.op)));
+
+        // the lanes are combined one after another from the stack
+        let frame = self.vector.typ.size().next_multiple_of(16);
+
+        asm.asm.add_instruction(Instruction::with2(Code::Sub_rm64_imm32, Register::RSP, frame as i32)?)?;
+        compile_store(asm, rsp_mem(0), self.vector.reg, self.vector.typ)?;
+
+        compile_load(asm, self.out.reg, elem, rsp_mem(0))?;
+
+        for lane in 1..lanes {
+            let mem = rsp_mem(lane * size);
+
+            if elem.float() {
+                let code = match (self.op, elem) {
+                    (ReduceOp::Add, Type::f64) => Code::Addsd_xmm_xmmm64,
+                    (ReduceOp::Add, _) => Code::Addss_xmm_xmmm32,
+                    (ReduceOp::Mul, Type::f64) => Code::Mulsd_xmm_xmmm64,
+                    (ReduceOp::Mul, _) => Code::Mulss_xmm_xmmm32,
+                    (ReduceOp::Min, Type::f64) => Code::Minsd_xmm_xmmm64,
+                    (ReduceOp::Min, _) => Code::Minss_xmm_xmmm32,
+                    (ReduceOp::Max, Type::f64) => Code::Maxsd_xmm_xmmm64,
+                    (ReduceOp::Max, _) => Code::Maxss_xmm_xmmm32,
+                    _ => return Err(unsupported()),
+                };
+
+                asm.asm.add_instruction(Instruction::with2(code, self.out.reg, mem)?)?;
+                continue;
+            }
+
+            // the lane is loaded into the scratch register (extended to at least 32 bits)
+            let tmp = asm.call.tmp_reg();
+            let wide = if size == 8 { 8 } else { 4 };
+
+            if elem.signed() {
+                compile_sext_mem(asm, tmp, size, mem)?;
+            } else {
+                compile_zext_mem(asm, tmp, size, mem)?;
+            }
+
+            let out = sized(self.out.reg, wide);
+            let tmp = sized(tmp, wide);
+
+            let code = match (self.op, wide) {
+                (ReduceOp::Add, 8) => Code::Add_r64_rm64,
+                (ReduceOp::Add, _) => Code::Add_r32_rm32,
+                (ReduceOp::Mul, 8) => Code::Imul_r64_rm64,
+                (ReduceOp::Mul, _) => Code::Imul_r32_rm32,
+                (ReduceOp::And, 8) => Code::And_r64_rm64,
+                (ReduceOp::And, _) => Code::And_r32_rm32,
+                (ReduceOp::Or, 8) => Code::Or_r64_rm64,
+                (ReduceOp::Or, _) => Code::Or_r32_rm32,
+                (ReduceOp::Xor, 8) => Code::Xor_r64_rm64,
+                (ReduceOp::Xor, _) => Code::Xor_r32_rm32,
+                (ReduceOp::Min | ReduceOp::Max, _) => {
+                    // the out register is compared in the size of the lanes
+                    let cmp = match size {
+                        1 => Code::Cmp_r8_rm8,
+                        2 => Code::Cmp_r16_rm16,
+                        4 => Code::Cmp_r32_rm32,
+                        _ => Code::Cmp_r64_rm64,
+                    };
+
+                    asm.asm.add_instruction(Instruction::with2(cmp, sized(out, size), sized(tmp, size))?)?;
+
+                    // takes the lane if the current value is bigger (min) or smaller (max)
+                    match (self.op, elem.signed(), wide) {
+                        (ReduceOp::Min, true, 8) => Code::Cmovg_r64_rm64,
+                        (ReduceOp::Min, true, _) => Code::Cmovg_r32_rm32,
+                        (ReduceOp::Min, false, 8) => Code::Cmova_r64_rm64,
+                        (ReduceOp::Min, false, _) => Code::Cmova_r32_rm32,
+                        (_, true, 8) => Code::Cmovl_r64_rm64,
+                        (_, true, _) => Code::Cmovl_r32_rm32,
+                        (_, false, 8) => Code::Cmovb_r64_rm64,
+                        (_, false, _) => Code::Cmovb_r32_rm32,
+                    }
+                },
+            };
+
+            asm.asm.add_instruction(Instruction::with2(code, out, tmp)?)?;
+        }
+
+        asm.asm.add_instruction(Instruction::with2(Code::Add_rm64_imm32, Register::RSP, frame as i32)?)?;
+
+        Ok(())
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.out.reg)
+    }
+
+    fn display(&self) -> String {
+        let op = format!("{:?}", self.op).to_lowercase();
+
+        format!("{} = reduce.{} {} {}", self.out, op, self.vector.typ, self.vector)
+    }
+}
+
+/// Zero extends the integer with the given size at `mem` into the 64 bit register `dst`
+fn compile_zext_mem(asm: &mut AsmFunction, dst: Register, size: usize, mem: MemoryOperand) -> Result<(), Box<dyn std::error::Error>> {
+    let (code, dst) = match size {
+        1 => (Code::Movzx_r32_rm8, sized(dst, 4)),
+        2 => (Code::Movzx_r32_rm16, sized(dst, 4)),
+        4 => (Code::Mov_r32_rm32, sized(dst, 4)),
+        _ => (Code::Mov_r64_rm64, dst),
+    };
+
+    asm.asm.add_instruction(Instruction::with2(code, dst, mem)?)?;
+
+    Ok(())
+}
+
+/// Sign extends the integer with the given size at `mem` into the 64 bit register `dst`
+fn compile_sext_mem(asm: &mut AsmFunction, dst: Register, size: usize, mem: MemoryOperand) -> Result<(), Box<dyn std::error::Error>> {
+    let code = match size {
+        1 => Code::Movsx_r64_rm8,
+        2 => Code::Movsx_r64_rm16,
+        4 => Code::Movsxd_r64_rm32,
+        _ => Code::Mov_r64_rm64,
+    };
+
+    asm.asm.add_instruction(Instruction::with2(code, dst, mem)?)?;
+
+    Ok(())
+}
+
+/// Returns the error if the value can't be used by atomic operations (only integers and pointers can)
+fn check_atomic(ir: &str, value: &VarGen) -> Result<(), Box<dyn std::error::Error>> {
+    if !value.in_reg || value.typ.float() || value.typ.vector() {
+        return Err(Box::from(error::IrError::UnsupportedType(format!("{} (in {})", value.typ.name(), ir))));
+    }
+
+    Ok(())
+}
+
+/// Returns the `mov r/m, r` instruction for the size
+fn mov_code(size: usize) -> Code {
+    match size {
+        8 => Code::Mov_rm64_r64,
+        4 => Code::Mov_rm32_r32,
+        2 => Code::Mov_rm16_r16,
+        _ => Code::Mov_rm8_r8,
+    }
+}
+
+/// Returns the `cmov` which takes the source after `cmp dst, src` if it is smaller (min) or bigger (max) than the destination
+/// 
+/// There is no 8 or 16 bit `cmov`, so it is used with the 32 bit registers for them
+fn min_max_cmov(min: bool, signed: bool, size: usize) -> Code {
+    match (min, signed, size) {
+        (true, true, 8) => Code::Cmovg_r64_rm64,
+        (true, true, _) => Code::Cmovg_r32_rm32,
+        (true, false, 8) => Code::Cmova_r64_rm64,
+        (true, false, _) => Code::Cmova_r32_rm32,
+        (false, true, 8) => Code::Cmovl_r64_rm64,
+        (false, true, _) => Code::Cmovl_r32_rm32,
+        (false, false, 8) => Code::Cmovb_r64_rm64,
+        (false, false, _) => Code::Cmovb_r32_rm32,
+    }
+}
+
+/// Adds the instruction with a `lock` prefix
+fn compile_locked(asm: &mut AsmFunction, mut instr: Instruction) -> Result<(), Box<dyn std::error::Error>> {
+    instr.set_has_lock_prefix(true);
+    asm.asm.add_instruction(instr)?;
+
+    Ok(())
+}
+
+/// Saves RAX (if it isn't `out`) because it is used by `cmpxchg`
+/// 
+/// Operands which are stored in RAX are moved into another (saved) register which isn't in `avoid`.
+/// Returns the registers of the operands and the registers which need to be restored (in the order they need to be popped)
+fn compile_save_rax(asm: &mut AsmFunction, out: Register, operands: [Register; 2], avoid: &[Register]) -> Result<([Register; 2], Vec<Register>), Box<dyn std::error::Error>> {
+    let mut saved = vec![];
+    let mut regs = operands;
+
+    if full(out) != Register::RAX {
+        asm.asm.add_instruction(Instruction::with1(Code::Push_r64, Register::RAX)?)?;
+        saved.push(Register::RAX);
+    }
+
+    for nr in 0..regs.len() {
+        if full(regs[nr]) != Register::RAX {
+            continue;
+        }
+
+        let used: Vec<Register> = avoid.iter().chain(&regs).chain(&[out, asm.call.tmp_reg()]).map(|reg| full(*reg)).collect();
+
+        let scratch = [Register::RCX, Register::RDX, Register::RSI, Register::RDI, Register::R8, Register::R9, Register::R10].into_iter()
+            .find(|reg| !used.contains(reg))
+            .unwrap(); // at most five registers are used
+
+        asm.asm.add_instruction(Instruction::with1(Code::Push_r64, scratch)?)?;
+        asm.asm.add_instruction(Instruction::with2(Code::Mov_rm64_r64, scratch, Register::RAX)?)?;
+
+        saved.push(scratch);
+        regs[nr] = sized(scratch, regs[nr].size());
+    }
+
+    saved.reverse();
+
+    Ok((regs, saved))
+}
+
+/// Restores the registers which were saved by `compile_save_rax`
+fn compile_restore(asm: &mut AsmFunction, saved: Vec<Register>) -> Result<(), Box<dyn std::error::Error>> {
+    for reg in saved {
+        asm.asm.add_instruction(Instruction::with1(Code::Pop_r64, reg)?)?;
+    }
+
+    Ok(())
+}
+
+impl Compile for AtomicLoad {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        check_atomic("AtomicLoad", &self.out)?;
+
+        // aligned loads are atomic and have acquire semantics on x86
+        compile_load(asm, self.out.reg, self.out.typ, ptr_mem(&self.ptr))
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.out.reg)
+    }
+
+    fn display(&self) -> String {
+        format!("{} = load atomic {}, ptr {} {}", self.out, self.out.typ, self.ptr, display_ordering(self.ordering))
+    }
+}
+
+impl Compile for AtomicStore {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        check_atomic("AtomicStore", &self.value)?;
+
+        if self.ordering != AtomicOrdering::SeqCst {
+            return compile_store(asm, ptr_mem(&self.ptr), self.value.reg, self.value.typ);
+        }
+
+        // xchg is locked, so later loads can't be done before the store
+        let size = self.value.typ.size();
+        let tmp = sized(asm.call.tmp_reg(), size);
+
+        let xchg = match size {
+            8 => Code::Xchg_rm64_r64,
+            4 => Code::Xchg_rm32_r32,
+            2 => Code::Xchg_rm16_r16,
+            _ => Code::Xchg_rm8_r8,
+        };
+
+        asm.asm.add_instruction(Instruction::with2(mov_code(size), tmp, self.value.reg)?)?;
+        asm.asm.add_instruction(Instruction::with2(xchg, ptr_mem(&self.ptr), tmp)?)?;
+
+        Ok(())
+    }
+
+    fn display(&self) -> String {
+        format!("store atomic {} {}, ptr {} {}", self.value.typ, self.value, self.ptr, display_ordering(self.ordering))
+    }
+}
+
+impl Compile for Fence {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        // the other orderings are guaranteed by x86 itself
+        if self.ordering == AtomicOrdering::SeqCst {
+            asm.asm.add_instruction(Instruction::with(Code::Mfence))?;
+        }
+
+        Ok(())
+    }
+
+    fn display(&self) -> String {
+        format!("fence {}", display_ordering(self.ordering))
+    }
+}
+
+impl Compile for AtomicRMW {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        check_atomic("AtomicRMW", &self.value)?;
+        check_atomic("AtomicRMW", &self.out)?;
+
+        let size = self.value.typ.size();
+        let mem = ptr_mem(&self.ptr);
+
+        if matches!(self.op, AtomicOp::Add | AtomicOp::Sub | AtomicOp::Xchg) {
+            let out = self.out.reg;
+
+            if out != self.value.reg {
+                asm.asm.add_instruction(Instruction::with2(mov_code(size), out, self.value.reg)?)?;
+            }
+
+            if self.op == AtomicOp::Sub {
+                let neg = match size {
+                    8 => Code::Neg_rm64,
+                    4 => Code::Neg_rm32,
+                    2 => Code::Neg_rm16,
+                    _ => Code::Neg_rm8,
+                };
+
+                asm.asm.add_instruction(Instruction::with1(neg, out)?)?;
+            }
+
+            if self.op == AtomicOp::Xchg {
+                let xchg = match size {
+                    8 => Code::Xchg_rm64_r64,
+                    4 => Code::Xchg_rm32_r32,
+                    2 => Code::Xchg_rm16_r16,
+                    _ => Code::Xchg_rm8_r8,
+                };
+
+                asm.asm.add_instruction(Instruction::with2(xchg, mem, out)?)?;
+            } else {
+                let xadd = match size {
+                    8 => Code::Xadd_rm64_r64,
+                    4 => Code::Xadd_rm32_r32,
+                    2 => Code::Xadd_rm16_r16,
+                    _ => Code::Xadd_rm8_r8,
+                };
+
+                compile_locked(asm, Instruction::with2(xadd, mem, out)?)?;
+            }
+
+            return Ok(());
+        }
+
+        // the other operations are done in a compare exchange loop:
+        // the new value is computed from the old one (in RAX) and is stored if the memory didn't change meanwhile
+        let ([ptr, value], saved) = compile_save_rax(asm, self.out.reg, [self.ptr.reg, self.value.reg], &[])?;
+
+        let mem = MemoryOperand::new(full(ptr), Register::None, 1, 0, 0, false, Register::None);
+        let rax = sized(Register::RAX, size);
+        let tmp = asm.call.tmp_reg();
+
+        compile_load(asm, rax, int_of_size(size), mem)?;
+
+        let mut retry = asm.asm.create_label();
+        asm.asm.set_label(&mut retry)?;
+
+        asm.asm.add_instruction(Instruction::with2(Code::Mov_rm64_r64, tmp, Register::RAX)?)?;
+
+        let wide = if size == 8 { 8 } else { 4 };
+
+        match self.op {
+            AtomicOp::And | AtomicOp::Or | AtomicOp::Xor => {
+                let code = match (self.op, size) {
+                    (AtomicOp::And, 8) => Code::And_rm64_r64,
+                    (AtomicOp::And, 4) => Code::And_rm32_r32,
+                    (AtomicOp::And, 2) => Code::And_rm16_r16,
+                    (AtomicOp::And, _) => Code::And_rm8_r8,
+                    (AtomicOp::Or, 8) => Code::Or_rm64_r64,
+                    (AtomicOp::Or, 4) => Code::Or_rm32_r32,
+                    (AtomicOp::Or, 2) => Code::Or_rm16_r16,
+                    (AtomicOp::Or, _) => Code::Or_rm8_r8,
+                    (_, 8) => Code::Xor_rm64_r64,
+                    (_, 4) => Code::Xor_rm32_r32,
+                    (_, 2) => Code::Xor_rm16_r16,
+                    (_, _) => Code::Xor_rm8_r8,
+                };
+
+                asm.asm.add_instruction(Instruction::with2(code, sized(tmp, size), value)?)?;
+            },
+            _ => {
+                let cmp = match size {
+                    8 => Code::Cmp_rm64_r64,
+                    4 => Code::Cmp_rm32_r32,
+                    2 => Code::Cmp_rm16_r16,
+                    _ => Code::Cmp_rm8_r8,
+                };
+
+                asm.asm.add_instruction(Instruction::with2(cmp, sized(tmp, size), value)?)?;
+
+                let cmov = min_max_cmov(self.op == AtomicOp::Min, self.value.typ.signed(), size);
+
+                asm.asm.add_instruction(Instruction::with2(cmov, sized(tmp, wide), sized(value, wide))?)?;
+            },
+        }
+
+        let cmpxchg = match size {
+            8 => Code::Cmpxchg_rm64_r64,
+            4 => Code::Cmpxchg_rm32_r32,
+            2 => Code::Cmpxchg_rm16_r16,
+            _ => Code::Cmpxchg_rm8_r8,
+        };
+
+        compile_locked(asm, Instruction::with2(cmpxchg, mem, sized(tmp, size))?)?;
+        asm.asm.jne(retry)?;
+
+        if self.out.reg != rax {
+            asm.asm.add_instruction(Instruction::with2(mov_code(size), self.out.reg, rax)?)?;
+        }
+
+        compile_restore(asm, saved)
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.out.reg)
+    }
+
+    fn display(&self) -> String {
+        let op = format!("{:?}", self.op).to_lowercase();
+
+        format!("{} = atomicrmw {} ptr {}, {} {} {}", self.out, op, self.ptr, self.value.typ, self.value, display_ordering(self.ordering))
+    }
+}
+
+impl Compile for CmpXchg {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        check_atomic("CmpXchg", &self.new)?;
+        check_atomic("CmpXchg", &self.expected)?;
+        check_atomic("CmpXchg", &self.out)?;
+
+        let size = self.new.typ.size();
+        let rax = sized(Register::RAX, size);
+        let tmp = sized(asm.call.tmp_reg(), 1);
+
+        let avoid: Vec<Register> = [Some(self.expected.reg), self.success.map(|success| success.reg)].into_iter().flatten().collect();
+        let ([ptr, new], saved) = compile_save_rax(asm, self.out.reg, [self.ptr.reg, self.new.reg], &avoid)?;
+
+        let mem = MemoryOperand::new(full(ptr), Register::None, 1, 0, 0, false, Register::None);
+
+        if self.expected.reg != rax {
+            asm.asm.add_instruction(Instruction::with2(mov_code(size), rax, self.expected.reg)?)?;
+        }
+
+        let cmpxchg = match size {
+            8 => Code::Cmpxchg_rm64_r64,
+            4 => Code::Cmpxchg_rm32_r32,
+            2 => Code::Cmpxchg_rm16_r16,
+            _ => Code::Cmpxchg_rm8_r8,
+        };
+
+        compile_locked(asm, Instruction::with2(cmpxchg, mem, new)?)?;
+
+        if self.success.is_some() {
+            asm.asm.add_instruction(Instruction::with1(Code::Sete_rm8, tmp)?)?;
+        }
+
+        if self.out.reg != rax {
+            asm.asm.add_instruction(Instruction::with2(mov_code(size), self.out.reg, rax)?)?;
+        }
+
+        compile_restore(asm, saved)?;
+
+        // set after the registers are restored, because it could be stored in one of them
+        if let Some(success) = self.success {
+            asm.asm.add_instruction(Instruction::with2(Code::Mov_rm8_r8, success.reg, tmp)?)?;
+        }
+
+        Ok(())
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.out.reg)
+    }
+
+    fn display(&self) -> String {
+        let out = match self.success {
+            Some(success) => format!("{}, {}", self.out, success),
+            None => self.out.to_string(),
+        };
+
+        format!("{0} = cmpxchg ptr {1}, {2} {3}, {2} {4} {5}", out, self.ptr, self.expected.typ, self.expected, self.new, display_ordering(self.ordering))
+    }
+}
+
+/// Returns the error for a type which the intrinsic doesn't support
+fn unsupported_intrinsic(intrinsic: Intrinsic, value: &VarGen) -> Box<dyn std::error::Error> {
+    Box::from(error::IrError::UnsupportedType(format!("{} (in {})", value.typ.name(), intrinsic.name())))
+}
+
+/// Compiles the call of an intrinsic into inline instructions
+fn compile_intrinsic(asm: &mut AsmFunction, intrinsic: Intrinsic, args: &[VarGen], out: Option<VarGen>) -> Result<(), Box<dyn std::error::Error>> {
+    if args.len() != intrinsic.args() {
+        return Err(Box::from(error::IrError::WrongArgCount(intrinsic.name().to_string(), intrinsic.args(), args.len())));
+    }
+
+    if let Some(value) = args.iter().chain(&out).find(|value| !value.in_reg || value.typ.vector()) {
+        return Err(unsupported_intrinsic(intrinsic, value));
+    }
+
+    if matches!(intrinsic, Intrinsic::Memcpy | Intrinsic::Memset | Intrinsic::Memmove) {
+        return compile_rep(asm, intrinsic, args);
+    }
+
+    // the other intrinsics only compute the output
+    let Some(out) = out else {
+        return Ok(());
+    };
+
+    let float = matches!(intrinsic, Intrinsic::Sqrt | Intrinsic::Fabs | Intrinsic::Fma);
+
+    if let Some(value) = args.iter().chain([&out]).find(|value| match intrinsic {
+        Intrinsic::Min | Intrinsic::Max => value.typ.float() != out.typ.float(),
+        Intrinsic::Rotl | Intrinsic::Rotr => value.typ.float(),
+        _ => value.typ.float() != float,
+    }) {
+        return Err(unsupported_intrinsic(intrinsic, value));
+    }
+
+    let size = out.typ.size();
+    let f64 = size == 8;
+    let wide = if f64 { 8 } else { 4 };
+    let bits = size as u32 * 8;
+    let tmp = asm.call.tmp_reg();
+
+    match intrinsic {
+        Intrinsic::Sqrt => {
+            let code = if f64 { Code::Sqrtsd_xmm_xmmm64 } else { Code::Sqrtss_xmm_xmmm32 };
+
+            asm.asm.add_instruction(Instruction::with2(code, out.reg, args[0].reg)?)?;
+        },
+        Intrinsic::Fabs => {
+            // clears the sign bit
+            let (to_gpr, btr, to_xmm) = match f64 {
+                true => (Code::Movq_rm64_xmm, Code::Btr_rm64_imm8, Code::Movq_xmm_rm64),
+                false => (Code::Movd_rm32_xmm, Code::Btr_rm32_imm8, Code::Movd_xmm_rm32),
+            };
+
+            asm.asm.add_instruction(Instruction::with2(to_gpr, sized(tmp, wide), args[0].reg)?)?;
+            asm.asm.add_instruction(Instruction::with2(btr, sized(tmp, wide), bits - 1)?)?;
+            asm.asm.add_instruction(Instruction::with2(to_xmm, out.reg, sized(tmp, wide))?)?;
+        },
+        Intrinsic::Min | Intrinsic::Max => {
+            let min = intrinsic == Intrinsic::Min;
+
+            // out is overwritten with the first value, so it can't be the second one
+            let (a, b) = if full(out.reg) == full(args[1].reg) { (args[1], args[0]) } else { (args[0], args[1]) };
+
+            if out.typ.float() {
+                let code = match (min, f64) {
+                    (true, true) => Code::Minsd_xmm_xmmm64,
+                    (true, false) => Code::Minss_xmm_xmmm32,
+                    (false, true) => Code::Maxsd_xmm_xmmm64,
+                    (false, false) => Code::Maxss_xmm_xmmm32,
+                };
+
+                asm.parallel_copy(vec![(out.reg, a.reg)])?;
+                asm.asm.add_instruction(Instruction::with2(code, out.reg, b.reg)?)?;
+            } else {
+                let cmp = match size {
+                    8 => Code::Cmp_rm64_r64,
+                    4 => Code::Cmp_rm32_r32,
+                    2 => Code::Cmp_rm16_r16,
+                    _ => Code::Cmp_rm8_r8,
+                };
+
+                asm.parallel_copy(vec![(out.reg, a.reg)])?;
+                asm.asm.add_instruction(Instruction::with2(cmp, out.reg, b.reg)?)?;
+                asm.asm.add_instruction(Instruction::with2(min_max_cmov(min, out.typ.signed(), size), sized(out.reg, wide), sized(b.reg, wide))?)?;
+            }
+        },
+        Intrinsic::Ctpop => {
+            compile_zext(asm, tmp, &args[0])?;
+
+            if asm.popcnt {
+                let code = if f64 { Code::Popcnt_r64_rm64 } else { Code::Popcnt_r32_rm32 };
+
+                asm.asm.add_instruction(Instruction::with2(code, sized(out.reg, wide), sized(tmp, wide))?)?;
+            } else {
+                // adds the bits one by one (the carry is the bit which is shifted out)
+                asm.asm.add_instruction(Instruction::with2(Code::Xor_r32_rm32, sized(out.reg, 4), sized(out.reg, 4))?)?;
+
+                let mut next = asm.asm.create_label();
+                asm.asm.set_label(&mut next)?;
+
+                asm.asm.add_instruction(Instruction::with2(Code::Shr_rm64_1, tmp, 1)?)?;
+                asm.asm.add_instruction(Instruction::with2(Code::Adc_rm32_imm8, sized(out.reg, 4), 0)?)?;
+                asm.asm.add_instruction(Instruction::with2(Code::Test_rm64_r64, tmp, tmp)?)?;
+                asm.asm.jne(next)?;
+            }
+        },
+        Intrinsic::Ctlz => {
+            compile_zext(asm, tmp, &args[0])?;
+
+            let (out, tmp) = (sized(out.reg, wide), sized(tmp, wide));
+
+            if asm.lzcnt {
+                let code = if f64 { Code::Lzcnt_r64_rm64 } else { Code::Lzcnt_r32_rm32 };
+
+                asm.asm.add_instruction(Instruction::with2(code, out, tmp)?)?;
+
+                // the value was zero extended
+                if bits < 32 {
+                    asm.asm.add_instruction(Instruction::with2(Code::Sub_rm32_imm8, out, 32 - bits)?)?;
+                }
+            } else {
+                // bsr returns the index of the highest set bit (bits - 1 - index is the same as index ^ (bits - 1)),
+                // for zero it doesn't set the output so the value is chosen which gives the bit width
+                let (bsr, cmov) = match f64 {
+                    true => (Code::Bsr_r64_rm64, Code::Cmovne_r64_rm64),
+                    false => (Code::Bsr_r32_rm32, Code::Cmovne_r32_rm32),
+                };
+
+                asm.asm.add_instruction(Instruction::with2(bsr, tmp, tmp)?)?;
+                asm.asm.add_instruction(Instruction::with2(Code::Mov_r32_imm32, sized(out, 4), 2 * bits - 1)?)?;
+                asm.asm.add_instruction(Instruction::with2(cmov, out, tmp)?)?;
+                asm.asm.add_instruction(Instruction::with2(Code::Xor_rm32_imm32, sized(out, 4), bits - 1)?)?;
+            }
+        },
+        Intrinsic::Cttz => {
+            compile_zext(asm, tmp, &args[0])?;
+
+            // a set bit above the value, so zero gives the bit width
+            if bits < 32 {
+                asm.asm.add_instruction(Instruction::with2(Code::Bts_rm32_imm8, sized(tmp, 4), bits)?)?;
+            }
+
+            // without BMI1 tzcnt runs as bsf
+            if asm.bmi1 {
+                let code = if f64 { Code::Tzcnt_r64_rm64 } else { Code::Tzcnt_r32_rm32 };
+
+                asm.asm.add_instruction(Instruction::with2(code, sized(out.reg, wide), sized(tmp, wide))?)?;
+            } else {
+                // bsf doesn't set the output for zero
+                let (bsf, cmov) = match f64 {
+                    true => (Code::Bsf_r64_rm64, Code::Cmovne_r64_rm64),
+                    false => (Code::Bsf_r32_rm32, Code::Cmovne_r32_rm32),
+                };
+
+                asm.asm.add_instruction(Instruction::with2(bsf, sized(tmp, wide), sized(tmp, wide))?)?;
+                asm.asm.add_instruction(Instruction::with2(Code::Mov_r32_imm32, sized(out.reg, 4), bits)?)?;
+                asm.asm.add_instruction(Instruction::with2(cmov, sized(out.reg, wide), sized(tmp, wide))?)?;
+            }
+        },
+        Intrinsic::Bswap => {
+            asm.parallel_copy(vec![(out.reg, args[0].reg)])?;
+
+            match size {
+                8 => asm.asm.add_instruction(Instruction::with1(Code::Bswap_r64, out.reg)?)?,
+                4 => asm.asm.add_instruction(Instruction::with1(Code::Bswap_r32, out.reg)?)?,
+                2 => asm.asm.add_instruction(Instruction::with2(Code::Rol_rm16_imm8, out.reg, 8)?)?,
+                _ => {},
+            }
+        },
+        Intrinsic::Rotl | Intrinsic::Rotr => {
+            let code = match (intrinsic, size) {
+                (Intrinsic::Rotl, 8) => Code::Rol_rm64_CL,
+                (Intrinsic::Rotl, 4) => Code::Rol_rm32_CL,
+                (Intrinsic::Rotl, 2) => Code::Rol_rm16_CL,
+                (Intrinsic::Rotl, _) => Code::Rol_rm8_CL,
+                (_, 8) => Code::Ror_rm64_CL,
+                (_, 4) => Code::Ror_rm32_CL,
+                (_, 2) => Code::Ror_rm16_CL,
+                (_, _) => Code::Ror_rm8_CL,
+            };
+
+            let (x, n) = (&args[0], &args[1]);
+
+            if full(out.reg) == full(n.reg) && full(out.reg) != full(x.reg) {
+                // the count would be overwritten by the value
+                let target = VarGen::new_reg(out.typ, sized(tmp, size));
+
+                asm.parallel_copy(vec![(target.reg, x.reg)])?;
+                compile_shift(asm, code, &target, n)?;
+                asm.parallel_copy(vec![(out.reg, target.reg)])?;
+            } else {
+                asm.parallel_copy(vec![(out.reg, x.reg)])?;
+                compile_shift(asm, code, &out, n)?;
+            }
+        },
+        Intrinsic::Fma => {
+            if !asm.fma {
+                let func = if f64 { "fma" } else { "fmaf" };
+                asm.require_extern(func);
+
+                return Call::new(func, args.to_vec(), Some(out)).compile(asm);
+            }
+
+            let tmpf = asm.call.tmpf_reg();
+            let code = if f64 { Code::VEX_Vfmadd231sd_xmm_xmm_xmmm64 } else { Code::VEX_Vfmadd231ss_xmm_xmm_xmmm32 };
+
+            asm.asm.add_instruction(Instruction::with2(Code::Movaps_xmm_xmmm128, tmpf, args[2].reg)?)?;
+            asm.asm.add_instruction(Instruction::with3(code, tmpf, args[0].reg, args[1].reg)?)?;
+            asm.asm.add_instruction(Instruction::with2(Code::Movaps_xmm_xmmm128, out.reg, tmpf)?)?;
+        },
+        Intrinsic::Memcpy | Intrinsic::Memset | Intrinsic::Memmove => unreachable!(), // are compiled before
+    }
+
+    Ok(())
+}
+
+/// Compiles `memcpy`, `memset` and `memmove` with the `rep` string instructions
+/// 
+/// The registers which they use are saved
+fn compile_rep(asm: &mut AsmFunction, intrinsic: Intrinsic, args: &[VarGen]) -> Result<(), Box<dyn std::error::Error>> {
+    let (dst, src, len) = (&args[0], &args[1], &args[2]);
+
+    if dst.typ.float() || src.typ.float() || len.typ.float() {
+        let value = [dst, src, len].into_iter().find(|value| value.typ.float()).unwrap();
+        return Err(unsupported_intrinsic(intrinsic, value));
+    }
+
+    let source = if intrinsic == Intrinsic::Memset { Register::RAX } else { Register::RSI };
+    let saved = [Register::RDI, source, Register::RCX];
+
+    for reg in saved {
+        asm.asm.add_instruction(Instruction::with1(Code::Push_r64, reg)?)?;
+    }
+
+    asm.parallel_copy(vec![(Register::RDI, dst.reg), (source, src.reg), (Register::RCX, len.reg)])?;
+
+    if len.typ.size() < 8 {
+        compile_zext(asm, Register::RCX, &VarGen::new_reg(len.typ, sized(Register::RCX, len.typ.size())))?;
+    }
+
+    match intrinsic {
+        Intrinsic::Memset => asm.asm.rep().stosb()?,
+        Intrinsic::Memmove => {
+            let mut forward = asm.asm.create_label();
+            let mut done = asm.asm.create_label();
+
+            // copies backwards if the destination is behind the source (so the source isn't overwritten before it is read)
+            asm.asm.add_instruction(Instruction::with2(Code::Cmp_rm64_r64, Register::RDI, Register::RSI)?)?;
+            asm.asm.jbe(forward)?;
+
+            let last = |reg| MemoryOperand::new(reg, Register::RCX, 1, -1, 1, false, Register::None);
+
+            asm.asm.add_instruction(Instruction::with2(Code::Lea_r64_m, Register::RSI, last(Register::RSI))?)?;
+            asm.asm.add_instruction(Instruction::with2(Code::Lea_r64_m, Register::RDI, last(Register::RDI))?)?;
+            asm.asm.std()?;
+            asm.asm.rep().movsb()?;
+            asm.asm.cld()?;
+            asm.asm.jmp(done)?;
+
+            asm.asm.set_label(&mut forward)?;
+            asm.asm.rep().movsb()?;
+
+            asm.asm.set_label(&mut done)?;
+        },
+        _ => asm.asm.rep().movsb()?,
+    }
+
+    for reg in saved.into_iter().rev() {
+        asm.asm.add_instruction(Instruction::with1(Code::Pop_r64, reg)?)?;
+    }
+
+    Ok(())
+}
+
+/// The operation of the arithmetic with overflow check
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OverflowOp {
+    Add,
+    Sub,
+    Mul,
+}
+
+/// Computes `lhs (op) rhs` into `out` and sets `overflow` or jumps to the trap block if it overflowed
+fn compile_with_overflow(asm: &mut AsmFunction, op: OverflowOp, out: &VarGen, overflow: Option<VarGen>, lhs: &VarGen, rhs: &VarGen, trap: &Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(value) = [out, lhs, rhs].into_iter().find(|value| !value.in_reg || value.typ.float() || value.typ.vector()) {
+        return Err(Box::from(error::IrError::UnsupportedType(format!("{} (in {:?}WithOverflow)", value.typ.name(), op))));
+    }
+
+    let size = out.typ.size();
+    let signed = out.typ.signed();
+    let tmp = asm.call.tmp_reg();
+
+    // the result is computed in the scratch register, so the operands can also be the output
+    // (the moves and pops afterwards don't change the flags)
+    if op != OverflowOp::Mul || (signed && size > 1) {
+        let code = match (op, size) {
+            (OverflowOp::Add, 8) => Code::Add_r64_rm64,
+            (OverflowOp::Add, 4) => Code::Add_r32_rm32,
+            (OverflowOp::Add, 2) => Code::Add_r16_rm16,
+            (OverflowOp::Add, _) => Code::Add_r8_rm8,
+            (OverflowOp::Sub, 8) => Code::Sub_r64_rm64,
+            (OverflowOp::Sub, 4) => Code::Sub_r32_rm32,
+            (OverflowOp::Sub, 2) => Code::Sub_r16_rm16,
+            (OverflowOp::Sub, _) => Code::Sub_r8_rm8,
+            (_, 8) => Code::Imul_r64_rm64,
+            (_, 4) => Code::Imul_r32_rm32,
+            (_, _) => Code::Imul_r16_rm16,
+        };
+
+        asm.asm.add_instruction(Instruction::with2(Code::Mov_rm64_r64, tmp, full(lhs.reg))?)?;
+        asm.asm.add_instruction(Instruction::with2(code, sized(tmp, size), rhs.reg)?)?;
+    } else {
+        // the one operand multiplication computes rdx:rax = rax * rhs (ax = al * rhs for bytes),
+        // the flags are set if the upper half is needed
+        let avoid: Vec<Register> = overflow.iter().map(|overflow| overflow.reg).collect();
+        let ([lhs, rhs], mut saved) = compile_save_rax(asm, out.reg, [lhs.reg, rhs.reg], &avoid)?;
+
+        if size > 1 && full(out.reg) != Register::RDX && !saved.contains(&Register::RDX) {
+            asm.asm.add_instruction(Instruction::with1(Code::Push_r64, Register::RDX)?)?;
+            saved.insert(0, Register::RDX);
+        }
+
+        let code = match (signed, size) {
+            (true, _) => Code::Imul_rm8,
+            (false, 8) => Code::Mul_rm64,
+            (false, 4) => Code::Mul_rm32,
+            (false, 2) => Code::Mul_rm16,
+            (false, _) => Code::Mul_rm8,
+        };
+
+        asm.asm.add_instruction(Instruction::with2(Code::Mov_rm64_r64, Register::RAX, full(lhs))?)?;
+        asm.asm.add_instruction(Instruction::with1(code, rhs)?)?;
+        asm.asm.add_instruction(Instruction::with2(Code::Mov_rm64_r64, tmp, Register::RAX)?)?;
+
+        compile_restore(asm, saved)?;
+    }
+
+    asm.asm.add_instruction(Instruction::with2(mov_code(size), out.reg, sized(tmp, size))?)?;
+
+    // unsigned addition and subtraction set the carry flag, the others the overflow flag
+    let carry = !signed && op != OverflowOp::Mul;
+
+    if let Some(overflow) = overflow {
+        let code = if carry { Code::Setb_rm8 } else { Code::Seto_rm8 };
+
+        asm.asm.add_instruction(Instruction::with1(code, sized(overflow.reg, 1))?)?;
+    }
+
+    if let Some(trap) = trap {
+        let mut edge = asm.asm.create_label();
+        let copies = asm.has_edge_copies(trap);
+
+        let target = if copies { edge } else { asm.label(trap) };
+
+        if carry {
+            asm.asm.jb(target)?;
+        } else {
+            asm.asm.jo(target)?;
+        }
+
+        if copies {
+            // the copies of the trap edge need their own path
+            let mut next = asm.asm.create_label();
+            asm.asm.jmp(next)?;
+
+            asm.asm.set_label(&mut edge)?;
+            asm.asm.zero_bytes()?;
+            asm.edge_copies(trap)?;
+
+            let label = asm.label(trap);
+            asm.asm.jmp(label)?;
+
+            asm.asm.set_label(&mut next)?;
+            asm.asm.zero_bytes()?;
+        }
+    }
+
+    Ok(())
+}
+
+macro_rules! OverflowStruct {
+    ($name:tt, $op:expr) => {
+        impl Compile for $name {
+            fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+                compile_with_overflow(asm, $op, &self.out, self.overflow, &self.lhs, &self.rhs, &self.trap)
+            }
+
+            fn out_reg(&self) -> Option<Register> {
+                Some(self.out.reg)
+            }
+
+            fn display(&self) -> String {
+                let out = match self.overflow {
+                    Some(overflow) => format!("{}, {}", self.out, overflow),
+                    None => self.out.to_string(),
+                };
+                let trap = match &self.trap {
+                    Some(trap) => format!(", trap label %{}", trap),
+                    None => String::new(),
+                };
+
+                let op = format!("{:?}", $op).to_lowercase();
+
+                format!("{} = {}.with.overflow {} {}, {}{}", out, op, self.out.typ, self.lhs, self.rhs, trap)
+            }
+        }
+    };
+}
+
+OverflowStruct!(AddWithOverflow, OverflowOp::Add);
+OverflowStruct!(SubWithOverflow, OverflowOp::Sub);
+OverflowStruct!(MulWithOverflow, OverflowOp::Mul);
+
+/// Stops the execution with `ud2` or calls the trap handler of the context (and then returns)
+fn compile_trap(asm: &mut AsmFunction, code: u32) -> Result<(), Box<dyn std::error::Error>> {
+    if !asm.trap_handler {
+        asm.asm.ud2()?;
+        return Ok(());
+    }
+
+    asm.make_stack_safe()?;
+    asm.require_extern(TRAP_HANDLER);
+
+    let home = asm.call.home().next_multiple_of(16);
+
+    if home > 0 {
+        asm.asm.add_instruction(Instruction::with2(Code::Sub_rm64_imm32, Register::RSP, home as i32)?)?;
+    }
+
+    let mut name = asm.name.as_bytes().to_vec();
+    name.push(0);
+
+    let (func, trap, at) = (asm.call.arg64_reg(0).unwrap(), asm.call.arg64_reg(1).unwrap(), asm.call.arg64_reg(2).unwrap()); // every calling convention has three argument registers
+
+    compile_const_op(asm, Code::Lea_r64_m, func, name)?;
+    asm.asm.add_instruction(Instruction::with2(Code::Mov_r32_imm32, sized(trap, 4), code)?)?;
+    asm.asm.add_instruction(Instruction::with2(Code::Mov_r64_imm64, at, asm.ir as u64)?)?;
+
+    asm.asm.call(0)?;
+    asm.reloc_at_current_pos(TRAP_HANDLER, -4, 4)?;
+
+    if home > 0 {
+        asm.asm.add_instruction(Instruction::with2(Code::Add_rm64_imm32, Register::RSP, home as i32)?)?;
+    }
+
+    // the handler returned, so the function returns a zeroed value
+    asm.asm.add_instruction(Instruction::with2(Code::Xor_r32_rm32, Register::EAX, Register::EAX)?)?;
+    asm.asm.add_instruction(Instruction::with2(Code::Xor_r32_rm32, Register::EDX, Register::EDX)?)?;
+    asm.asm.add_instruction(Instruction::with2(Code::Xorps_xmm_xmmm128, Register::XMM0, Register::XMM0)?)?;
+    asm.asm.jmp(asm.exit())?;
+
+    Ok(())
+}
+
+impl Compile for Trap {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        compile_trap(asm, self.code)
+    }
+
+    fn display(&self) -> String {
+        format!("trap {}", self.code)
+    }
+}
+
+impl Compile for Unreachable {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        compile_trap(asm, Unreachable::CODE)
+    }
+
+    fn display(&self) -> String {
+        "unreachable".into()
+    }
+}
+
+impl Compile for TailCall {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        // the frame is always torn down, so it has to exist (the prologue is only known after everything is compiled)
+        asm.make_stack_safe()?;
+
+        let types: Vec<Type> = self.args.iter().map(|arg| arg.typ).collect();
+        let reg_args = asm.call.reg_args(&types);
+
+        // on windows 128 bit integers are passed as pointers to the stack frame, which doesn't exist anymore
+        if asm.call.conv() == CallingConvention::WindowsFastcall {
+            if let Some(arg) = self.args.iter().find(|arg| arg.typ.wide()) {
+                return Err(Box::from(error::IrError::UnsupportedType(format!("{} (in TailCall)", arg.typ.name()))));
+            }
+        }
+
+        let (stack_args, outgoing) = stack_arg_offsets(asm, &types, &reg_args);
+
+        let incoming = stack_arg_offsets(asm, &asm.args, &asm.call.reg_args(&asm.args)).1;
+
+        if outgoing > incoming {
+            return Err(Box::from(error::IrError::TailCallArgs(self.func.clone())));
+        }
+
+        // the stack arguments are placed where the own ones are (above the return address)
+        compile_call_args(asm, &self.args, reg_args, stack_args, |off| {
+            MemoryOperand::new(Register::RBP, Register::None, 1, off as i64 + 16, 1, false, Register::None)
+        })?;
+
+        let ymm_args = self.args.iter().any(|arg| arg.typ.vector() && arg.typ.size() == 32);
+
+        if asm.uses_ymm() && !ymm_args {
+            asm.asm.vzeroupper()?;
+        }
+
+        asm.restore_saved()?;
+        asm.asm.add_instruction(Instruction::with2(Code::Mov_rm64_r64, Register::RSP, Register::RBP)?)?;
+        asm.asm.add_instruction(Instruction::with1(Code::Pop_r64, Register::RBP)?)?;
+
+        // encoded by hand, so the encoder can't turn it into a short jump (the relocation needs 4 bytes)
+        asm.asm.db(&[0xE9, 0, 0, 0, 0])?;
+        asm.reloc_at_current_pos(&self.func, -4, 4)?;
+
+        Ok(())
+    }
+
+    fn display(&self) -> String {
+        format!("tail call @{}({})", self.func, display_args(&self.args))
+    }
+}
+
+impl Compile for InlineAsm {
+    fn prepare(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let changed = self.clobbers.iter().copied()
+            .chain(self.inputs.iter().map(|(reg, _)| *reg))
+            .chain(self.outputs.iter().map(|(_, reg)| *reg));
+
+        for reg in changed {
+            // the frame (and the stack slots) is addressed with them
+            if matches!(reg.full_register(), Register::RSP | Register::RBP) {
+                return Err(Box::from(error::IrError::InvalidAsm(format!("{:?} can't be changed", reg).to_lowercase())));
+            }
+
+            if asm.call.callee_saved(reg) {
+                asm.save_reg(reg);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let bindings = self.inputs.iter().map(|(_, var)| var).chain(self.outputs.iter().map(|(var, _)| var));
+
+        for var in bindings {
+            if var.typ.wide() {
+                return Err(Box::from(error::IrError::UnsupportedType(format!("{} (in InlineAsm)", var.typ.name()))));
+            }
+        }
+
+        // parsed first, so invalid assembly doesn't leave anything behind
+        let instrs = match &self.code {
+            AsmCode::Intel(code) => intel::parse(code)?,
+            AsmCode::Closure(_) => vec![],
+        };
+
+        // the full registers (zmm for vectors), so every size of a register matches
+        let changed: Vec<Register> = self.clobbers.iter().copied()
+            .chain(self.inputs.iter().map(|(reg, _)| *reg))
+            .chain(self.outputs.iter().map(|(_, reg)| *reg))
+            .map(|reg| reg.full_register())
+            .collect();
+
+        let outs: Vec<Register> = self.outputs.iter()
+            .filter(|(var, _)| var.in_reg)
+            .map(|(var, _)| var.reg.full_register())
+            .collect();
+
+        let saved: Vec<Register> = asm.live_regs().into_iter()
+            .filter(|reg| changed.contains(&reg.full_register()) && !outs.contains(&reg.full_register()))
+            .collect();
+
+        let frame = saved.iter().map(|reg| saved_size(*reg)).sum::<usize>().next_multiple_of(16);
+
+        if frame > 0 {
+            asm.asm.add_instruction(Instruction::with2(Code::Sub_rm64_imm32, Register::RSP, frame as i32)?)?;
+        }
+
+        compile_spill(asm, &saved, 0)?;
+
+        let copies = self.inputs.iter()
+            .filter(|(_, var)| var.in_reg)
+            .map(|(reg, var)| (*reg, var.reg))
+            .collect();
+
+        asm.parallel_copy(copies)?;
+
+        for (reg, var) in self.inputs.iter().filter(|(_, var)| !var.in_reg) {
+            let reg = if reg.is_gpr() { sized(*reg, var.typ.size()) } else { *reg };
+
+            compile_load(asm, reg, var.typ, stack_mem(var))?;
+        }
+
+        match &self.code {
+            AsmCode::Closure(code) => code(&mut asm.asm)?,
+            AsmCode::Intel(_) => {
+                for instr in instrs {
+                    asm.asm.add_instruction(instr)?;
+                }
+            },
+        }
+
+        // stored first, the copies can overwrite the registers
+        for (var, reg) in self.outputs.iter().filter(|(var, _)| !var.in_reg) {
+            let reg = if reg.is_gpr() { sized(*reg, var.typ.size()) } else { *reg };
+
+            compile_store(asm, stack_mem(var), reg, var.typ)?;
+        }
+
+        let copies = self.outputs.iter()
+            .filter(|(var, _)| var.in_reg)
+            .map(|(var, reg)| (var.reg, *reg))
+            .collect();
+
+        asm.parallel_copy(copies)?;
+
+        compile_reload(asm, &saved, 0)?;
+
+        if frame > 0 {
+            asm.asm.add_instruction(Instruction::with2(Code::Add_rm64_imm32, Register::RSP, frame as i32)?)?;
+        }
+
+        Ok(())
+    }
+
+    fn display(&self) -> String {
+        let reg = |reg: &Register| format!("{:?}", reg).to_lowercase();
+
+        let code = match &self.code {
+            AsmCode::Closure(_) => "<closure>".to_string(),
+            AsmCode::Intel(code) => format!("{:?}", code),
+        };
+        let inputs: Vec<String> = self.inputs.iter().map(|(target, var)| format!("{} = {}", reg(target), var)).collect();
+        let outputs: Vec<String> = self.outputs.iter().map(|(var, src)| format!("{} = {}", var, reg(src))).collect();
+        let clobbers: Vec<String> = self.clobbers.iter().map(reg).collect();
+
+        format!("asm {} in({}) out({}) clobber({})", code, inputs.join(", "), outputs.join(", "), clobbers.join(", "))
+    }
+}
+
+impl Compile for Select {
+    fn compile(&self, asm: &mut AsmFunction) -> Result<(), Box<dyn std::error::Error>> {
+        let (out, cond, a, b) = (&self.out, &self.cond, &self.a, &self.b);
+
+        if cond.typ.float() || cond.typ.vector() {
+            return Err(Box::from(error::IrError::UnsupportedType(format!("{} (in Select)", cond.typ.name()))));
+        }
+
+        if let Some(value) = [out, cond, a, b].into_iter().find(|value| !value.in_reg || value.typ.wide()) {
+            return Err(Box::from(error::IrError::UnsupportedType(format!("{} (in Select)", vector_name(&value.typ)))));
+        }
+
+        for value in [a, b] {
+            if value.typ.float() != out.typ.float() || value.typ.vector() != out.typ.vector() || value.typ.size() != out.typ.size() {
+                return Err(invalid_cast("Select", out, value));
+            }
+        }
+
+        let test = match cond.typ.size() {
+            8 => Code::Test_rm64_r64,
+            4 => Code::Test_rm32_r32,
+            2 => Code::Test_rm16_r16,
+            _ => Code::Test_rm8_r8,
+        };
+
+        if !out.typ.float() && !out.typ.vector() {
+            let size = if out.typ.size() == 8 { 8 } else { 4 };
+            let (cmovne, cmove) = match size {
+                8 => (Code::Cmovne_r64_rm64, Code::Cmove_r64_rm64),
+                _ => (Code::Cmovne_r32_rm32, Code::Cmove_r32_rm32),
+            };
+
+            // the moves don't change the flags, so out can also be cond
+            asm.asm.add_instruction(Instruction::with2(test, cond.reg, cond.reg)?)?;
+
+            if full(out.reg) == full(a.reg) {
+                asm.asm.add_instruction(Instruction::with2(cmove, sized(out.reg, size), sized(b.reg, size))?)?;
+            } else {
+                asm.parallel_copy(vec![(out.reg, b.reg)])?;
+                asm.asm.add_instruction(Instruction::with2(cmovne, sized(out.reg, size), sized(a.reg, size))?)?;
+            }
+
+            return Ok(());
+        }
+
+        let ymm_out = out.reg.is_ymm();
+
+        if asm.avx {
+            // a mask of all ones (or zeros) in every lane, so vblendvpd can pick the bytes
+            let tmp = asm.call.tmp_reg();
+            let mask = asm.call.tmpf_reg();
+
+            // every non zero condition becomes 1 (its sign bit can't be used directly)
+            asm.asm.add_instruction(Instruction::with2(test, cond.reg, cond.reg)?)?;
+            asm.asm.add_instruction(Instruction::with1(Code::Setne_rm8, sized(tmp, 1))?)?;
+            asm.asm.add_instruction(Instruction::with2(Code::Movzx_r32_rm8, sized(tmp, 4), sized(tmp, 1))?)?;
+            asm.asm.add_instruction(Instruction::with1(Code::Neg_rm64, tmp)?)?;
+            asm.asm.add_instruction(Instruction::with2(Code::VEX_Vmovq_xmm_rm64, mask, tmp)?)?;
+
+            if out.typ.vector() {
+                asm.asm.add_instruction(Instruction::with2(Code::VEX_Vmovddup_xmm_xmmm64, mask, mask)?)?;
+            }
+
+            if ymm_out {
+                asm.asm.add_instruction(Instruction::with4(Code::VEX_Vinsertf128_ymm_ymm_xmmm128_imm8, ymm(mask), ymm(mask), mask, 1)?)?;
+                asm.asm.add_instruction(Instruction::with4(Code::VEX_Vblendvpd_ymm_ymm_ymmm256_ymm, out.reg, b.reg, a.reg, ymm(mask))?)?;
+            } else {
+                asm.asm.add_instruction(Instruction::with4(Code::VEX_Vblendvpd_xmm_xmm_xmmm128_xmm, out.reg, b.reg, a.reg, mask)?)?;
+            }
+
+            return Ok(());
+        }
+
+        // without avx there's no blend with a mask register (blendvpd always uses xmm0), so it's a short branch
+        let mut end = asm.asm.create_label();
+
+        if full(out.reg) == full(a.reg) {
+            asm.asm.add_instruction(Instruction::with2(test, cond.reg, cond.reg)?)?;
+            asm.asm.jne(end)?;
+            asm.parallel_copy(vec![(out.reg, b.reg)])?;
+        } else {
+            asm.parallel_copy(vec![(out.reg, b.reg)])?;
+            asm.asm.add_instruction(Instruction::with2(test, cond.reg, cond.reg)?)?;
+            asm.asm.je(end)?;
+            asm.parallel_copy(vec![(out.reg, a.reg)])?;
+        }
+
+        asm.asm.set_label(&mut end)?;
+        asm.asm.zero_bytes()?;
+
+        Ok(())
+    }
+
+    fn out_reg(&self) -> Option<Register> {
+        Some(self.out.reg)
+    }
+
+    fn display(&self) -> String {
+        format!("{0} = select {1} {2}, {3} {4}, {3} {5}", self.out, self.cond.typ, self.cond, self.a.typ, self.a, self.b)
+    }
+}
@@ -0,0 +1,39 @@
+use std::fmt;
+
+/// An error which can occure while compiling the ir
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IrError {
+    UnknownBlock(String),
+    DuplicatedBlock(String),
+    InvalidCast(String, String, String),
+    UnsupportedType(String),
+    InvalidLane(usize, String),
+    InvalidField(usize, String),
+    WrongArgCount(String, usize, usize),
+    TailCallArgs(String),
+    InvalidAsm(String),
+    UnknownSlot(String),
+    TypeMismatch(String, String, String),
+}
+
+impl fmt::Display for IrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let str = match self {
+            IrError::UnknownBlock(n) => format!("unknown block {}", n),
+            IrError::DuplicatedBlock(n) => format!("block {} is defined multiple times", n),
+            IrError::InvalidCast(i, from, to) => format!("{} can't convert {} to {}", i, from, to),
+            IrError::UnsupportedType(t) => format!("the type {} isn't supported here", t),
+            IrError::InvalidLane(l, t) => format!("lane {} is out of range for {}", l, t),
+            IrError::InvalidField(n, t) => format!("field {} is out of range for {}", n, t),
+            IrError::WrongArgCount(n, e, g) => format!("{} takes {} arguments but got {}", n, e, g),
+            IrError::TailCallArgs(n) => format!("the stack arguments of the tail call to {} don't fit into the ones of the caller", n),
+            IrError::InvalidAsm(e) => format!("invalid inline assembly: {}", e),
+            IrError::UnknownSlot(v) => format!("{} isn't a pointer or a stack slot from AsmFunction::alloca", v),
+            IrError::TypeMismatch(i, e, g) => format!("{} expects {} but got {}", i, e, g),
+        };
+
+        write!(f, "{}", str)
+    }
+}
+
+impl std::error::Error for IrError {}
@@ -0,0 +1,284 @@
+//! A parser for intel syntax assembly (used by `InlineAsm`)
+//!
+//! Every line (or `;` separated statement) is one instruction like `add rax, qword ptr [rbx + rcx*8 + 16]`.
+//! The instruction is searched by its mnemonic and operands, so every instruction with explicit operands
+//! which can be encoded in 64 bit mode is supported (no labels/branch targets).
+//! Comments start with `//` or `#`
+
+use iced_x86::{Code, Encoder, EncodingKind, Instruction, MemoryOperand, Mnemonic, Register};
+
+use super::error::IrError;
+
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+    Reg(Register),
+    /// The memory operand and the size from `<size> ptr`
+    Mem(MemoryOperand, Option<usize>),
+    Imm(i64),
+}
+
+/// Parses the assembly into instructions
+pub fn parse(code: &str) -> Result<Vec<Instruction>, IrError> {
+    let mut instrs = vec![];
+
+    for line in code.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        let line = line.split("//").next().unwrap_or_default();
+
+        for stmt in line.split(';') {
+            let stmt = stmt.trim();
+
+            if !stmt.is_empty() {
+                instrs.push(parse_instruction(stmt)?);
+            }
+        }
+    }
+
+    Ok(instrs)
+}
+
+fn invalid(stmt: &str, msg: &str) -> IrError {
+    IrError::InvalidAsm(format!("{} ({})", stmt, msg))
+}
+
+fn parse_instruction(stmt: &str) -> Result<Instruction, IrError> {
+    let mut rest = stmt;
+    let mut prefixes = vec![];
+
+    let mnemonic = loop {
+        let (word, tail) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let word = word.to_lowercase();
+        rest = tail.trim();
+
+        match word.as_str() {
+            "lock" | "rep" | "repe" | "repz" | "repne" | "repnz" => prefixes.push(word),
+            _ => break word,
+        }
+    };
+
+    let mnemonic = Mnemonic::values()
+        .find(|m| format!("{:?}", m).to_lowercase() == mnemonic)
+        .ok_or_else(|| invalid(stmt, "unknown mnemonic"))?;
+
+    let mut operands = vec![];
+
+    if !rest.is_empty() {
+        for op in rest.split(',') {
+            operands.push(parse_operand(op).ok_or_else(|| invalid(stmt, &format!("invalid operand `{}`", op.trim())))?);
+        }
+    }
+
+    let mut codes: Vec<Code> = Code::values()
+        .filter(|code| {
+            let op_code = code.op_code();
+
+            op_code.mnemonic() == mnemonic && op_code.is_instruction() && op_code.mode64() && op_code.op_count() as usize == operands.len()
+        })
+        .collect();
+
+    // the shortest encodings first
+    codes.sort_by_key(|code| match code.op_code().encoding() {
+        EncodingKind::Legacy => 0,
+        EncodingKind::VEX => 1,
+        EncodingKind::EVEX => 2,
+        _ => 3,
+    });
+
+    let size = operands.iter().find_map(|op| match op {
+        Operand::Mem(_, size) => Some(*size),
+        _ => None,
+    });
+
+    let mut encoder = Encoder::new(64);
+
+    let found: Vec<Instruction> = codes.into_iter()
+        .filter_map(|code| build(code, &operands))
+        .filter(|instr| match size {
+            Some(Some(size)) => instr.memory_size().size() == size,
+            _ => true,
+        })
+        .filter(|instr| encoder.encode(instr, 0).is_ok())
+        .collect();
+
+    let Some(mut instr) = found.first().copied() else {
+        return Err(invalid(stmt, "no instruction with these operands"));
+    };
+
+    if size == Some(None) && found.iter().any(|other| other.memory_size().size() != instr.memory_size().size()) {
+        return Err(invalid(stmt, "the operand size is ambiguous, use `<size> ptr`"));
+    }
+
+    for prefix in prefixes {
+        match prefix.as_str() {
+            "lock" => instr.set_has_lock_prefix(true),
+            "rep" | "repe" | "repz" => instr.set_has_repe_prefix(true),
+            _ => instr.set_has_repne_prefix(true),
+        }
+    }
+
+    Ok(instr)
+}
+
+/// Creates the instruction (`None` if the operands don't fit the code)
+fn build(code: Code, operands: &[Operand]) -> Option<Instruction> {
+    use Operand::{Imm, Mem, Reg};
+
+    // immediates which don't fit an i32 are only allowed as an unsigned 32 bit value (or with mov r64, imm64)
+    let signed = |imm: i64| i32::try_from(imm).ok();
+    let unsigned = |imm: i64| u32::try_from(imm).ok();
+
+    let instr = match *operands {
+        [] => Ok(Instruction::with(code)),
+        [Reg(a)] => Instruction::with1(code, a),
+        [Mem(a, _)] => Instruction::with1(code, a),
+        [Imm(a)] => match signed(a) {
+            Some(a) => Instruction::with1(code, a),
+            None => Instruction::with1(code, unsigned(a)?),
+        },
+        [Reg(a), Reg(b)] => Instruction::with2(code, a, b),
+        [Reg(a), Mem(b, _)] => Instruction::with2(code, a, b),
+        [Reg(a), Imm(b)] => match (signed(b), unsigned(b)) {
+            (Some(b), _) => Instruction::with2(code, a, b),
+            (_, Some(b)) => Instruction::with2(code, a, b),
+            _ => Instruction::with2(code, a, b),
+        },
+        [Mem(a, _), Reg(b)] => Instruction::with2(code, a, b),
+        [Mem(a, _), Imm(b)] => match signed(b) {
+            Some(b) => Instruction::with2(code, a, b),
+            None => Instruction::with2(code, a, unsigned(b)?),
+        },
+        [Imm(a), Reg(b)] => Instruction::with2(code, signed(a)?, b),
+        [Imm(a), Imm(b)] => Instruction::with2(code, signed(a)?, signed(b)?),
+        [Reg(a), Reg(b), Reg(c)] => Instruction::with3(code, a, b, c),
+        [Reg(a), Reg(b), Mem(c, _)] => Instruction::with3(code, a, b, c),
+        [Reg(a), Reg(b), Imm(c)] => Instruction::with3(code, a, b, signed(c)?),
+        [Reg(a), Mem(b, _), Reg(c)] => Instruction::with3(code, a, b, c),
+        [Reg(a), Mem(b, _), Imm(c)] => Instruction::with3(code, a, b, signed(c)?),
+        [Reg(a), Imm(b), Imm(c)] => Instruction::with3(code, a, signed(b)?, signed(c)?),
+        [Mem(a, _), Reg(b), Reg(c)] => Instruction::with3(code, a, b, c),
+        [Mem(a, _), Reg(b), Imm(c)] => Instruction::with3(code, a, b, signed(c)?),
+        [Reg(a), Reg(b), Reg(c), Reg(d)] => Instruction::with4(code, a, b, c, d),
+        [Reg(a), Reg(b), Reg(c), Mem(d, _)] => Instruction::with4(code, a, b, c, d),
+        [Reg(a), Reg(b), Reg(c), Imm(d)] => Instruction::with4(code, a, b, c, signed(d)?),
+        [Reg(a), Reg(b), Mem(c, _), Reg(d)] => Instruction::with4(code, a, b, c, d),
+        [Reg(a), Reg(b), Mem(c, _), Imm(d)] => Instruction::with4(code, a, b, c, signed(d)?),
+        [Reg(a), Reg(b), Imm(c), Imm(d)] => Instruction::with4(code, a, b, signed(c)?, signed(d)?),
+        _ => return None,
+    };
+
+    instr.ok()
+}
+
+fn parse_operand(op: &str) -> Option<Operand> {
+    let op = op.trim().to_lowercase();
+
+    if let Some(open) = op.find('[') {
+        let close = op.strip_suffix(']')?.len();
+
+        let size = match op[..open].trim().trim_end_matches("ptr").trim() {
+            "" => None,
+            "byte" => Some(1),
+            "word" => Some(2),
+            "dword" => Some(4),
+            "qword" => Some(8),
+            "tbyte" | "tword" => Some(10),
+            "xmmword" => Some(16),
+            "ymmword" => Some(32),
+            "zmmword" => Some(64),
+            _ => return None,
+        };
+
+        return Some(Operand::Mem(parse_memory(&op[open + 1..close])?, size));
+    }
+
+    match register(&op) {
+        Some(reg) => Some(Operand::Reg(reg)),
+        None => parse_imm(&op).map(Operand::Imm),
+    }
+}
+
+/// Parses `base + index*scale + disp` (every part is optional)
+fn parse_memory(mem: &str) -> Option<MemoryOperand> {
+    let mem: String = mem.chars().filter(|c| !c.is_whitespace()).collect();
+
+    let mut base = Register::None;
+    let mut index = Register::None;
+    let mut scale = 1;
+    let mut disp = 0i64;
+
+    let mut terms = vec![];
+    let mut start = 0;
+
+    for (pos, c) in mem.char_indices() {
+        if (c == '+' || c == '-') && pos > start {
+            terms.push(&mem[start..pos]);
+            start = pos;
+        }
+    }
+    terms.push(&mem[start..]);
+
+    for term in terms {
+        let (neg, term) = match term.strip_prefix('-') {
+            Some(term) => (true, term),
+            None => (false, term.strip_prefix('+').unwrap_or(term)),
+        };
+
+        if let Some((a, b)) = term.split_once('*') {
+            let (reg, factor) = match (register(a), register(b)) {
+                (Some(reg), None) => (reg, b),
+                (None, Some(reg)) => (reg, a),
+                _ => return None,
+            };
+
+            if neg || index != Register::None {
+                return None;
+            }
+
+            index = reg;
+            scale = match parse_imm(factor)? {
+                factor @ (1 | 2 | 4 | 8) => factor as u32,
+                _ => return None,
+            };
+        } else if let Some(reg) = register(term) {
+            if neg {
+                return None;
+            }
+
+            if base == Register::None {
+                base = reg;
+            } else if index == Register::None {
+                index = reg;
+            } else {
+                return None;
+            }
+        } else {
+            let value = parse_imm(term)?;
+            disp += if neg { -value } else { value };
+        }
+    }
+
+    let displ_size = if disp == 0 { 0 } else { 1 };
+
+    Some(MemoryOperand::new(base, index, scale, disp, displ_size, false, Register::None))
+}
+
+fn register(name: &str) -> Option<Register> {
+    Register::values()
+        .filter(|reg| *reg != Register::None)
+        .find(|reg| format!("{:?}", reg).to_lowercase() == name)
+}
+
+/// Parses a decimal or hexadecimal (`0x`) number
+fn parse_imm(imm: &str) -> Option<i64> {
+    let (neg, imm) = match imm.strip_prefix('-') {
+        Some(imm) => (true, imm),
+        None => (false, imm),
+    };
+
+    let value = match imm.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()? as i64,
+        None => imm.parse::<u64>().ok()? as i64,
+    };
+
+    Some(if neg { value.wrapping_neg() } else { value })
+}
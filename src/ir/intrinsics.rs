@@ -0,0 +1,204 @@
+//! Built-in functions which are compiled into inline instructions (or into calls of the host libc)
+//!
+//! Intrinsics are ordinary calls of functions with reserved names (`rllvm.<name>`),
+//! so `intrinsics::memcpy(dst, src, len)` is the same as `Call::new("rllvm.memcpy", vec![dst, src, len], None)`.
+//!
+//! Example:
+//!
+//! ```
+//! use std::error::Error;
+//! use rllvm::{contxt::{contxt::Context, jit::JitFunction}, ir::{intrinsics, ir::*, r#type::Type}};
+//! use target_lexicon::Triple;
+//!
+//! fn main() -> Result<(), Box<dyn Error>>{
+//!     let mut contxt = Context::new( Triple::host() )?;
+//!     let func = contxt.add_function("hypot", vec![Type::f64, Type::f64], Type::f64);
+//!     let asm = func.asm_func()?;
+//!
+//!     let x = asm.arg(0).unwrap();
+//!     let y = asm.arg(1).unwrap();
+//!
+//!     func.push( x * x );
+//!     func.push( y * y );
+//!     func.push( x + y );
+//!     func.push( intrinsics::sqrt(x, x) );
+//!     func.push( Return::new(x) );
+//!
+//!     unsafe {
+//!         let mut func: JitFunction<unsafe extern "C" fn(f64, f64) -> f64> = contxt.get_jit_function("hypot")?;
+//!         assert_eq!(func.call(3.0, 4.0), 5.0);
+//!     }
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use super::{ir::Call, var::VarGen};
+
+/// A built-in function
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Intrinsic {
+    /// `memcpy(dst, src, len)` copies `len` bytes (with `rep movsb`)
+    Memcpy,
+    /// `memset(dst, value, len)` sets `len` bytes to the byte `value` (with `rep stosb`)
+    Memset,
+    /// `memmove(dst, src, len)` copies `len` bytes, the memory regions can overlap
+    Memmove,
+    /// `out = sqrt(x)` for floats
+    Sqrt,
+    /// `out = fabs(x)` for floats
+    Fabs,
+    /// `out = min(a, b)` for integers (signed or unsigned depending on the type) and floats
+    Min,
+    /// `out = max(a, b)` for integers (signed or unsigned depending on the type) and floats
+    Max,
+    /// `out = ctpop(x)` counts the set bits
+    Ctpop,
+    /// `out = ctlz(x)` counts the leading zeros (the bit width for zero)
+    Ctlz,
+    /// `out = cttz(x)` counts the trailing zeros (the bit width for zero)
+    Cttz,
+    /// `out = bswap(x)` reverses the bytes
+    Bswap,
+    /// `out = rotl(x, n)` rotates the bits left
+    Rotl,
+    /// `out = rotr(x, n)` rotates the bits right
+    Rotr,
+    /// `out = fma(a, b, c)` computes `a * b + c` with only one rounding
+    Fma,
+}
+
+impl Intrinsic {
+    /// All intrinsics
+    pub const ALL: [Intrinsic; 14] = [
+        Intrinsic::Memcpy, Intrinsic::Memset, Intrinsic::Memmove, Intrinsic::Sqrt, Intrinsic::Fabs, Intrinsic::Min, Intrinsic::Max,
+        Intrinsic::Ctpop, Intrinsic::Ctlz, Intrinsic::Cttz, Intrinsic::Bswap, Intrinsic::Rotl, Intrinsic::Rotr, Intrinsic::Fma,
+    ];
+
+    /// Returns the name of the function which is called
+    pub fn name(&self) -> &'static str {
+        match self {
+            Intrinsic::Memcpy => "rllvm.memcpy",
+            Intrinsic::Memset => "rllvm.memset",
+            Intrinsic::Memmove => "rllvm.memmove",
+            Intrinsic::Sqrt => "rllvm.sqrt",
+            Intrinsic::Fabs => "rllvm.fabs",
+            Intrinsic::Min => "rllvm.min",
+            Intrinsic::Max => "rllvm.max",
+            Intrinsic::Ctpop => "rllvm.ctpop",
+            Intrinsic::Ctlz => "rllvm.ctlz",
+            Intrinsic::Cttz => "rllvm.cttz",
+            Intrinsic::Bswap => "rllvm.bswap",
+            Intrinsic::Rotl => "rllvm.rotl",
+            Intrinsic::Rotr => "rllvm.rotr",
+            Intrinsic::Fma => "rllvm.fma",
+        }
+    }
+
+    /// Returns the intrinsic which is called by the function name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Intrinsic::ALL.into_iter().find(|intrinsic| intrinsic.name() == name)
+    }
+
+    /// Returns the number of arguments
+    pub fn args(&self) -> usize {
+        match self {
+            Intrinsic::Memcpy | Intrinsic::Memset | Intrinsic::Memmove | Intrinsic::Fma => 3,
+            Intrinsic::Min | Intrinsic::Max | Intrinsic::Rotl | Intrinsic::Rotr => 2,
+            _ => 1,
+        }
+    }
+}
+
+fn call(intrinsic: Intrinsic, args: Vec<VarGen>, out: Option<VarGen>) -> Box<Call> {
+    Call::new(intrinsic.name(), args, out)
+}
+
+/// Copies `len` bytes from `src` to `dst`
+pub fn memcpy(dst: VarGen, src: VarGen, len: VarGen) -> Box<Call> {
+    call(Intrinsic::Memcpy, vec![dst, src, len], None)
+}
+
+/// Sets `len` bytes at `dst` to the byte `value`
+pub fn memset(dst: VarGen, value: VarGen, len: VarGen) -> Box<Call> {
+    call(Intrinsic::Memset, vec![dst, value, len], None)
+}
+
+/// Copies `len` bytes from `src` to `dst` (the memory regions can overlap)
+pub fn memmove(dst: VarGen, src: VarGen, len: VarGen) -> Box<Call> {
+    call(Intrinsic::Memmove, vec![dst, src, len], None)
+}
+
+/// Stores the square root of `x` into `out`
+pub fn sqrt(out: VarGen, x: VarGen) -> Box<Call> {
+    call(Intrinsic::Sqrt, vec![x], Some(out))
+}
+
+/// Stores the absolute value of the float `x` into `out`
+pub fn fabs(out: VarGen, x: VarGen) -> Box<Call> {
+    call(Intrinsic::Fabs, vec![x], Some(out))
+}
+
+/// Stores the smaller value into `out`
+pub fn min(out: VarGen, a: VarGen, b: VarGen) -> Box<Call> {
+    call(Intrinsic::Min, vec![a, b], Some(out))
+}
+
+/// Stores the bigger value into `out`
+pub fn max(out: VarGen, a: VarGen, b: VarGen) -> Box<Call> {
+    call(Intrinsic::Max, vec![a, b], Some(out))
+}
+
+/// Stores the number of set bits of `x` into `out`
+pub fn ctpop(out: VarGen, x: VarGen) -> Box<Call> {
+    call(Intrinsic::Ctpop, vec![x], Some(out))
+}
+
+/// Stores the number of leading zeros of `x` into `out`
+pub fn ctlz(out: VarGen, x: VarGen) -> Box<Call> {
+    call(Intrinsic::Ctlz, vec![x], Some(out))
+}
+
+/// Stores the number of trailing zeros of `x` into `out`
+pub fn cttz(out: VarGen, x: VarGen) -> Box<Call> {
+    call(Intrinsic::Cttz, vec![x], Some(out))
+}
+
+/// Stores `x` with reversed bytes into `out`
+pub fn bswap(out: VarGen, x: VarGen) -> Box<Call> {
+    call(Intrinsic::Bswap, vec![x], Some(out))
+}
+
+/// Stores `x` rotated left by `n` bits into `out`
+pub fn rotl(out: VarGen, x: VarGen, n: VarGen) -> Box<Call> {
+    call(Intrinsic::Rotl, vec![x, n], Some(out))
+}
+
+/// Stores `x` rotated right by `n` bits into `out`
+pub fn rotr(out: VarGen, x: VarGen, n: VarGen) -> Box<Call> {
+    call(Intrinsic::Rotr, vec![x, n], Some(out))
+}
+
+/// Stores `a * b + c` into `out` (rounded only once)
+pub fn fma(out: VarGen, a: VarGen, b: VarGen, c: VarGen) -> Box<Call> {
+    call(Intrinsic::Fma, vec![a, b, c], Some(out))
+}
+
+extern "C" fn host_fma(a: f64, b: f64, c: f64) -> f64 {
+    a.mul_add(b, c)
+}
+
+extern "C" fn host_fmaf(a: f32, b: f32, c: f32) -> f32 {
+    a.mul_add(b, c)
+}
+
+/// Returns the address of the host implementation of a libc function which is called by intrinsics
+/// 
+/// Used in jit mode if the libc (or libm) of the host doesn't export the function
+pub fn host_function(name: &str) -> Option<usize> {
+    match name {
+        "fma" => Some(host_fma as *const () as usize),
+        "fmaf" => Some(host_fmaf as *const () as usize),
+        _ => None,
+    }
+}
@@ -1,6 +1,9 @@
 //! RLLVM's ir
 
 pub mod compile;
+pub mod error;
+pub mod intel;
+pub mod intrinsics;
 pub mod var;
 pub mod r#type;
 
@@ -52,9 +55,695 @@ macro_rules! IrTypeWith1 {
     };
 }
 
+macro_rules! IrTypeOverflow {
+    ($name:tt, $op:literal) => {
+        #[doc = concat!("Stores `lhs ", $op, " rhs` into `out` and if it overflowed into the `Type::bool` variable `overflow`")]
+        /// 
+        /// The overflow is checked signed or unsigned depending on the type of `out`.
+        /// With a trap block, it is jumped to on overflow (instead of continuing)
+        pub struct $name {
+            pub out: VarGen,
+            pub overflow: Option<VarGen>,
+            pub lhs: VarGen,
+            pub rhs: VarGen,
+            pub trap: Option<String>,
+        }
+
+        impl $name {
+            /// Creates new instance
+            pub fn new(out: VarGen, overflow: Option<VarGen>, lhs: VarGen, rhs: VarGen) -> Box<Self> {
+                Box::from(
+                    Self {
+                        out,
+                        overflow,
+                        lhs,
+                        rhs,
+                        trap: None,
+                    }
+                )
+            }
+
+            /// Creates new instance which jumps to the trap block on overflow
+            pub fn new_trap(out: VarGen, lhs: VarGen, rhs: VarGen, trap: &str) -> Box<Self> {
+                Box::from(
+                    Self {
+                        out,
+                        overflow: None,
+                        lhs,
+                        rhs,
+                        trap: Some(trap.to_string()),
+                    }
+                )
+            }
+        }
+    };
+}
+
 pub mod ir {
+    use iced_x86::{code_asm::CodeAssembler, IcedError, Register};
+
+    use super::{error::IrError, r#type::Type, var::VarGen};
+
     IrTypeWith2!(Add, AddTrait, T, U);
     IrTypeWith2!(Sub, SubTrait, T, U);
     IrTypeWith2!(Mul, MulTrait, T, U);
+    IrTypeWith2!(Div, DivTrait, T, U);
+    IrTypeWith2!(Rem, RemTrait, T, U);
+    IrTypeWith2!(And, AndTrait, T, U);
+    IrTypeWith2!(Or, OrTrait, T, U);
+    IrTypeWith2!(Xor, XorTrait, T, U);
+    IrTypeWith2!(Shl, ShlTrait, T, U);
+    IrTypeWith2!(LShr, LShrTrait, T, U);
+    IrTypeWith2!(AShr, AShrTrait, T, U);
+    IrTypeWith1!(Not, NotTrait, T);
+    IrTypeWith1!(Neg, NegTrait, T);
     IrTypeWith1!(Return, ReturnTrait, T);
-}
\ No newline at end of file
+    IrTypeWith1!(Alloca, AllocaTrait, T);
+    IrTypeWith2!(Load, LoadTrait, T, U);
+    IrTypeWith2!(Store, StoreTrait, T, U);
+    IrTypeWith2!(ZExt, ZExtTrait, T, U);
+    IrTypeWith2!(SExt, SExtTrait, T, U);
+    IrTypeWith2!(Trunc, TruncTrait, T, U);
+    IrTypeWith2!(SIToFP, SIToFPTrait, T, U);
+    IrTypeWith2!(UIToFP, UIToFPTrait, T, U);
+    IrTypeWith2!(FPToSI, FPToSITrait, T, U);
+    IrTypeWith2!(FPToUI, FPToUITrait, T, U);
+    IrTypeWith2!(FPExt, FPExtTrait, T, U);
+    IrTypeWith2!(FPTrunc, FPTruncTrait, T, U);
+    IrTypeWith2!(Bitcast, BitcastTrait, T, U);
+
+    /// The condition of an integer compare
+    #[allow(non_camel_case_types)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ICmpCond {
+        eq, ne,
+        slt, sle, sgt, sge,
+        ult, ule, ugt, uge,
+    }
+
+    /// The condition of a floating point compare
+    /// 
+    /// Ordered conditions are false if one operand is NaN, unordered ones are true
+    #[allow(non_camel_case_types)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum FCmpCond {
+        oeq, one, olt, ole, ogt, oge, ord,
+        ueq, une, ult, ule, ugt, uge, uno,
+    }
+
+    /// Compares two integers and stores the result as a `Type::bool` into `out`
+    pub struct ICmp<T, U> {
+        pub cond: ICmpCond,
+        pub inner1: T,
+        pub inner2: U,
+        pub out: VarGen,
+    }
+
+    impl<T, U> ICmp<T, U> {
+        /// Creates new instance
+        pub fn new(cond: ICmpCond, op0: T, op1: U, out: VarGen) -> Box<Self> {
+            Box::from(
+                Self {
+                    cond,
+                    inner1: op0,
+                    inner2: op1,
+                    out,
+                }
+            )
+        }
+    }
+
+    /// Compares two floats and stores the result as a `Type::bool` into `out`
+    pub struct FCmp<T, U> {
+        pub cond: FCmpCond,
+        pub inner1: T,
+        pub inner2: U,
+        pub out: VarGen,
+    }
+
+    impl<T, U> FCmp<T, U> {
+        /// Creates new instance
+        pub fn new(cond: FCmpCond, op0: T, op1: U, out: VarGen) -> Box<Self> {
+            Box::from(
+                Self {
+                    cond,
+                    inner1: op0,
+                    inner2: op1,
+                    out,
+                }
+            )
+        }
+    }
+
+    /// Jumps to the block with the given name
+    pub struct Br {
+        pub block: String,
+    }
+
+    impl Br {
+        /// Creates new instance
+        pub fn new(block: &str) -> Box<Self> {
+            Box::from(
+                Self {
+                    block: block.to_string(),
+                }
+            )
+        }
+    }
+
+    /// Jumps to the block `then` if the `Type::bool` variable is true, else to the block `otherwise`
+    pub struct CondBr {
+        pub cond: VarGen,
+        pub then: String,
+        pub otherwise: String,
+    }
+
+    impl CondBr {
+        /// Creates new instance
+        pub fn new(cond: VarGen, then: &str, otherwise: &str) -> Box<Self> {
+            Box::from(
+                Self {
+                    cond,
+                    then: then.to_string(),
+                    otherwise: otherwise.to_string(),
+                }
+            )
+        }
+    }
+
+    /// Merges the values which flow in from the predecessor blocks into `out`
+    /// 
+    /// The values are copied into `out` at the end of the predecessor blocks
+    pub struct Phi {
+        pub out: VarGen,
+        pub incoming: Vec<(String, VarGen)>,
+    }
+
+    impl Phi {
+        /// Creates new instance (`incoming` is a list of the predecessor blocks and their values)
+        pub fn new(out: VarGen, incoming: Vec<(&str, VarGen)>) -> Box<Self> {
+            Box::from(
+                Self {
+                    out,
+                    incoming: incoming.into_iter().map(|(block, value)| (block.to_string(), value)).collect(),
+                }
+            )
+        }
+    }
+
+    /// Calls the function with the given name and stores the returned value into `out`
+    /// 
+    /// The arguments are passed like the calling convention of the target says
+    /// and all variables are preserved across the call
+    pub struct Call {
+        pub func: String,
+        pub args: Vec<VarGen>,
+        pub out: Option<VarGen>,
+        /// The number of fixed arguments if the function is variadic (like `printf`)
+        pub variadic: Option<usize>,
+    }
+
+    impl Call {
+        /// Creates new instance
+        pub fn new(func: &str, args: Vec<VarGen>, out: Option<VarGen>) -> Box<Self> {
+            Box::from(
+                Self {
+                    func: func.to_string(),
+                    args,
+                    out,
+                    variadic: None,
+                }
+            )
+        }
+
+        /// Creates new instance which calls a variadic function (the arguments after the `fixed` ones are the variadic ones)
+        /// 
+        /// The variadic arguments are promoted like in C (floats to doubles, small integers to 32 bit integers)
+        pub fn new_variadic(func: &str, args: Vec<VarGen>, fixed: usize, out: Option<VarGen>) -> Box<Self> {
+            Box::from(
+                Self {
+                    func: func.to_string(),
+                    args,
+                    out,
+                    variadic: Some(fixed),
+                }
+            )
+        }
+    }
+
+    /// Computes the address `base + index * scale + offset` into the pointer `out`
+    /// 
+    /// The base can also be a stack slot (from `AsmFunction::alloca`), then the address of the slot is used
+    pub struct GetElementPtr {
+        pub out: VarGen,
+        pub base: VarGen,
+        pub index: Option<VarGen>,
+        pub scale: usize,
+        pub offset: isize,
+    }
+
+    impl GetElementPtr {
+        /// Creates new instance
+        pub fn new(out: VarGen, base: VarGen, index: Option<VarGen>, scale: usize, offset: isize) -> Box<Self> {
+            Box::from(
+                Self {
+                    out,
+                    base,
+                    index,
+                    scale,
+                    offset,
+                }
+            )
+        }
+
+        /// Computes the address of the element with the given index (the base needs to be a typed pointer)
+        pub fn element(out: VarGen, base: VarGen, index: VarGen) -> Result<Box<Self>, IrError> {
+            match base.typ.pointee() {
+                Some(typ) => Ok(GetElementPtr::element_of(out, base, index, typ)),
+                None => Err(IrError::UnsupportedType(format!("{} (in GetElementPtr::element)", base.typ.name()))),
+            }
+        }
+
+        /// Computes the address of the element with the given index in an array of `typ` (for untyped pointers)
+        pub fn element_of(out: VarGen, base: VarGen, index: VarGen, typ: Type) -> Box<Self> {
+            GetElementPtr::new(out, base, Some(index), typ.size(), 0)
+        }
+
+        /// Computes the address of the field (or array element) with the given index
+        /// 
+        /// The base needs to be a typed pointer to a struct/array or a stack slot of a struct/array
+        pub fn field(out: VarGen, base: VarGen, nr: usize) -> Result<Box<Self>, IrError> {
+            let typ = base.typ.pointee().unwrap_or(base.typ);
+
+            match typ.offset(nr) {
+                Some(offset) => Ok(GetElementPtr::new(out, base, None, 1, offset as isize)),
+                None => Err(IrError::InvalidField(nr, typ.to_string())),
+            }
+        }
+    }
+
+    /// Stores the address of the global variable with the given name (see `Context::add_global`) into the pointer `out`
+    pub struct GlobalAddr {
+        pub out: VarGen,
+        pub name: String,
+    }
+
+    impl GlobalAddr {
+        /// Creates new instance
+        pub fn new(out: VarGen, name: &str) -> Box<Self> {
+            Box::from(
+                Self {
+                    out,
+                    name: name.to_string(),
+                }
+            )
+        }
+    }
+
+    /// Jumps to the block of the case which equals the value (or to the `default` block if no case matches)
+    /// 
+    /// Depending on the number of cases and how dense they are, it is lowered to
+    /// a chain of compares, a binary search or a jump table
+    pub struct Switch {
+        pub value: VarGen,
+        pub default: String,
+        pub cases: Vec<(i64, String)>,
+    }
+
+    impl Switch {
+        /// Creates new instance (`cases` is a list of the values and their blocks)
+        pub fn new(value: VarGen, default: &str, cases: Vec<(i64, &str)>) -> Box<Self> {
+            Box::from(
+                Self {
+                    value,
+                    default: default.to_string(),
+                    cases: cases.into_iter().map(|(value, block)| (value, block.to_string())).collect(),
+                }
+            )
+        }
+    }
+
+    /// Builds the vector `out` from the lanes of the vectors `a` and `b` (like LLVM's `shufflevector`)
+    /// 
+    /// Every mask entry is one lane of the result: entries below the number of lanes select a lane of `a`,
+    /// the others a lane of `b`
+    pub struct Shuffle {
+        pub out: VarGen,
+        pub a: VarGen,
+        pub b: VarGen,
+        pub mask: Vec<usize>,
+    }
+
+    impl Shuffle {
+        /// Creates new instance
+        pub fn new(out: VarGen, a: VarGen, b: VarGen, mask: Vec<usize>) -> Box<Self> {
+            Box::from(
+                Self {
+                    out,
+                    a,
+                    b,
+                    mask,
+                }
+            )
+        }
+    }
+
+    /// Stores the lane `index` of the vector into the scalar `out`
+    pub struct ExtractElement {
+        pub out: VarGen,
+        pub vector: VarGen,
+        pub index: usize,
+    }
+
+    impl ExtractElement {
+        /// Creates new instance
+        pub fn new(out: VarGen, vector: VarGen, index: usize) -> Box<Self> {
+            Box::from(
+                Self {
+                    out,
+                    vector,
+                    index,
+                }
+            )
+        }
+    }
+
+    /// Replaces the lane `index` of the vector with the scalar `value`
+    pub struct InsertElement {
+        pub vector: VarGen,
+        pub value: VarGen,
+        pub index: usize,
+    }
+
+    impl InsertElement {
+        /// Creates new instance
+        pub fn new(vector: VarGen, value: VarGen, index: usize) -> Box<Self> {
+            Box::from(
+                Self {
+                    vector,
+                    value,
+                    index,
+                }
+            )
+        }
+    }
+
+    /// The operation which combines the lanes of a vector in `Reduce`
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ReduceOp {
+        Add,
+        Mul,
+        And,
+        Or,
+        Xor,
+        Min,
+        Max,
+    }
+
+    /// Combines all lanes of the vector with the operation into the scalar `out` (a horizontal reduction)
+    pub struct Reduce {
+        pub op: ReduceOp,
+        pub out: VarGen,
+        pub vector: VarGen,
+    }
+
+    impl Reduce {
+        /// Creates new instance
+        pub fn new(op: ReduceOp, out: VarGen, vector: VarGen) -> Box<Self> {
+            Box::from(
+                Self {
+                    op,
+                    out,
+                    vector,
+                }
+            )
+        }
+    }
+
+    /// The memory ordering of an atomic operation (like `std::sync::atomic::Ordering`)
+    /// 
+    /// On x86 every load is an acquire and every store a release, so only `SeqCst` stores and fences need extra instructions
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum AtomicOrdering {
+        Relaxed,
+        Acquire,
+        Release,
+        AcqRel,
+        SeqCst,
+    }
+
+    /// Atomically loads the value the pointer points to into `out`
+    pub struct AtomicLoad {
+        pub out: VarGen,
+        pub ptr: VarGen,
+        pub ordering: AtomicOrdering,
+    }
+
+    impl AtomicLoad {
+        /// Creates new instance
+        pub fn new(out: VarGen, ptr: VarGen, ordering: AtomicOrdering) -> Box<Self> {
+            Box::from(
+                Self {
+                    out,
+                    ptr,
+                    ordering,
+                }
+            )
+        }
+    }
+
+    /// Atomically stores the value where the pointer points to
+    pub struct AtomicStore {
+        pub ptr: VarGen,
+        pub value: VarGen,
+        pub ordering: AtomicOrdering,
+    }
+
+    impl AtomicStore {
+        /// Creates new instance
+        pub fn new(ptr: VarGen, value: VarGen, ordering: AtomicOrdering) -> Box<Self> {
+            Box::from(
+                Self {
+                    ptr,
+                    value,
+                    ordering,
+                }
+            )
+        }
+    }
+
+    /// The operation of an `AtomicRMW`
+    /// 
+    /// `Min` and `Max` compare signed or unsigned depending on the type of the value
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum AtomicOp {
+        Add,
+        Sub,
+        And,
+        Or,
+        Xor,
+        Xchg,
+        Min,
+        Max,
+    }
+
+    /// Atomically replaces the value the pointer points to with `old (op) value` and stores the old value into `out`
+    pub struct AtomicRMW {
+        pub op: AtomicOp,
+        pub out: VarGen,
+        pub ptr: VarGen,
+        pub value: VarGen,
+        pub ordering: AtomicOrdering,
+    }
+
+    impl AtomicRMW {
+        /// Creates new instance
+        pub fn new(op: AtomicOp, out: VarGen, ptr: VarGen, value: VarGen, ordering: AtomicOrdering) -> Box<Self> {
+            Box::from(
+                Self {
+                    op,
+                    out,
+                    ptr,
+                    value,
+                    ordering,
+                }
+            )
+        }
+    }
+
+    /// Atomically replaces the value the pointer points to with `new` if it equals `expected`
+    /// 
+    /// The old value is stored into `out` and if the exchange happened into the `Type::bool` variable `success`
+    pub struct CmpXchg {
+        pub out: VarGen,
+        pub success: Option<VarGen>,
+        pub ptr: VarGen,
+        pub expected: VarGen,
+        pub new: VarGen,
+        pub ordering: AtomicOrdering,
+    }
+
+    impl CmpXchg {
+        /// Creates new instance
+        pub fn new(out: VarGen, success: Option<VarGen>, ptr: VarGen, expected: VarGen, new: VarGen, ordering: AtomicOrdering) -> Box<Self> {
+            Box::from(
+                Self {
+                    out,
+                    success,
+                    ptr,
+                    expected,
+                    new,
+                    ordering,
+                }
+            )
+        }
+    }
+
+    /// Orders the memory accesses before and after it
+    pub struct Fence {
+        pub ordering: AtomicOrdering,
+    }
+
+    impl Fence {
+        /// Creates new instance
+        pub fn new(ordering: AtomicOrdering) -> Box<Self> {
+            Box::from(
+                Self {
+                    ordering,
+                }
+            )
+        }
+    }
+
+    IrTypeOverflow!(AddWithOverflow, "+");
+    IrTypeOverflow!(SubWithOverflow, "-");
+    IrTypeOverflow!(MulWithOverflow, "*");
+
+    /// Stops the execution because of an error with the given code
+    /// 
+    /// Compiled into `ud2`, or into a call of the trap handler if the context has one
+    pub struct Trap {
+        pub code: u32,
+    }
+
+    impl Trap {
+        /// Creates new instance
+        pub fn new(code: u32) -> Box<Self> {
+            Box::from(
+                Self {
+                    code,
+                }
+            )
+        }
+    }
+
+    /// Marks code which is never executed
+    /// 
+    /// Compiled like a `Trap` with the code `Unreachable::CODE`
+    #[derive(Default)]
+    pub struct Unreachable {}
+
+    impl Unreachable {
+        /// The trap code which the trap handler gets
+        pub const CODE: u32 = u32::MAX;
+
+        /// Creates new instance
+        pub fn new() -> Box<Self> {
+            Box::from(
+                Self {}
+            )
+        }
+    }
+
+    /// Calls the function with the given name in the frame of the current function and returns its result
+    /// 
+    /// The stack frame is torn down before the function is jumped to, so the stack doesn't grow.
+    /// The arguments which are passed on the stack need to fit into the stack arguments of the current function
+    pub struct TailCall {
+        pub func: String,
+        pub args: Vec<VarGen>,
+    }
+
+    impl TailCall {
+        /// Creates new instance
+        pub fn new(func: &str, args: Vec<VarGen>) -> Box<Self> {
+            Box::from(
+                Self {
+                    func: func.to_string(),
+                    args,
+                }
+            )
+        }
+    }
+
+    /// A closure which adds instructions to the assembler
+    pub type AsmClosure = Box<dyn Fn(&mut CodeAssembler) -> Result<(), IcedError>>;
+
+    /// The code of an `InlineAsm`
+    pub enum AsmCode {
+        /// A closure which adds the instructions to the assembler
+        Closure(AsmClosure),
+        /// Intel syntax assembly (see `ir::intel`)
+        Intel(String),
+    }
+
+    /// Embeds assembly into the function
+    /// 
+    /// The `inputs` are moved into their registers before the code and the `outputs` are moved
+    /// out of their registers after it. Variables which live in a clobbered (or input/output) register are saved around the code,
+    /// callee saved registers are saved by the prologue (and restored before returning). RSP and RBP can't be changed
+    pub struct InlineAsm {
+        pub code: AsmCode,
+        pub inputs: Vec<(Register, VarGen)>,
+        pub outputs: Vec<(VarGen, Register)>,
+        pub clobbers: Vec<Register>,
+    }
+
+    impl InlineAsm {
+        /// Creates new instance which runs the closure on the assembler
+        pub fn new(code: impl Fn(&mut CodeAssembler) -> Result<(), IcedError> + 'static, inputs: Vec<(Register, VarGen)>, outputs: Vec<(VarGen, Register)>, clobbers: Vec<Register>) -> Box<Self> {
+            Box::from(
+                Self {
+                    code: AsmCode::Closure(Box::new(code)),
+                    inputs,
+                    outputs,
+                    clobbers,
+                }
+            )
+        }
+
+        /// Creates new instance from intel syntax assembly
+        pub fn intel(code: &str, inputs: Vec<(Register, VarGen)>, outputs: Vec<(VarGen, Register)>, clobbers: Vec<Register>) -> Box<Self> {
+            Box::from(
+                Self {
+                    code: AsmCode::Intel(code.to_string()),
+                    inputs,
+                    outputs,
+                    clobbers,
+                }
+            )
+        }
+    }
+
+    /// Stores `a` into `out` if `cond` isn't zero, otherwise `b` (without a branch for integers)
+    pub struct Select {
+        pub out: VarGen,
+        pub cond: VarGen,
+        pub a: VarGen,
+        pub b: VarGen,
+    }
+
+    impl Select {
+        /// Creates new instance
+        pub fn new(out: VarGen, cond: VarGen, a: VarGen, b: VarGen) -> Box<Self> {
+            Box::from(
+                Self {
+                    out,
+                    cond,
+                    a,
+                    b,
+                }
+            )
+        }
+    }
+}
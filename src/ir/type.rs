@@ -1,5 +1,9 @@
 #![allow(non_camel_case_types)]
 
+use std::{collections::HashSet, fmt, hash::Hash, sync::{Mutex, OnceLock}};
+
+use target_lexicon::{Architecture, OperatingSystem, PointerWidth, Triple};
+
 /// Stores Type information 
 /// used for arguments and return types
 /// 
@@ -13,38 +17,115 @@
 /// assert_eq!(Type::i8.size(), 1);
 /// assert_eq!(Type::i32.name(), "i32");
 /// ```
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Type {
+    /// No value (e.g. the return type of a function which returns nothing)
+    void,
+
+    /// 128 bit integers (passed in two registers on linux and by reference on windows)
+    u128,
     u64,
     u32,
     u16,
     u8,
 
+    i128,
     i64,
     i32,
     i16,
     i8,
 
+    /// Pointer sized integers (the size depends on the pointer width of the target)
+    usize,
+    isize,
+
     f64,
     f32,
+
+    bool,
+
+    /// A pointer to anything
+    ptr,
+    /// A pointer to a value of the given type (see `Type::ptr_to`)
+    typed_ptr(&'static Type),
+
+    /// A struct with the given fields (see `Type::new_struct`)
+    r#struct(&'static StructType),
+    /// A fixed size array (see `Type::array_of`)
+    array(&'static Type, usize),
+    /// A SIMD vector with the given number of lanes (see `Type::vector_of`)
+    vector(&'static Type, usize),
+}
+
+/// The fields of a struct type
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StructType {
+    pub fields: Vec<Type>,
+    /// If set, the fields aren't aligned (like `#[repr(packed)]`)
+    pub packed: bool,
+}
+
+/// The parts of the target which change the layout of types
+struct Layout {
+    /// Alignment of 8 byte scalars
+    wide_align: usize,
+    ptr_size: usize,
+}
+
+impl Layout {
+    fn new(triple: &Triple) -> Self {
+        let wide_align = match triple.architecture {
+            Architecture::X86_32(_) if triple.operating_system != OperatingSystem::Windows => 4,
+            _ => 8,
+        };
+
+        let ptr_size = match triple.pointer_width() {
+            Ok(PointerWidth::U16) => 2,
+            Ok(PointerWidth::U32) => 4,
+            _ => 8,
+        };
+
+        Self { wide_align, ptr_size }
+    }
+
+    /// The layout which is used by the code generation (x86-64)
+    fn native() -> Self {
+        Self { wide_align: 8, ptr_size: 8 }
+    }
 }
 
 impl Type {
-    /// Returns the size of the type
+    /// Returns the size of the type on x86-64 (the target of the code generation, see `size_in` for other targets)
     /// Example:
     /// ```rust
     /// use rllvm::ir::r#type::Type;
-    /// assert_eq!(Type::i16.size(), 2)
+    /// use target_lexicon::Triple;
+    /// 
+    /// assert_eq!(Type::i16.size(), 2);
+    /// assert_eq!(Type::usize.size(), Type::usize.size_in(&"x86_64-unknown-linux-gnu".parse::<Triple>().unwrap()));
+    /// assert_eq!(Type::usize.size_in(&"i686-unknown-linux-gnu".parse::<Triple>().unwrap()), 4);
     /// ```
     pub fn size(&self) -> usize {
         match self {
+            Type::void => 0,
+
+            Type::u128 | Type::i128 => 16,
             Type::u64 | Type::i64 => 8,
             Type::u32 | Type::i32 => 4,
             Type::u16 | Type::i16 => 2,
             Type::u8  | Type::i8  => 1,
 
+            Type::usize | Type::isize => self.layout_size(&Layout::native()),
+
             Type::f64 => 8,
             Type::f32 => 4,
+
+            Type::bool => 1,
+
+            Type::ptr | Type::typed_ptr(_) => self.layout_size(&Layout::native()),
+
+            Type::r#struct(_) | Type::array(..) => self.layout_size(&Layout::native()),
+            Type::vector(typ, lanes) => typ.size() * lanes,
         }
     }
 
@@ -52,11 +133,18 @@ impl Type {
     /// stored on the stack
     pub fn stack(&self) -> bool {
         match self {
+            Type::void => false,
+            Type::u128 | Type::i128 => true,
             Type::u64 | Type::i64 => false,
             Type::u32 | Type::i32 => false,
             Type::u16 | Type::i16 => false,
             Type::u8  | Type::i8  => false,
+            Type::usize | Type::isize => false,
             Type::f32 | Type::f64 => false,
+            Type::bool => false,
+            Type::ptr | Type::typed_ptr(_) => false,
+            Type::r#struct(_) | Type::array(..) => true,
+            Type::vector(..) => false,
         }
     }
 
@@ -64,27 +152,282 @@ impl Type {
     /// stored in registers
     pub fn reg(&self) -> bool {
         match self {
+            Type::void => false,
+            Type::u128 | Type::i128 => false, // needs two registers
             Type::u64 | Type::i64 => true,
             Type::u32 | Type::i32 => true,
             Type::u16 | Type::i16 => true,
             Type::u8  | Type::i8  => true,
+            Type::usize | Type::isize => true,
             Type::f32 | Type::f64 => true,
+            Type::bool => true,
+            Type::ptr | Type::typed_ptr(_) => true,
+            Type::r#struct(_) | Type::array(..) => false,
+            Type::vector(..) => true, // xmm (or ymm) registers
         }
     }
 
     /// Returns the name of the types
     pub fn name(&self) -> &str {
         match self {
+            Type::void => "void",
+            Type::u128 => "u128",
             Type::u64 => "u64",
             Type::u32 => "u32",
             Type::u16 => "u16",
             Type::u8 => "u8",
+            Type::i128 => "i128",
             Type::i64 => "i64",
             Type::i32 => "i32",
             Type::i16 => "i16",
             Type::i8 => "i8",
+            Type::usize => "usize",
+            Type::isize => "isize",
             Type::f64 => "f64",
             Type::f32 => "f32",
+            Type::bool => "bool",
+            Type::ptr | Type::typed_ptr(_) => "ptr",
+            Type::r#struct(_) => "struct",
+            Type::array(..) => "array",
+            Type::vector(..) => "vector",
         }
     }
-}
\ No newline at end of file
+
+    /// Returns if the type is a signed integer
+    pub fn signed(&self) -> bool {
+        matches!(self, Type::i128 | Type::i64 | Type::i32 | Type::i16 | Type::i8 | Type::isize)
+    }
+
+    /// Returns if the type is a 128 bit integer
+    pub fn wide(&self) -> bool {
+        matches!(self, Type::u128 | Type::i128)
+    }
+
+    /// Returns if the type is a floating point type
+    pub fn float(&self) -> bool {
+        matches!(self, Type::f64 | Type::f32)
+    }
+
+    /// Returns if the type is a pointer
+    pub fn pointer(&self) -> bool {
+        matches!(self, Type::ptr | Type::typed_ptr(_))
+    }
+
+    /// Returns a pointer type which points to values of the given type
+    /// 
+    /// Equal types share one allocation, so creating them again doesn't allocate
+    /// 
+    /// Example:
+    /// ```rust
+    /// use rllvm::ir::r#type::Type;
+    /// 
+    /// let typ = Type::ptr_to(Type::u32);
+    /// assert_eq!(typ.pointee(), Some(Type::u32));
+    /// assert_eq!(typ.size(), 8);
+    /// 
+    /// if let (Type::typed_ptr(a), Type::typed_ptr(b)) = (typ, Type::ptr_to(Type::u32)) {
+    ///     assert!(std::ptr::eq(a, b));
+    /// }
+    /// ```
+    pub fn ptr_to(typ: Type) -> Type {
+        Type::typed_ptr(intern(&TYPES, typ))
+    }
+
+    /// Returns the type the pointer points to (if it is a typed pointer)
+    pub fn pointee(&self) -> Option<Type> {
+        match self {
+            Type::typed_ptr(typ) => Some(**typ),
+            _ => None,
+        }
+    }
+
+    /// Returns a struct type with the given fields
+    /// 
+    /// The fields are laid out like a `#[repr(C)]` (or `#[repr(C, packed)]`) struct
+    pub fn new_struct(fields: Vec<Type>, packed: bool) -> Type {
+        Type::r#struct(intern(&STRUCTS, StructType { fields, packed }))
+    }
+
+    /// Returns an array type with `len` elements of the given type
+    pub fn array_of(typ: Type, len: usize) -> Type {
+        Type::array(intern(&TYPES, typ), len)
+    }
+
+    /// Returns a vector type with `lanes` elements of the given type
+    /// 
+    /// Vectors are 128 bit (e.g. `<4 x f32>`) or 256 bit (e.g. `<8 x f32>`, needs AVX) wide
+    /// 
+    /// Example:
+    /// ```rust
+    /// use rllvm::ir::r#type::Type;
+    /// 
+    /// let typ = Type::vector_of(Type::f32, 4);
+    /// assert_eq!(typ.size(), 16);
+    /// assert_eq!(typ.field(3), Some(Type::f32));
+    /// ```
+    pub fn vector_of(typ: Type, lanes: usize) -> Type {
+        Type::vector(intern(&TYPES, typ), lanes)
+    }
+
+    /// Returns if the type is a SIMD vector
+    pub fn vector(&self) -> bool {
+        matches!(self, Type::vector(..))
+    }
+
+    /// Returns if the type is a struct or an array
+    pub fn aggregate(&self) -> bool {
+        matches!(self, Type::r#struct(_) | Type::array(..))
+    }
+
+    /// Returns the type of the field (or array element) with the given index
+    pub fn field(&self, nr: usize) -> Option<Type> {
+        match self {
+            Type::r#struct(typ) => typ.fields.get(nr).copied(),
+            Type::array(typ, len) | Type::vector(typ, len) if nr < *len => Some(**typ),
+            _ => None,
+        }
+    }
+
+    /// Returns the alignment of the type (on x86-64)
+    pub fn align(&self) -> usize {
+        self.layout_align(&Layout::native())
+    }
+
+    /// Returns the offset of the field (or array element) with the given index (on x86-64)
+    /// 
+    /// Example:
+    /// ```rust
+    /// use rllvm::ir::r#type::Type;
+    /// 
+    /// let typ = Type::new_struct(vec![Type::u8, Type::u32, Type::u16], false);
+    /// assert_eq!(typ.offset(1), Some(4));
+    /// assert_eq!(typ.size(), 12);
+    /// ```
+    pub fn offset(&self, nr: usize) -> Option<usize> {
+        self.layout_offset(nr, &Layout::native())
+    }
+
+    /// Returns the size of the type on the given target
+    /// 
+    /// Example:
+    /// ```rust
+    /// use rllvm::ir::r#type::Type;
+    /// use target_lexicon::Triple;
+    /// 
+    /// let typ = Type::new_struct(vec![Type::u32, Type::f64], false);
+    /// assert_eq!(typ.size_in(&"x86_64-unknown-linux-gnu".parse::<Triple>().unwrap()), 16);
+    /// assert_eq!(typ.size_in(&"i686-unknown-linux-gnu".parse::<Triple>().unwrap()), 12);
+    /// ```
+    pub fn size_in(&self, triple: &Triple) -> usize {
+        self.layout_size(&Layout::new(triple))
+    }
+
+    /// Returns the alignment of the type on the given target
+    pub fn align_in(&self, triple: &Triple) -> usize {
+        self.layout_align(&Layout::new(triple))
+    }
+
+    /// Returns the offset of the field (or array element) with the given index on the given target
+    pub fn offset_in(&self, nr: usize, triple: &Triple) -> Option<usize> {
+        self.layout_offset(nr, &Layout::new(triple))
+    }
+
+    fn layout_size(&self, layout: &Layout) -> usize {
+        match self {
+            Type::r#struct(typ) => {
+                let mut size = 0;
+
+                for field in &typ.fields {
+                    size = align_up(size, field.layout_align(layout), typ.packed) + field.layout_size(layout);
+                }
+
+                align_up(size, self.layout_align(layout), typ.packed)
+            },
+            Type::array(typ, len) | Type::vector(typ, len) => typ.layout_size(layout) * len,
+            Type::ptr | Type::typed_ptr(_) => layout.ptr_size,
+            Type::usize | Type::isize => layout.ptr_size,
+            _ => self.size(),
+        }
+    }
+
+    fn layout_align(&self, layout: &Layout) -> usize {
+        match self {
+            Type::r#struct(typ) if typ.packed => 1,
+            Type::r#struct(typ) => typ.fields.iter().map(|field| field.layout_align(layout)).max().unwrap_or(1),
+            Type::array(typ, _) => typ.layout_align(layout),
+            Type::u128 | Type::i128 => 16,
+            Type::vector(..) => self.layout_size(layout),
+            _ => self.layout_size(layout).clamp(1, layout.wide_align),
+        }
+    }
+
+    fn layout_offset(&self, nr: usize, layout: &Layout) -> Option<usize> {
+        match self {
+            Type::r#struct(typ) => {
+                let mut offset = 0;
+
+                for (index, field) in typ.fields.iter().enumerate() {
+                    offset = align_up(offset, field.layout_align(layout), typ.packed);
+
+                    if index == nr {
+                        return Some(offset);
+                    }
+
+                    offset += field.layout_size(layout);
+                }
+
+                None
+            },
+            Type::array(typ, len) | Type::vector(typ, len) if nr < *len => Some(typ.layout_size(layout) * nr),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    /// Writes the name of the type (aggregates with their elements, like `<4 x f32>` or `{ i32, f64 }`)
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::r#struct(st) => {
+                let fields: Vec<String> = st.fields.iter().map(|field| field.to_string()).collect();
+
+                if st.packed {
+                    write!(f, "<{{ {} }}>", fields.join(", "))
+                } else {
+                    write!(f, "{{ {} }}", fields.join(", "))
+                }
+            },
+            Type::array(elem, len) => write!(f, "[{} x {}]", len, elem),
+            Type::vector(elem, lanes) => write!(f, "<{} x {}>", lanes, elem),
+            _ => write!(f, "{}", self.name()),
+        }
+    }
+}
+
+static TYPES: OnceLock<Mutex<HashSet<&'static Type>>> = OnceLock::new();
+static STRUCTS: OnceLock<Mutex<HashSet<&'static StructType>>> = OnceLock::new();
+
+/// Returns the allocation of the value which is shared by all equal values
+/// 
+/// The values are leaked, so `Type` stays copyable, but every distinct value is only allocated once
+fn intern<T: Eq + Hash + Sync>(pool: &'static OnceLock<Mutex<HashSet<&'static T>>>, value: T) -> &'static T {
+    let mut pool = pool.get_or_init(Default::default).lock().unwrap();
+
+    if let Some(known) = pool.get(&value) {
+        return known;
+    }
+
+    let value = Box::leak(Box::new(value));
+    pool.insert(value);
+
+    value
+}
+
+/// Rounds `offset` up to the next multiple of `align` (if not packed)
+fn align_up(offset: usize, align: usize, packed: bool) -> usize {
+    if packed {
+        offset
+    } else {
+        offset.next_multiple_of(align)
+    }
+}
@@ -1,9 +1,10 @@
-use std::{error::Error, ops::{Add, Mul, Sub}};
+use std::{error::Error, fmt, ops::{Add, BitAnd, BitOr, BitXor, Div, Mul, Neg, Not, Rem, Shl, Shr, Sub}};
 
 use iced_x86::{code_asm::*, Code, Instruction, MemoryOperand, Register};
-use super::r#type::Type;
+use super::{compile::Compile, error::IrError, ir::{FCmp, FCmpCond, ICmp, ICmpCond}, r#type::Type};
 
 /// A variable code generation helper
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct VarGen {
     pub on_stack: bool,
     pub in_reg: bool,
@@ -54,7 +55,7 @@ impl VarGen {
     /// Mov the value from the target register into the register in which the var is stored
     pub fn set_reg(&mut self, target: Register, asm: &mut CodeAssembler) -> Result<(), Box<dyn Error>> {
         match self.typ {
-            Type::u64 | Type::i64 => {
+            Type::u64 | Type::i64 | Type::usize | Type::isize | Type::ptr | Type::typed_ptr(_) => {
                 asm.add_instruction(
                     Instruction::with2(Code::Mov_rm64_r64, self.reg, target)?
                 )?;
@@ -69,11 +70,14 @@ impl VarGen {
                     Instruction::with2(Code::Mov_rm16_r16, self.reg, target)?
                 )?;
             },
-            Type::u8 | Type::i8 => {
+            Type::u8 | Type::i8 | Type::bool => {
                 asm.add_instruction(
                     Instruction::with2(Code::Mov_rm8_r8, self.reg, target)?
                 )?;
             },
+            Type::void | Type::u128 | Type::i128 | Type::r#struct(_) | Type::array(..) => {
+                return Err(Box::from(IrError::UnsupportedType(self.typ.name().to_string())));
+            },
             Type::f32 => {
                 asm.add_instruction(
                     Instruction::with2(Code::Movss_xmm_xmmm32, self.reg, target)?
@@ -83,6 +87,13 @@ impl VarGen {
                 asm.add_instruction(
                     Instruction::with2(Code::Movq_xmm_xmmm64, self.reg, target)?
                 )?;
+            },
+            Type::vector(..) => {
+                let code = if self.reg.is_ymm() { Code::VEX_Vmovaps_ymm_ymmm256 } else { Code::Movaps_xmm_xmmm128 };
+
+                asm.add_instruction(
+                    Instruction::with2(code, self.reg, target)?
+                )?;
             }
         };
 
@@ -101,12 +112,16 @@ impl VarGen {
             return Ok( (new_base, adr) );
         }
 
+        if !self.typ.reg() || self.typ.vector() {
+            return Err(Box::from(IrError::UnsupportedType(self.typ.name().to_string())));
+        }
+
         self.in_reg     = false;
         self.on_stack   = true;
 
         let mem = {
             match self.typ {
-                Type::u64 | Type::i64 | Type::f64 => MemoryOperand::new(
+                Type::u64 | Type::i64 | Type::usize | Type::isize | Type::f64 | Type::ptr | Type::typed_ptr(_) => MemoryOperand::new(
                     Register::RBP, 
                     Register::None, 8, -(adr as i64), 8, false, Register::None),
                 Type::u32 | Type::i32 | Type::f32 => MemoryOperand::new(
@@ -115,14 +130,15 @@ impl VarGen {
                 Type::u16 | Type::i16  => MemoryOperand::new(
                     Register::RBP, 
                     Register::None, 8, -(adr as i64), 2, false, Register::None),
-                Type::u8 | Type::i8  => MemoryOperand::new(
+                Type::u8 | Type::i8 | Type::bool => MemoryOperand::new(
                     Register::RBP, 
                     Register::None, 8, -(adr as i64), 2, false, Register::None),
+                _ => unreachable!(), // checked above
             }
         };
 
         match self.typ {
-            Type::u64 | Type::i64 => {
+            Type::u64 | Type::i64 | Type::usize | Type::isize | Type::ptr | Type::typed_ptr(_) => {
                 asm.add_instruction(
                     Instruction::with2(Code::Mov_rm64_r64, self.reg, mem)?
                 )?;
@@ -137,7 +153,7 @@ impl VarGen {
                     Instruction::with2(Code::Mov_rm16_r16, self.reg, mem)?
                 )?;
             },
-            Type::u8 | Type::i8 => {
+            Type::u8 | Type::i8 | Type::bool => {
                 asm.add_instruction(
                     Instruction::with2(Code::Mov_rm8_r8, self.reg, mem)?
                 )?;
@@ -148,12 +164,80 @@ impl VarGen {
                     Instruction::with2(Code::Movlpd_xmm_m64, self.reg, mem)?
                 )?;
             }
+
+            _ => unreachable!(), // checked above
         };
 
         Ok( (new_base, adr) )
     }
 }
 
+impl fmt::Display for VarGen {
+    /// Writes the name of the variable (`%<register>` or `%stack<offset>`)
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.in_reg {
+            let reg = format!("{:?}", self.reg).to_lowercase();
+
+            write!(f, "%{}", reg)
+        } else {
+            write!(f, "%stack{}", self.stack_adr)
+        }
+    }
+}
+
+impl VarGen {
+    /// Compares the variable with `rhs` and stores the result into the `Type::bool` variable `out`
+    pub fn icmp(self, cond: ICmpCond, rhs: VarGen, out: VarGen) -> Box<ICmp<VarGen, VarGen>> {
+        ICmp::new(cond, self, rhs, out)
+    }
+
+    /// Compares the float variable with `rhs` and stores the result into the `Type::bool` variable `out`
+    pub fn fcmp(self, cond: FCmpCond, rhs: VarGen, out: VarGen) -> Box<FCmp<VarGen, VarGen>> {
+        FCmp::new(cond, self, rhs, out)
+    }
+
+    /// Builds a compare (respecting the signedness of the type) with the given condition
+    fn cmp(self, rhs: VarGen, out: VarGen, signed: ICmpCond, unsigned: ICmpCond, float: FCmpCond) -> Box<dyn Compile> {
+        if self.typ.float() {
+            self.fcmp(float, rhs, out)
+        } else if self.typ.signed() {
+            self.icmp(signed, rhs, out)
+        } else {
+            self.icmp(unsigned, rhs, out)
+        }
+    }
+
+    /// `out = self == rhs`
+    pub fn equal(self, rhs: VarGen, out: VarGen) -> Box<dyn Compile> {
+        self.cmp(rhs, out, ICmpCond::eq, ICmpCond::eq, FCmpCond::oeq)
+    }
+
+    /// `out = self != rhs`
+    pub fn not_equal(self, rhs: VarGen, out: VarGen) -> Box<dyn Compile> {
+        self.cmp(rhs, out, ICmpCond::ne, ICmpCond::ne, FCmpCond::une)
+    }
+
+    /// `out = self < rhs`
+    pub fn lt(self, rhs: VarGen, out: VarGen) -> Box<dyn Compile> {
+        self.cmp(rhs, out, ICmpCond::slt, ICmpCond::ult, FCmpCond::olt)
+    }
+
+    /// `out = self <= rhs`
+    pub fn le(self, rhs: VarGen, out: VarGen) -> Box<dyn Compile> {
+        self.cmp(rhs, out, ICmpCond::sle, ICmpCond::ule, FCmpCond::ole)
+    }
+
+    /// `out = self > rhs`
+    pub fn gt(self, rhs: VarGen, out: VarGen) -> Box<dyn Compile> {
+        self.cmp(rhs, out, ICmpCond::sgt, ICmpCond::ugt, FCmpCond::ogt)
+    }
+
+    /// `out = self >= rhs`
+    pub fn ge(self, rhs: VarGen, out: VarGen) -> Box<dyn Compile> {
+        self.cmp(rhs, out, ICmpCond::sge, ICmpCond::uge, FCmpCond::oge)
+    }
+}
+
 impl Add<VarGen> for VarGen{
     type Output = Box<super::ir::Add<VarGen, VarGen>>;
 
@@ -186,7 +270,6 @@ impl Mul<VarGen> for VarGen{
     }
 }
 
-/*
 impl Div<VarGen> for VarGen{
     type Output = Box<super::ir::Div<VarGen, VarGen>>;
 
@@ -195,4 +278,121 @@ impl Div<VarGen> for VarGen{
             self, rhs
         )
     }
-}*/
\ No newline at end of file
+}
+
+impl Rem<VarGen> for VarGen{
+    type Output = Box<super::ir::Rem<VarGen, VarGen>>;
+
+    fn rem(self, rhs: Self) -> Box<super::ir::Rem<VarGen, VarGen>> {
+        super::ir::Rem::new(
+            self, rhs
+        )
+    }
+}
+
+impl BitAnd<VarGen> for VarGen{
+    type Output = Box<super::ir::And<VarGen, VarGen>>;
+
+    fn bitand(self, rhs: Self) -> Box<super::ir::And<VarGen, VarGen>> {
+        super::ir::And::new(
+            self, rhs
+        )
+    }
+}
+
+impl BitOr<VarGen> for VarGen{
+    type Output = Box<super::ir::Or<VarGen, VarGen>>;
+
+    fn bitor(self, rhs: Self) -> Box<super::ir::Or<VarGen, VarGen>> {
+        super::ir::Or::new(
+            self, rhs
+        )
+    }
+}
+
+impl BitXor<VarGen> for VarGen{
+    type Output = Box<super::ir::Xor<VarGen, VarGen>>;
+
+    fn bitxor(self, rhs: Self) -> Box<super::ir::Xor<VarGen, VarGen>> {
+        super::ir::Xor::new(
+            self, rhs
+        )
+    }
+}
+
+impl Shl<VarGen> for VarGen{
+    type Output = Box<super::ir::Shl<VarGen, VarGen>>;
+
+    fn shl(self, rhs: Self) -> Box<super::ir::Shl<VarGen, VarGen>> {
+        super::ir::Shl::new(
+            self, rhs
+        )
+    }
+}
+
+/// `>>` is always a logical shift, use `AShr::new` for an arithmetic shift
+impl Shr<VarGen> for VarGen{
+    type Output = Box<super::ir::LShr<VarGen, VarGen>>;
+
+    fn shr(self, rhs: Self) -> Box<super::ir::LShr<VarGen, VarGen>> {
+        super::ir::LShr::new(
+            self, rhs
+        )
+    }
+}
+
+impl Not for VarGen{
+    type Output = Box<super::ir::Not<VarGen>>;
+
+    fn not(self) -> Box<super::ir::Not<VarGen>> {
+        super::ir::Not::new(
+            self
+        )
+    }
+}
+
+impl Neg for VarGen{
+    type Output = Box<super::ir::Neg<VarGen>>;
+
+    fn neg(self) -> Box<super::ir::Neg<VarGen>> {
+        super::ir::Neg::new(
+            self
+        )
+    }
+}
+
+macro_rules! ImmOp {
+    ($trait:ident, $func:ident, $ir:ident, $($imm:ty),*) => {
+        $(
+            impl $trait<$imm> for VarGen {
+                type Output = Box<super::ir::$ir<VarGen, $imm>>;
+
+                fn $func(self, rhs: $imm) -> Box<super::ir::$ir<VarGen, $imm>> {
+                    super::ir::$ir::new(
+                        self, rhs
+                    )
+                }
+            }
+
+            impl $trait<VarGen> for $imm {
+                type Output = Box<super::ir::$ir<$imm, VarGen>>;
+
+                fn $func(self, rhs: VarGen) -> Box<super::ir::$ir<$imm, VarGen>> {
+                    super::ir::$ir::new(
+                        self, rhs
+                    )
+                }
+            }
+        )*
+    };
+}
+
+// literals without a suffix fall back to i32 (or f64), they need a suffix if they don't fit
+// or if the result is used right away (like `*(var - 1_i64)`)
+ImmOp!(Add, add, Add, i64, i32, f64, f32);
+ImmOp!(Sub, sub, Sub, i64, i32, f64, f32);
+ImmOp!(Mul, mul, Mul, i64, i32, f64, f32);
+ImmOp!(Div, div, Div, f64, f32);
+ImmOp!(BitAnd, bitand, And, i64, i32);
+ImmOp!(BitOr, bitor, Or, i64, i32);
+ImmOp!(BitXor, bitxor, Xor, i64, i32);
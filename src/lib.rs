@@ -43,7 +43,7 @@
 //! 
 //! ### Linker
 //! ```rust
-//! use rllvm::contxt::{jit::JitFunction, link::{JitLinker, Link}};
+//! use rllvm::contxt::{jit::JitFunction, link::{JitLinker, Link, LinkKind}};
 //! 
 //! fn main() {
 //!     let mut linker = JitLinker::new();
@@ -58,8 +58,8 @@
 //!         0xc3,                           // ret
 //!     ], false);
 //! 
-//!     linker.add_reloc( Link { from: "main".into(), to: "test".into(), at: 1, size: 4, replace: false} );
-//!     linker.add_reloc( Link { from: "test".into(), to: "test_data".into(), at: 1, size: 4, replace: true} ); // replace means inline
+//!     linker.add_reloc( Link { from: "main".into(), to: "test".into(), at: 1, size: 4, replace: false, kind: LinkKind::Relative} );
+//!     linker.add_reloc( Link { from: "test".into(), to: "test_data".into(), at: 1, size: 4, replace: true, kind: LinkKind::Relative} ); // replace means inline
 //!     linker.add_label("test_data", vec![5]);
 //! 
 //!     unsafe {
@@ -109,7 +109,7 @@ pub mod prelude {
     pub use crate::target::call_conv::TargetCallConv;
 
     #[cfg(feature = "function")]
-    pub use crate::func::{Function, AsmFunction};
+    pub use crate::func::{Function, AsmFunction, Block};
 
     pub use crate::naming::NamingGenerator;
 
@@ -119,7 +119,8 @@ pub mod prelude {
         jit::JitFunction,
         link::{
             JitLinker,
-            Link
+            Link,
+            LinkKind,
         }
     };
 
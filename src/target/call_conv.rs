@@ -0,0 +1,357 @@
+use target_lexicon::CallingConvention;
+use iced_x86::{code_asm::*, Register};
+use crate::{contxt::contxt::ContextError, ir::r#type::Type};
+
+/// Stores the calling convention
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetCallConv {
+    conv: CallingConvention,
+
+    arg8: Vec<AsmRegister8>,
+    arg16: Vec<AsmRegister16>,
+    arg32: Vec<AsmRegister32>,
+    arg64: Vec<AsmRegister64>,
+    argf: Vec<AsmRegisterXmm>,
+
+    ret8: AsmRegister8,
+    ret16: AsmRegister16,
+    ret32: AsmRegister32,
+    ret64: AsmRegister64,
+    retf: AsmRegisterXmm,
+    
+    arg8_reg: Vec<Register>,
+    arg16_reg: Vec<Register>,
+    arg32_reg: Vec<Register>,
+    arg64_reg: Vec<Register>,
+    argf_reg: Vec<Register>,
+
+    ret8_reg: Register,
+    ret16_reg: Register,
+    ret32_reg: Register,
+    ret64_reg: Register,
+    retf_reg: Register,
+
+    tmp_reg: Register,
+    tmpf_reg: Register,
+
+    var_reg: Vec<Register>,
+    varf_reg: Vec<Register>,
+
+    /// Stack shadow space
+    pub shadow: usize,
+}
+
+impl TargetCallConv {
+    /// Returns a new instance (or an error if the calling convention isn't supported)
+    pub fn new(conv: CallingConvention) -> Result<Self, ContextError> {
+        match conv {
+            CallingConvention::SystemV => Ok(TargetCallConv::linux()),
+            CallingConvention::WindowsFastcall => Ok(TargetCallConv::windows()),
+            _ => Err(ContextError::UnsuportedCall(format!("{:?}", conv))),
+        }
+    }
+
+    /// Returns linux calling convention
+    pub fn linux() -> Self {
+        Self {
+            conv: CallingConvention::SystemV,
+
+            arg8:   vec![dil,   sil,    dl,     cl,     r8b,    r9b ],
+            arg16:  vec![di,     si,    dx,     cx,     r8w,    r9w ],
+            arg32:  vec![edi,   esi,    edx,    ecx,    r8d,    r9d ],
+            arg64:  vec![rdi,   rsi,    rdx,    rcx,    r8,     r9  ],
+
+            argf: vec![xmm0,    xmm1,   xmm2,   xmm3,   xmm4,   xmm5, xmm6, xmm7 ],
+
+            ret8: al,
+            ret16: ax,
+            ret32: eax,
+            ret64: rax,
+            retf: xmm0,
+
+            
+            arg8_reg:   vec![Register::DIL, Register::SIL, Register::DL, Register::CL, Register::R8L, Register::R9L],
+            arg16_reg:  vec![Register::DI, Register::SI, Register::DX, Register::CX, Register::R8W, Register::R9W],
+            arg32_reg:  vec![Register::EDI,   Register::ESI,    Register::EDX,    Register::ECX,    Register::R8D,    Register::R9D ],
+            arg64_reg:  vec![Register::RDI,   Register::RSI,    Register::RDX,    Register::RCX,    Register::R8,     Register::R9  ],
+
+            argf_reg: vec![Register::XMM0,    Register::XMM1,   Register::XMM2,   Register::XMM3,   Register::XMM4,   Register::XMM5, Register::XMM6, Register::XMM7 ],
+
+            ret8_reg: Register::AL,
+            ret16_reg: Register::AX,
+            ret32_reg: Register::EAX,
+            ret64_reg: Register::RAX,
+            retf_reg: Register::XMM0,
+
+            tmp_reg: Register::R11,
+            tmpf_reg: Register::XMM15,
+
+            var_reg: vec![Register::R10, Register::RAX, Register::R9, Register::R8, Register::RCX, Register::RDX, Register::RSI, Register::RDI],
+            varf_reg: vec![Register::XMM8, Register::XMM9, Register::XMM10, Register::XMM11, Register::XMM12, Register::XMM13, Register::XMM14,
+                           Register::XMM7, Register::XMM6, Register::XMM5, Register::XMM4, Register::XMM3, Register::XMM2, Register::XMM1, Register::XMM0],
+
+            shadow: 32,
+        }
+    }
+
+    /// Returns windows calling convention
+    pub fn windows() -> Self {
+        Self {
+            conv: CallingConvention::WindowsFastcall,
+
+            arg8:   vec![cl,    dl,     r8b,    r9b],
+            arg16:  vec![cx,    dx,     r8w,    r9w],
+            arg32:  vec![ecx,   edx,    r8d,    r9d],
+            arg64:  vec![rcx,   rdx,    r8,     r9],
+
+            argf: vec![xmm0,    xmm1,   xmm2,   xmm3, ],
+
+            ret8: al,
+            ret16: ax,
+            ret32: eax,
+            ret64: rax,
+            retf: xmm0,
+
+            
+            arg8_reg:   vec![Register::CL, Register::DL, Register::R8L, Register::R9L],
+            arg16_reg:  vec![Register::CX, Register::DX, Register::R8W, Register::R9W],
+            arg32_reg:  vec![Register::ECX, Register::EDX, Register::R8D, Register::R9D],
+            arg64_reg:  vec![Register::RCX, Register::RDX, Register::R8, Register::R9],
+
+            argf_reg: vec![Register::XMM0, Register::XMM1, Register::XMM2, Register::XMM3 ],
+
+            ret8_reg: Register::AL,
+            ret16_reg: Register::AX,
+            ret32_reg: Register::EAX,
+            ret64_reg: Register::RAX,
+            retf_reg: Register::XMM0,
+
+            tmp_reg: Register::R11,
+            tmpf_reg: Register::XMM5,
+
+            var_reg: vec![Register::R10, Register::RAX, Register::R9, Register::R8, Register::RDX, Register::RCX],
+            varf_reg: vec![Register::XMM4, Register::XMM3, Register::XMM2, Register::XMM1, Register::XMM0],
+
+            shadow: 32,
+        }
+    }
+
+    pub fn arg8(&self, nr: usize) -> Option<AsmRegister8> {
+        self.arg8.get(nr).copied()
+    }
+
+    pub fn arg16(&self, nr: usize) -> Option<AsmRegister16> {
+        self.arg16.get(nr).copied()
+    }
+
+    pub fn arg32(&self, nr: usize) -> Option<AsmRegister32> {
+        self.arg32.get(nr).copied()
+    }
+
+    pub fn arg64(&self, nr: usize) -> Option<AsmRegister64> {
+        self.arg64.get(nr).copied()
+    }
+
+    pub fn argf(&self, nr: usize) -> Option<AsmRegisterXmm> {
+        self.argf.get(nr).copied()
+    }
+
+    pub fn ret8(&self) -> AsmRegister8 {
+        self.ret8
+    }
+
+    pub fn ret16(&self) -> AsmRegister16 {
+        self.ret16
+    }
+
+    pub fn ret32(&self) -> AsmRegister32 {
+        self.ret32
+    }
+
+    pub fn ret64(&self) -> AsmRegister64 {
+        self.ret64
+    }
+
+    pub fn retf(&self) -> AsmRegisterXmm {
+        self.retf
+    }
+
+    pub fn arg8_reg(&self, nr: usize) -> Option<Register> {
+        self.arg8_reg.get(nr).copied()
+    }
+
+    pub fn arg16_reg(&self, nr: usize) -> Option<Register> {
+        self.arg16_reg.get(nr).copied()
+    }
+
+    pub fn arg32_reg(&self, nr: usize) -> Option<Register> {
+        self.arg32_reg.get(nr).copied()
+    }
+
+    pub fn arg64_reg(&self, nr: usize) -> Option<Register> {
+        self.arg64_reg.get(nr).copied()
+    }
+
+    pub fn argf_reg(&self, nr: usize) -> Option<Register> {
+        self.argf_reg.get(nr).copied()
+    }
+
+    pub fn ret8_reg(&self) -> Register {
+        self.ret8_reg
+    }
+
+    pub fn ret16_reg(&self) -> Register {
+        self.ret16_reg
+    }
+
+    pub fn ret32_reg(&self) -> Register {
+        self.ret32_reg
+    }
+
+    pub fn ret64_reg(&self) -> Register {
+        self.ret64_reg
+    }
+
+    pub fn retf_reg(&self) -> Register {
+        self.retf_reg
+    }
+
+    /// Returns a volatile register which is never used for arguments
+    /// (it is used as a scratch register by the code generation)
+    pub fn tmp_reg(&self) -> Register {
+        self.tmp_reg
+    }
+
+    /// Returns a volatile xmm register which is never used for arguments
+    /// (it is used as a scratch register by the code generation)
+    pub fn tmpf_reg(&self) -> Register {
+        self.tmpf_reg
+    }
+
+    /// Returns the volatile register which can be used to store variables
+    /// (the argument registers are also included)
+    pub fn var_reg(&self, nr: usize) -> Option<Register> {
+        self.var_reg.get(nr).copied()
+    }
+
+    /// Returns the volatile xmm register which can be used to store variables
+    /// (the argument registers are also included)
+    pub fn varf_reg(&self, nr: usize) -> Option<Register> {
+        self.varf_reg.get(nr).copied()
+    }
+
+    /// Returns the calling convention
+    pub fn conv(&self) -> CallingConvention {
+        self.conv
+    }
+
+    /// Returns if the register (of any size) has to be preserved by the callee
+    ///
+    /// For the vector registers only the lower 128 bits are preserved (xmm6 - xmm15 on windows)
+    pub fn callee_saved(&self, reg: Register) -> bool {
+        let windows = self.conv == CallingConvention::WindowsFastcall;
+
+        match reg.full_register() {
+            Register::RBX | Register::RBP | Register::RSP | Register::R12 | Register::R13 | Register::R14 | Register::R15 => true,
+            Register::RDI | Register::RSI => windows,
+            reg => windows && reg.is_zmm() && (6..16).contains(&reg.number()),
+        }
+    }
+
+    /// Returns the size of the space which a caller needs to reserve for the callee
+    /// below the stack arguments (the home space of the windows calling convention)
+    pub fn home(&self) -> usize {
+        match self.conv {
+            CallingConvention::WindowsFastcall => 32,
+            _ => 0,
+        }
+    }
+
+    /// Returns for every argument the index of the register in which it is passed
+    /// (or None if it is passed on the stack)
+    /// 
+    /// The index is into the `argf` registers for floats (and vectors) and into the integer registers (`arg64`, ...) else.
+    /// On windows the position of the argument decides the register, on linux integers and floats are counted seperatly
+    /// 
+    /// 128 bit integers take two registers on linux (the index is the one of the lower half)
+    /// and are passed as a pointer to the value on windows
+    pub fn reg_args(&self, args: &[Type]) -> Vec<Option<usize>> {
+        let mut ints = 0;
+        let mut floats = 0;
+
+        let mut ret = vec![];
+
+        for (pos, arg) in args.iter().enumerate() {
+            if arg.wide() {
+                let index = match self.conv {
+                    CallingConvention::WindowsFastcall => Some(pos).filter(|pos| *pos < self.arg64_reg.len()),
+                    _ if ints + 2 <= self.arg64_reg.len() => {
+                        ints += 2;
+                        Some(ints - 2)
+                    },
+                    _ => None,
+                };
+
+                ret.push(index);
+                continue;
+            }
+
+            if !arg.reg() {
+                ret.push(None);
+                continue;
+            }
+
+            if arg.vector() && self.conv == CallingConvention::WindowsFastcall {
+                // vectors are passed by reference which isn't supported
+                ret.push(None);
+                continue;
+            }
+
+            let (index, max) = match self.conv {
+                CallingConvention::WindowsFastcall => {
+                    let max = if arg.float() || arg.vector() { self.argf_reg.len() } else { self.arg64_reg.len() };
+                    (pos, max)
+                },
+                _ => {
+                    if arg.float() || arg.vector() {
+                        floats += 1;
+                        (floats - 1, self.argf_reg.len())
+                    } else {
+                        ints += 1;
+                        (ints - 1, self.arg64_reg.len())
+                    }
+                },
+            };
+
+            ret.push( if index < max { Some(index) } else { None } );
+        }
+
+        ret
+    }
+
+    /// Returns the type in which a variadic argument is passed
+    /// (the default argument promotions of C: floats become doubles and smaller integers 32 bit integers)
+    pub fn variadic_type(&self, typ: Type) -> Type {
+        match typ {
+            Type::f32 => Type::f64,
+            Type::i8 | Type::i16 => Type::i32,
+            Type::u8 | Type::u16 | Type::bool => Type::u32,
+            _ => typ,
+        }
+    }
+
+    /// Returns the value which needs to be in AL when calling a variadic function which gets
+    /// the given number of float registers (the system v abi needs the upper bound of the used vector registers)
+    pub fn variadic_al(&self, floats: usize) -> Option<u8> {
+        match self.conv {
+            CallingConvention::WindowsFastcall => None,
+            _ => Some(floats as u8),
+        }
+    }
+
+    /// Returns if float arguments of variadic functions also need to be passed in the integer register
+    /// of their position (the windows calling convention does so, because the callee doesn't know their type)
+    pub fn variadic_int_copies(&self) -> bool {
+        self.conv == CallingConvention::WindowsFastcall
+    }
+}
@@ -0,0 +1,4 @@
+//! Calling conventions
+
+pub mod call_conv;
+pub mod reg;
@@ -0,0 +1,57 @@
+use iced_x86::Register;
+
+/// Returns the general purpose register which is part of `reg` and has the given size in bytes
+/// 
+/// Returns `Register::None` if `reg` isn't a general purpose register or the size is invalid
+/// 
+/// ## Example
+/// ```rust
+/// use iced_x86::Register;
+/// use rllvm::target::reg::sized;
+/// 
+/// assert_eq!(sized(Register::RDI, 4), Register::EDI);
+/// assert_eq!(sized(Register::R9D, 1), Register::R9L);
+/// assert_eq!(sized(Register::SI, 8), Register::RSI);
+/// ```
+pub fn sized(reg: Register, size: usize) -> Register {
+    if !reg.is_gpr() {
+        return Register::None;
+    }
+
+    let nr = reg.full_register() as usize - Register::RAX as usize;
+
+    let reg = match size {
+        8 => Register::RAX as usize + nr,
+        4 => Register::EAX as usize + nr,
+        2 => Register::AX as usize + nr,
+        1 => match nr {
+            0..=3 => Register::AL as usize + nr,
+            4..=7 => Register::SPL as usize + nr - 4,
+            _ => Register::R8L as usize + nr - 8,
+        },
+        _ => return Register::None,
+    };
+
+    Register::try_from(reg).unwrap_or(Register::None)
+}
+
+/// Returns the 64bit version of the general purpose register
+/// or the xmm version of the vector register
+pub fn full(reg: Register) -> Register {
+    if reg.is_gpr() {
+        sized(reg, 8)
+    } else if reg.is_xmm() || reg.is_ymm() || reg.is_zmm() {
+        Register::try_from(Register::XMM0 as usize + reg.number()).unwrap_or(Register::None)
+    } else {
+        reg
+    }
+}
+
+/// Returns the ymm version of the vector register
+pub fn ymm(reg: Register) -> Register {
+    if reg.is_xmm() || reg.is_ymm() || reg.is_zmm() {
+        Register::try_from(Register::YMM0 as usize + reg.number()).unwrap_or(Register::None)
+    } else {
+        Register::None
+    }
+}
@@ -1,6 +1,9 @@
 use std::error::Error;
+use std::sync::atomic::{AtomicI32, AtomicI64, AtomicU32, Ordering};
 
-use rllvm::{contxt::{contxt::Context, jit::JitFunction}, ir::{ir::Return, r#type::Type}, target::call_conv::TargetCallConv};
+use iced_x86::{code_asm::{qword_ptr, rcx, rsp}, Register};
+use object::{Object, ObjectSection, ObjectSymbol};
+use rllvm::{contxt::{contxt::Context, jit::JitFunction}, ir::{compile::Compile, intrinsics, ir::*, r#type::Type, var::VarGen}, target::call_conv::TargetCallConv};
 
 #[test]
 fn asm_function_jit() -> Result<(), Box<dyn Error>>{
@@ -27,6 +30,9 @@ fn asm_function_jit() -> Result<(), Box<dyn Error>>{
         assert_eq!(out, 138);
     }
 
+    // only system v and windows fastcall are supported
+    assert!(TargetCallConv::new(target_lexicon::CallingConvention::AppleAarch64).is_err());
+
     Ok(())
 }
 
@@ -44,8 +50,2318 @@ fn return_types() -> Result<(), Box<dyn Error>> {
 
         println!("out: {}", out);
 
-        //assert_eq!(out, 0.5 as f32);
+        assert_eq!(out, 0.5_f32);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn division() -> Result<(), Box<dyn Error>> {
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let func = contxt.add_function("div", vec![Type::i32, Type::i32], Type::i32);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+    let y = asm.arg(1).unwrap();
+
+    func.ir.push( Return::new(*(x / y)) );
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(i32, i32) -> i32> = contxt.get_jit_function("div")?;
+        assert_eq!(func.call(-17, 5), -3);
+    }
+
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let func = contxt.add_function("rem", vec![Type::u64, Type::u64], Type::u64);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+    let y = asm.arg(1).unwrap();
+
+    func.ir.push( Return::new(*(x % y)) );
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(u64, u64) -> u64> = contxt.get_jit_function("rem")?;
+        assert_eq!(func.call(u64::MAX, 10), u64::MAX % 10);
+    }
+
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let func = contxt.add_function("fdiv", vec![Type::f64, Type::f64], Type::f64);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+    let y = asm.arg(1).unwrap();
+
+    func.ir.push( Return::new(*(x / y)) );
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(f64, f64) -> f64> = contxt.get_jit_function("fdiv")?;
+        assert_eq!(func.call(7.5, 2.5), 3.0);
+    }
+
+    // the quotient is truncated (also out of the i64 range)
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    for (name, typ) in [("frem", Type::f64), ("frem32", Type::f32)] {
+        let func = contxt.add_function(name, vec![typ, typ], typ);
+        let asm = func.asm_func()?;
+
+        let x = asm.arg(0).unwrap();
+        let y = asm.arg(1).unwrap();
+
+        func.ir.push( Return::new(*(x % y)) );
+    }
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(f64, f64) -> f64> = contxt.get_jit_function("frem")?;
+        assert_eq!(func.call(7.5, 2.0), 1.5);
+        assert_eq!(func.call(-7.5, 2.0), -1.5);
+        assert_eq!(func.call(1e300, 1e300), 0.0);
+        assert!(func.call(f64::NAN, 2.0).is_nan());
+
+        let mut func: JitFunction<unsafe extern "C" fn(f32, f32) -> f32> = contxt.get_jit_function("frem32")?;
+        assert_eq!(func.call(-5.25, 2.0), -1.25);
+    }
+
+    // the operands need to be in registers
+    for rem in [false, true] {
+        let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+        let func = contxt.add_function("invalid", vec![Type::i64], Type::void);
+        let asm = func.asm_func()?;
+
+        let x = asm.arg(0).unwrap();
+        let slot = asm.alloca(Type::i64);
+
+        let ir: Box<dyn Compile> = if rem { x % slot } else { x / slot };
+        func.push( ir );
+        func.push( Return::new(()) );
+
+        assert!(unsafe { contxt.get_jit_function::<unsafe extern "C" fn(i64)>("invalid") }.is_err());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn bitwise() -> Result<(), Box<dyn Error>> {
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let func = contxt.add_function("shl", vec![Type::u64, Type::u64, Type::u64, Type::u64], Type::u64);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(3).unwrap();
+    let y = asm.arg(0).unwrap();
+
+    func.ir.push( Return::new(*(x << y)) );
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(u64, u64, u64, u64) -> u64> = contxt.get_jit_function("shl")?;
+        assert_eq!(func.call(4, 0, 0, 3), 48);
+    }
+
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let func = contxt.add_function("ashr", vec![Type::i32, Type::i32], Type::i32);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+    let y = asm.arg(1).unwrap();
+
+    func.ir.push( Return::new(*AShr::new(x, y)) );
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(i32, i32) -> i32> = contxt.get_jit_function("ashr")?;
+        assert_eq!(func.call(-64, 2), -16);
+    }
+
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let func = contxt.add_function("xor", vec![Type::u32, Type::u32], Type::u32);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+    let y = asm.arg(1).unwrap();
+
+    func.ir.push( x ^ y );
+    func.ir.push( Return::new(*!x) );
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(u32, u32) -> u32> = contxt.get_jit_function("xor")?;
+        assert_eq!(func.call(0b1100, 0b1010), !0b0110);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn negate() -> Result<(), Box<dyn Error>> {
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    for (name, typ) in [("neg", Type::f64), ("negf", Type::f32)] {
+        let func = contxt.add_function(name, vec![typ], typ);
+        let asm = func.asm_func()?;
+
+        let x = asm.arg(0).unwrap();
+
+        func.push( Return::new(*-x) );
+    }
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(f64) -> f64> = contxt.get_jit_function("neg")?;
+        assert_eq!(func.call(1.5), -1.5);
+        assert!(func.call(0.0).is_sign_negative());
+        assert!(func.call(-0.0).is_sign_positive());
+
+        let mut func: JitFunction<unsafe extern "C" fn(f32) -> f32> = contxt.get_jit_function("negf")?;
+        assert_eq!(func.call(-2.5), 2.5);
+        assert!(func.call(0.0).is_sign_negative());
+    }
+
+    // there's no bitwise not for floats and stack slots aren't supported
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let func = contxt.add_function("invalid", vec![Type::f64], Type::f64);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+    func.push( Return::new(*!x) );
+
+    assert!(unsafe { contxt.get_jit_function::<unsafe extern "C" fn()>("invalid") }.is_err());
+
+    // the math, bitwise and shift nodes don't support them either
+    for op in 0..4 {
+        let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+        let func = contxt.add_function("invalid", vec![Type::i64], Type::void);
+        let asm = func.asm_func()?;
+
+        let x = asm.arg(0).unwrap();
+        let slot = asm.alloca(Type::i64);
+
+        let ir: Box<dyn Compile> = match op {
+            0 => -slot,
+            1 => slot + x,
+            2 => x & slot,
+            _ => x << slot,
+        };
+        func.push( ir );
+
+        assert!(unsafe { contxt.get_jit_function::<unsafe extern "C" fn()>("invalid") }.is_err(), "{}", op);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn compare() -> Result<(), Box<dyn Error>> {
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let func = contxt.add_function("lt", vec![Type::i32, Type::i32], Type::bool);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+    let y = asm.arg(1).unwrap();
+    let out = asm.var(Type::bool).unwrap();
+
+    func.ir.push( x.lt(y, out) );
+    func.ir.push( Return::new(out) );
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(i32, i32) -> bool> = contxt.get_jit_function("lt")?;
+        assert!(func.call(-5, 3));
+        assert!(!func.call(3, -5));
+    }
+
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let func = contxt.add_function("une", vec![Type::f64, Type::f64], Type::bool);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+    let y = asm.arg(1).unwrap();
+    let out = asm.var(Type::bool).unwrap();
+
+    func.ir.push( Return::new(*x.fcmp(FCmpCond::une, y, out)) );
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(f64, f64) -> bool> = contxt.get_jit_function("une")?;
+        assert!(!func.call(1.0, 1.0));
+        assert!(func.call(1.0, 2.0));
+        assert!(func.call(f64::NAN, 1.0));
+    }
+
+    // a negated bool is still 0 or 1, so branches on it work
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let func = contxt.add_function("not", vec![Type::bool], Type::u32);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+
+    func.push( !x );
+    func.push( CondBr::new(x, "yes", "no") );
+
+    func.add_block("yes");
+    func.position_at_end("yes")?;
+    func.push( Return::new(1) );
+
+    func.add_block("no");
+    func.position_at_end("no")?;
+    func.push( Return::new(0) );
+
+    let func = contxt.add_function("not_value", vec![Type::bool], Type::u8);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+
+    func.push( Return::new(*!x) );
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(bool) -> u32> = contxt.get_jit_function("not")?;
+        assert_eq!(func.call(true), 0);
+        assert_eq!(func.call(false), 1);
+
+        let mut func: JitFunction<unsafe extern "C" fn(bool) -> u8> = contxt.get_jit_function("not_value")?;
+        assert_eq!(func.call(true), 0);
+        assert_eq!(func.call(false), 1);
+    }
+
+    // the operands need to be in registers
+    for typ in [Type::i64, Type::f64] {
+        let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+        let func = contxt.add_function("invalid", vec![typ], Type::bool);
+        let asm = func.asm_func()?;
+
+        let x = asm.arg(0).unwrap();
+        let slot = asm.alloca(typ);
+        let out = asm.var(Type::bool).unwrap();
+
+        func.push( x.lt(slot, out) );
+        func.push( Return::new(out) );
+
+        assert!(unsafe { contxt.get_jit_function::<unsafe extern "C" fn()>("invalid") }.is_err(), "{}", typ);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn blocks() -> Result<(), Box<dyn Error>> {
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let func = contxt.add_function("max", vec![Type::i64, Type::i64], Type::i64);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+    let y = asm.arg(1).unwrap();
+    let cond = asm.var(Type::bool).unwrap();
+
+    func.push( x.gt(y, cond) );
+    func.push( CondBr::new(cond, "x", "y") );
+
+    func.add_block("x");
+    func.add_block("y");
+
+    func.position_at_end("x")?;
+    func.push( Return::new(x) );
+
+    func.position_at_end("y")?;
+    func.push( Return::new(y) );
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(i64, i64) -> i64> = contxt.get_jit_function("max")?;
+        assert_eq!(func.call(-5, 3), 3);
+        assert_eq!(func.call(7, -5), 7);
+    }
+
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let func = contxt.add_function("sum", vec![Type::u64], Type::u64);
+    let asm = func.asm_func()?;
+
+    let n = asm.arg(0).unwrap();
+    let acc = asm.var(Type::u64).unwrap();
+    let zero = asm.var(Type::u64).unwrap();
+    let one = asm.var(Type::u64).unwrap();
+    let cond = asm.var(Type::bool).unwrap();
+
+    func.push( acc ^ acc );
+    func.push( zero ^ zero );
+    func.push( Br::new("loop") );
+
+    func.add_block("loop");
+    func.position_at_end("loop")?;
+    func.push( n.not_equal(zero, cond) );
+    func.push( CondBr::new(cond, "body", "done") );
+
+    func.add_block("body");
+    func.position_at_end("body")?;
+    func.push( acc + n );
+    func.push( n.equal(n, one) );
+    func.push( n - one );
+    func.push( Br::new("loop") );
+
+    func.add_block("done");
+    func.position_at_end("done")?;
+    func.push( Return::new(acc) );
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(u64) -> u64> = contxt.get_jit_function("sum")?;
+        assert_eq!(func.call(10), 55);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn phi() -> Result<(), Box<dyn Error>> {
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let func = contxt.add_function("fib", vec![Type::u64], Type::u64);
+    let asm = func.asm_func()?;
+
+    let n = asm.arg(0).unwrap();
+    let a = asm.var(Type::u64).unwrap();
+    let b = asm.var(Type::u64).unwrap();
+    let zero = asm.var(Type::u64).unwrap();
+    let one = asm.var(Type::u64).unwrap();
+    let out = asm.var(Type::u64).unwrap();
+    let cond = asm.var(Type::bool).unwrap();
+
+    func.push( a ^ a );
+    func.push( zero ^ zero );
+    func.push( n.equal(n, one) );
+    func.push( n.equal(n, b) );
+    func.push( Br::new("loop") );
+
+    func.add_block("loop");
+    func.position_at_end("loop")?;
+    func.push( Phi::new(a, vec![("entry", a), ("body", b)]) );
+    func.push( Phi::new(b, vec![("entry", b), ("body", a)]) );
+    func.push( n.not_equal(zero, cond) );
+    func.push( CondBr::new(cond, "body", "done") );
+
+    func.add_block("body");
+    func.position_at_end("body")?;
+    func.push( a + b ); // a = a + b, so the phis swap a and b
+    func.push( n - one );
+    func.push( Br::new("loop") );
+
+    func.add_block("done");
+    func.position_at_end("done")?;
+    func.push( Phi::new(out, vec![("loop", a)]) );
+    func.push( Return::new(out) );
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(u64) -> u64> = contxt.get_jit_function("fib")?;
+        assert_eq!(func.call(10), 55);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn stack_slots() -> Result<(), Box<dyn Error>> {
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let func = contxt.add_function("count", vec![Type::i32], Type::i32);
+    let asm = func.asm_func()?;
+
+    let n = asm.arg(0).unwrap();
+    let counter = asm.alloca(Type::i32);
+    let padding: Vec<_> = (0..20).map(|_| asm.alloca(Type::i64)).collect();
+    let far = asm.alloca(Type::i32);
+    let tmp = asm.var(Type::i32).unwrap();
+
+    func.push( Alloca::new(counter) );
+    for slot in padding {
+        func.push( Alloca::new(slot) );
+    }
+    func.push( Alloca::new(far) );
+    func.push( tmp ^ tmp );
+    func.push( Store::new(counter, tmp) );
+    func.push( Store::new(far, n) );
+    func.push( Br::new("update") );
+
+    func.add_block("update");
+    func.position_at_end("update")?;
+    func.push( Load::new(tmp, counter) );
+    func.push( tmp + n );
+    func.push( Store::new(counter, tmp) );
+    func.push( Load::new(tmp, far) );
+    func.push( tmp + n );
+    func.push( Store::new(far, tmp) );
+    func.push( Br::new("done") );
+
+    func.add_block("done");
+    func.position_at_end("done")?;
+    func.push( Load::new(tmp, counter) );
+    func.push( Load::new(n, far) );
+    func.push( Return::new(*(tmp + n)) );
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(i32) -> i32> = contxt.get_jit_function("count")?;
+        assert_eq!(func.call(3), 9);
+    }
+
+    // slots which weren't reserved by `alloca` can't be declared
+    for var in [VarGen::new_stack(Type::i32, 8), VarGen::new_reg(Type::i32, Register::EAX)] {
+        let mut contxt = Context::new(target_lexicon::Triple::host())?;
+        let func = contxt.add_function("invalid", vec![], Type::void);
+
+        func.push( Alloca::new(var) );
+        func.push( Return::new(()) );
+
+        assert!(unsafe { contxt.get_jit_function::<unsafe extern "C" fn()>("invalid") }.is_err());
+    }
+
+    // loads and stores need a pointer or a reserved slot of the value's type
+    for op in 0..4 {
+        let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+        let func = contxt.add_function("invalid", vec![Type::i64], Type::void);
+        let asm = func.asm_func()?;
+
+        let x = asm.arg(0).unwrap();
+        let slot = asm.alloca(Type::i32);
+
+        let ir: Box<dyn Compile> = match op {
+            0 => Store::new(VarGen::new_stack(Type::i64, 0), x),
+            1 => Store::new(slot, x),
+            2 => Load::new(x, slot),
+            _ => Load::new(x, x),
+        };
+        func.push( ir );
+        func.push( Return::new(()) );
+
+        assert!(unsafe { contxt.get_jit_function::<unsafe extern "C" fn(i64)>("invalid") }.is_err(), "{}", op);
+    }
+
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let func = contxt.add_function("spill", vec![Type::f64, Type::f64], Type::f64);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+    let y = asm.arg(1).unwrap();
+    let slot = asm.alloca(Type::f64);
+
+    func.push( Alloca::new(slot) );
+    func.push( Store::new(slot, x) );
+    func.push( x - y );
+    func.push( Load::new(y, slot) );
+    func.push( Return::new(*(y / x)) );
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(f64, f64) -> f64> = contxt.get_jit_function("spill")?;
+        assert_eq!(func.call(6.0, 3.0), 2.0);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn call() -> Result<(), Box<dyn Error>> {
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let func = contxt.add_function("sub", vec![Type::u64, Type::u64], Type::u64);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+    let y = asm.arg(1).unwrap();
+
+    func.push( Return::new(*(x - y)) );
+
+    let func = contxt.add_function("caller", vec![Type::u64, Type::u64], Type::u64);
+    let asm = func.asm_func()?;
+
+    let a = asm.arg(0).unwrap();
+    let b = asm.arg(1).unwrap();
+    let r = asm.var(Type::u64).unwrap();
+
+    func.push( Call::new("sub", vec![b, a], Some(r)) );
+    func.push( r * a );
+    func.push( Return::new(*(r + b)) );
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(u64, u64) -> u64> = contxt.get_jit_function("caller")?;
+        assert_eq!(func.call(3, 10), 31);
+    }
+
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let func = contxt.add_function("mix", vec![Type::u32, Type::f64, Type::u32, Type::f64], Type::f64);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(1).unwrap();
+    let y = asm.arg(3).unwrap();
+
+    func.push( Return::new(*(x - y)) );
+
+    let func = contxt.add_function("mixer", vec![Type::u32, Type::f64, Type::f64], Type::f64);
+    let asm = func.asm_func()?;
+
+    let n = asm.arg(0).unwrap();
+    let p = asm.arg(1).unwrap();
+    let q = asm.arg(2).unwrap();
+    let r = asm.var(Type::f64).unwrap();
+
+    func.push( Call::new("mix", vec![n, q, n, p], Some(r)) );
+    func.push( Return::new(r) );
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(u32, f64, f64) -> f64> = contxt.get_jit_function("mixer")?;
+        assert_eq!(func.call(1, 1.5, 4.0), 2.5);
+    }
+
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+    let call = contxt.call.clone();
+
+    let args = vec![Type::u64; 8];
+    let stack_index = call.reg_args(&args)[..7].iter().filter(|index| index.is_none()).count();
+
+    let func = contxt.add_function("eighth", args, Type::u64);
+    let asm = func.asm_func()?;
+
+    asm.asm.mov(call.ret64(), qword_ptr(rsp + 8 + call.home() + stack_index * 8))?;
+
+    let func = contxt.add_function("pass", vec![Type::u64, Type::u64], Type::u64);
+    let asm = func.asm_func()?;
+
+    let a = asm.arg(0).unwrap();
+    let b = asm.arg(1).unwrap();
+    let r = asm.var(Type::u64).unwrap();
+
+    func.push( Return::new(*Call::new("eighth", vec![a, a, a, a, a, a, a, b], Some(r))) );
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(u64, u64) -> u64> = contxt.get_jit_function("pass")?;
+        assert_eq!(func.call(1, 42), 42);
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+extern "C" fn triple(x: u64) -> u64 {
+    x * 3
+}
+
+#[test]
+fn externs() -> Result<(), Box<dyn Error>> {
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+    contxt.add_extern_at("triple", triple as *const () as usize);
+
+    let func = contxt.add_function("call_triple", vec![Type::u64], Type::u64);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+    let r = asm.var(Type::u64).unwrap();
+
+    func.push( Call::new("triple", vec![x], Some(r)) );
+    func.push( Return::new(*(r + x)) );
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(u64) -> u64> = contxt.get_jit_function("call_triple")?;
+        assert_eq!(func.call(5), 20);
+    }
+
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+    contxt.add_extern("labs");
+
+    let func = contxt.add_function("call_labs", vec![Type::i64], Type::i64);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+    let r = asm.var(Type::i64).unwrap();
+
+    func.push( Return::new(*Call::new("labs", vec![x], Some(r))) );
+
+    #[cfg(unix)]
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(i64) -> i64> = contxt.get_jit_function("call_labs")?;
+        assert_eq!(func.call(-7), 7);
+    }
+
+    let path = std::env::temp_dir().join("rllvm_externs.o");
+    contxt.write(path.to_str().unwrap())?;
+
+    let data = std::fs::read(&path)?;
+    std::fs::remove_file(&path)?;
+
+    let file = object::File::parse(&*data)?;
+
+    assert!(file.symbols().any(|sym| sym.name() == Ok("labs") && sym.is_undefined()));
+
+    Ok(())
+}
+
+/// Builds a function `conv` which converts its argument with the given ir
+fn conv<T: Compile + 'static>(ir: fn(VarGen, VarGen) -> Box<T>, from: Type, to: Type) -> Result<Context, Box<dyn Error>> {
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let func = contxt.add_function("conv", vec![from], to);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+    let out = asm.var(to).unwrap();
+
+    func.push( ir(out, x) );
+    func.push( Return::new(out) );
+
+    Ok(contxt)
+}
+
+#[test]
+fn conversions() -> Result<(), Box<dyn Error>> {
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(u8) -> u64> = conv(ZExt::new, Type::u8, Type::u64)?.get_jit_function("conv")?;
+        assert_eq!(func.call(200), 200);
+
+        let mut func: JitFunction<unsafe extern "C" fn(i8) -> i64> = conv(SExt::new, Type::i8, Type::i64)?.get_jit_function("conv")?;
+        assert_eq!(func.call(-5), -5);
+
+        let mut func: JitFunction<unsafe extern "C" fn(i32) -> i64> = conv(SExt::new, Type::i32, Type::i64)?.get_jit_function("conv")?;
+        assert_eq!(func.call(-70000), -70000);
+
+        let mut func: JitFunction<unsafe extern "C" fn(u64) -> u8> = conv(Trunc::new, Type::u64, Type::u8)?.get_jit_function("conv")?;
+        assert_eq!(func.call(0x1234), 0x34);
+
+        let mut func: JitFunction<unsafe extern "C" fn(i16) -> f64> = conv(SIToFP::new, Type::i16, Type::f64)?.get_jit_function("conv")?;
+        assert_eq!(func.call(-3), -3.0);
+
+        let mut func: JitFunction<unsafe extern "C" fn(u32) -> f32> = conv(UIToFP::new, Type::u32, Type::f32)?.get_jit_function("conv")?;
+        assert_eq!(func.call(u32::MAX), u32::MAX as f32);
+
+        let mut func: JitFunction<unsafe extern "C" fn(u64) -> f64> = conv(UIToFP::new, Type::u64, Type::f64)?.get_jit_function("conv")?;
+        assert_eq!(func.call(5), 5.0);
+        assert_eq!(func.call(u64::MAX), u64::MAX as f64);
+        assert_eq!(func.call((1 << 63) + 3), ((1_u64 << 63) + 3) as f64);
+
+        let mut func: JitFunction<unsafe extern "C" fn(f64) -> i32> = conv(FPToSI::new, Type::f64, Type::i32)?.get_jit_function("conv")?;
+        assert_eq!(func.call(-2.7), -2);
+
+        let mut func: JitFunction<unsafe extern "C" fn(f64) -> u64> = conv(FPToUI::new, Type::f64, Type::u64)?.get_jit_function("conv")?;
+        assert_eq!(func.call(3.9), 3);
+        assert_eq!(func.call(1.8e19), 18000000000000000000);
+
+        let mut func: JitFunction<unsafe extern "C" fn(f32) -> u32> = conv(FPToUI::new, Type::f32, Type::u32)?.get_jit_function("conv")?;
+        assert_eq!(func.call(4e9), 4000000000);
+
+        let mut func: JitFunction<unsafe extern "C" fn(f32) -> f64> = conv(FPExt::new, Type::f32, Type::f64)?.get_jit_function("conv")?;
+        assert_eq!(func.call(1.5), 1.5);
+
+        let mut func: JitFunction<unsafe extern "C" fn(f64) -> f32> = conv(FPTrunc::new, Type::f64, Type::f32)?.get_jit_function("conv")?;
+        assert_eq!(func.call(0.25), 0.25);
+
+        let mut func: JitFunction<unsafe extern "C" fn(f64) -> u64> = conv(Bitcast::new, Type::f64, Type::u64)?.get_jit_function("conv")?;
+        assert_eq!(func.call(1.0), 1.0_f64.to_bits());
+
+        let mut func: JitFunction<unsafe extern "C" fn(u32) -> f32> = conv(Bitcast::new, Type::u32, Type::f32)?.get_jit_function("conv")?;
+        assert_eq!(func.call(2.5_f32.to_bits()), 2.5);
+
+        assert!(conv(ZExt::new, Type::u64, Type::u8)?.get_jit_function::<unsafe extern "C" fn(u64) -> u8>("conv").is_err());
+    }
+
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let func = contxt.add_function("mixed", vec![Type::u8, Type::u32], Type::u32);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+    let y = asm.arg(1).unwrap();
+    let wide = asm.var(Type::u32).unwrap();
+
+    func.push( ZExt::new(wide, x) );
+    func.push( Return::new(*(wide + y)) );
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(u8, u32) -> u32> = contxt.get_jit_function("mixed")?;
+        assert_eq!(func.call(255, 1000), 1255);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn pointers() -> Result<(), Box<dyn Error>> {
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let func = contxt.add_function("sum", vec![Type::ptr_to(Type::u32), Type::u64], Type::u32);
+    let asm = func.asm_func()?;
+
+    let ptr = asm.arg(0).unwrap();
+    let len = asm.arg(1).unwrap();
+    let acc = asm.var(Type::u32).unwrap();
+    let i = asm.var(Type::u64).unwrap();
+    let one = asm.var(Type::u64).unwrap();
+    let value = asm.var(Type::u32).unwrap();
+    let elem = asm.var(Type::ptr).unwrap();
+    let cond = asm.var(Type::bool).unwrap();
+
+    func.push( acc ^ acc );
+    func.push( i ^ i );
+    func.push( i.equal(i, cond) );
+    func.push( ZExt::new(one, cond) );
+    func.push( Br::new("loop") );
+
+    func.add_block("loop");
+    func.position_at_end("loop")?;
+    func.push( i.lt(len, cond) );
+    func.push( CondBr::new(cond, "body", "done") );
+
+    func.add_block("body");
+    func.position_at_end("body")?;
+    func.push( GetElementPtr::element(elem, ptr, i)? );
+    func.push( Load::new(value, elem) );
+    func.push( acc + value );
+    func.push( i + one );
+    func.push( Br::new("loop") );
+
+    func.add_block("done");
+    func.position_at_end("done")?;
+    func.push( Return::new(acc) );
+
+    let func = contxt.add_function("set", vec![Type::ptr, Type::u16], Type::u64);
+    let asm = func.asm_func()?;
+
+    let ptr = asm.arg(0).unwrap();
+    let value = asm.arg(1).unwrap();
+    let field = asm.var(Type::ptr).unwrap();
+
+    func.push( GetElementPtr::new(field, ptr, None, 1, 2) );
+    func.push( Store::new(field, value) );
+    func.push( Return::new(field) );
+
+    // untyped pointers need the element type
+    let func = contxt.add_function("get", vec![Type::ptr, Type::u64], Type::u16);
+    let asm = func.asm_func()?;
+
+    let ptr = asm.arg(0).unwrap();
+    let index = asm.arg(1).unwrap();
+    let value = asm.var(Type::u16).unwrap();
+
+    assert!(GetElementPtr::element(ptr, ptr, index).is_err());
+
+    func.push( GetElementPtr::element_of(ptr, ptr, index, Type::u16) );
+    func.push( Return::new(*Load::new(value, ptr)) );
+
+    let func = contxt.add_function("through_slot", vec![Type::f64], Type::f64);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+    let slot = asm.alloca(Type::f64);
+    let adr = asm.var(Type::ptr).unwrap();
+    let y = asm.var(Type::f64).unwrap();
+
+    func.push( Alloca::new(slot) );
+    func.push( Store::new(slot, x) );
+    func.push( GetElementPtr::new(adr, slot, None, 1, 0) );
+    func.push( Load::new(y, adr) );
+    func.push( Return::new(*(y + x)) );
+
+    unsafe {
+        let data = [1_u32, 2, 3, 4, 5];
+
+        let mut func: JitFunction<unsafe extern "C" fn(*const u32, u64) -> u32> = contxt.get_jit_function("sum")?;
+        assert_eq!(func.call(data.as_ptr(), data.len() as u64), 15);
+
+        let mut data = [0_u16; 3];
+
+        let mut func: JitFunction<unsafe extern "C" fn(*mut u16, u16) -> u64> = contxt.get_jit_function("set")?;
+        assert_eq!(func.call(data.as_mut_ptr(), 7), data.as_ptr() as u64 + 2);
+        assert_eq!(data, [0, 7, 0]);
+
+        let mut func: JitFunction<unsafe extern "C" fn(*const u16, u64) -> u16> = contxt.get_jit_function("get")?;
+        assert_eq!(func.call(data.as_ptr(), 1), 7);
+
+        let mut func: JitFunction<unsafe extern "C" fn(f64) -> f64> = contxt.get_jit_function("through_slot")?;
+        assert_eq!(func.call(1.25), 2.5);
+    }
+
+    Ok(())
+}
+
+#[repr(C)]
+struct Particle {
+    id: u8,
+    pos: [f32; 3],
+    mass: f64,
+    flags: u16,
+}
+
+#[repr(C, packed)]
+struct Packed {
+    tag: u8,
+    value: u64,
+    small: u16,
+}
+
+#[test]
+fn aggregates() -> Result<(), Box<dyn Error>> {
+    let pos = Type::array_of(Type::f32, 3);
+    let particle = Type::new_struct(vec![Type::u8, pos, Type::f64, Type::u16], false);
+
+    assert_eq!(particle.size(), std::mem::size_of::<Particle>());
+    assert_eq!(particle.align(), std::mem::align_of::<Particle>());
+    assert_eq!(particle.size_in(&target_lexicon::Triple::host()), std::mem::size_of::<Particle>());
+    assert_eq!(particle.offset(0), Some(std::mem::offset_of!(Particle, id)));
+    assert_eq!(particle.offset(1), Some(std::mem::offset_of!(Particle, pos)));
+    assert_eq!(particle.offset(2), Some(std::mem::offset_of!(Particle, mass)));
+    assert_eq!(particle.offset(3), Some(std::mem::offset_of!(Particle, flags)));
+    assert_eq!(particle.offset(4), None);
+    assert_eq!(pos.offset(2), Some(8));
+
+    let packed = Type::new_struct(vec![Type::u8, Type::u64, Type::u16], true);
+
+    assert_eq!(packed.size(), std::mem::size_of::<Packed>());
+    assert_eq!(packed.offset(1), Some(std::mem::offset_of!(Packed, value)));
+    assert_eq!(packed.offset(2), Some(std::mem::offset_of!(Packed, small)));
+
+    let i686: target_lexicon::Triple = "i686-unknown-linux-gnu".parse().unwrap();
+    assert_eq!(particle.offset_in(2, &i686), Some(16));
+    assert_eq!(particle.align_in(&i686), 4);
+
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let func = contxt.add_function("update", vec![Type::ptr_to(particle), Type::u16], Type::f64);
+    let asm = func.asm_func()?;
+
+    let p = asm.arg(0).unwrap();
+    let flags = asm.arg(1).unwrap();
+    let field = asm.var(Type::ptr).unwrap();
+    let pos_ptr = asm.var(Type::ptr_to(pos)).unwrap();
+    let mass = asm.var(Type::f64).unwrap();
+    let z = asm.var(Type::f32).unwrap();
+    let wide = asm.var(Type::f64).unwrap();
+
+    func.push( GetElementPtr::field(field, p, 3)? );
+    func.push( Store::new(field, flags) );
+    func.push( GetElementPtr::field(field, p, 2)? );
+    func.push( Load::new(mass, field) );
+    func.push( GetElementPtr::field(pos_ptr, p, 1)? );
+    func.push( GetElementPtr::field(field, pos_ptr, 2)? );
+    func.push( Load::new(z, field) );
+    func.push( FPExt::new(wide, z) );
+    func.push( Return::new(*(mass + wide)) );
+
+    unsafe {
+        let mut particle = Particle { id: 1, pos: [1.0, 2.0, 3.5], mass: 10.0, flags: 0 };
+
+        let mut func: JitFunction<unsafe extern "C" fn(*mut Particle, u16) -> f64> = contxt.get_jit_function("update")?;
+        assert_eq!(func.call(&mut particle, 0xbeef), 13.5);
+        assert_eq!(particle.flags, 0xbeef);
+        assert_eq!(particle.id, 1);
+    }
+
+    // the field needs to exist
+    assert!(GetElementPtr::field(field, p, 4).is_err());
+    assert!(GetElementPtr::field(field, field, 0).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn globals() -> Result<(), Box<dyn Error>> {
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let table = Type::array_of(Type::u32, 3);
+
+    contxt.add_global("counter", Type::u64, Some(40_u64.to_le_bytes().to_vec()), true);
+    contxt.add_global("table", table, Some([10_u32, 20, 30].iter().flat_map(|x| x.to_le_bytes()).collect()), false);
+    contxt.add_global("zeroed", Type::u64, None, true);
+
+    let func = contxt.add_function("read", vec![], Type::u64);
+    let asm = func.asm_func()?;
+
+    let adr = asm.var(Type::ptr).unwrap();
+    let value = asm.var(Type::u64).unwrap();
+
+    func.push( GlobalAddr::new(adr, "counter") );
+    func.push( Load::new(value, adr) );
+    func.push( Return::new(value) );
+
+    let func = contxt.add_function("bump", vec![Type::u64, Type::u64], Type::u64);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+    let index = asm.arg(1).unwrap();
+    let adr = asm.var(Type::ptr_to(Type::u32)).unwrap();
+    let other = asm.var(Type::ptr).unwrap();
+    let entry = asm.var(Type::u32).unwrap();
+    let wide = asm.var(Type::u64).unwrap();
+    let value = asm.var(Type::u64).unwrap();
+
+    func.push( GlobalAddr::new(adr, "table") );
+    func.push( GetElementPtr::element(adr, adr, index)? );
+    func.push( Load::new(entry, adr) );
+    func.push( ZExt::new(wide, entry) );
+
+    func.push( GlobalAddr::new(other, "zeroed") );
+    func.push( Load::new(value, other) );
+    func.push( wide + value );
+    func.push( wide + x );
+
+    func.push( GlobalAddr::new(other, "counter") );
+    func.push( Load::new(value, other) );
+    func.push( value + wide );
+    func.push( Store::new(other, value) );
+
+    func.push( Return::new(*Call::new("read", vec![], Some(value))) );
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(u64, u64) -> u64> = contxt.get_jit_function("bump")?;
+        assert_eq!(func.call(2, 1), 62);
+
+        // the globals keep their values between calls
+        assert_eq!(func.call(2, 1), 84);
+        assert_eq!(*(contxt.global_adr("counter").unwrap() as *const u64), 84);
+    }
+
+    let path = std::env::temp_dir().join("rllvm_globals.o");
+    contxt.write(path.to_str().unwrap())?;
+
+    let data = std::fs::read(&path)?;
+    std::fs::remove_file(&path)?;
+
+    let file = object::File::parse(&*data)?;
+
+    let section = |name: &str| -> Result<String, Box<dyn Error>> {
+        let sym = file.symbols().find(|sym| sym.name() == Ok(name)).unwrap();
+        let section = file.section_by_index(sym.section_index().unwrap())?;
+
+        Ok(section.name()?.to_string())
+    };
+
+    if file.format() == object::BinaryFormat::Elf {
+        assert!(section("counter")?.starts_with(".data"));
+        assert!(section("table")?.starts_with(".rodata"));
+        assert!(section("zeroed")?.starts_with(".bss"));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn switch() -> Result<(), Box<dyn Error>> {
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    // dense cases are lowered to a jump table
+    let func = contxt.add_function("dense", vec![Type::i32, Type::i64, Type::i64], Type::i64);
+    let asm = func.asm_func()?;
+
+    let op = asm.arg(0).unwrap();
+    let a = asm.arg(1).unwrap();
+    let b = asm.arg(2).unwrap();
+    let out = asm.var(Type::i64).unwrap();
+
+    func.push( Switch::new(op, "join", vec![(0, "add"), (1, "sub"), (2, "mul"), (3, "xor"), (5, "or"), (-1, "join")]) );
+
+    let blocks: [(&str, Box<dyn Compile>); 3] = [("add", a + b), ("sub", a - b), ("mul", a * b)];
+
+    for (block, ir) in blocks {
+        func.add_block(block);
+        func.position_at_end(block)?;
+        func.push( ir );
+        func.push( Return::new(a) );
+    }
+
+    func.add_block("xor");
+    func.position_at_end("xor")?;
+    func.push( a ^ b );
+    func.push( Return::new(a) );
+
+    func.add_block("or");
+    func.position_at_end("or")?;
+    func.push( a | b );
+    func.push( Br::new("join") );
+
+    func.add_block("join");
+    func.position_at_end("join")?;
+    func.push( Phi::new(out, vec![("entry", b), ("or", a)]) );
+    func.push( Return::new(out) );
+
+    // sparse cases are searched
+    let func = contxt.add_function("sparse", vec![Type::u64, Type::u64], Type::u64);
+    let asm = func.asm_func()?;
+
+    let key = asm.arg(0).unwrap();
+    let x = asm.arg(1).unwrap();
+
+    let cases = vec![(1, "a"), (100, "b"), (1000, "a"), (100_000, "c"), (3_000_000_000, "b"), (-1, "c"), (7, "b")];
+    func.push( Switch::new(key, "default", cases) );
+
+    let blocks: [(&str, Box<dyn Compile>); 3] = [("a", x + x), ("b", x * x), ("c", x - x)];
+
+    for (block, ir) in blocks {
+        func.add_block(block);
+        func.position_at_end(block)?;
+        func.push( ir );
+        func.push( Return::new(x) );
+    }
+
+    func.add_block("default");
+    func.position_at_end("default")?;
+    func.push( Return::new(key) );
+
+    // few cases are compared one after another
+    let func = contxt.add_function("small", vec![Type::u8, Type::u64], Type::u64);
+    let asm = func.asm_func()?;
+
+    let key = asm.arg(0).unwrap();
+    let x = asm.arg(1).unwrap();
+
+    func.push( Switch::new(key, "default", vec![(1, "double"), (-1, "double")]) );
+
+    func.add_block("double");
+    func.position_at_end("double")?;
+    func.push( x + x );
+    func.push( Return::new(x) );
+
+    func.add_block("default");
+    func.position_at_end("default")?;
+    func.push( Return::new(x) );
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(i32, i64, i64) -> i64> = contxt.get_jit_function("dense")?;
+        assert_eq!(func.call(0, 6, 3), 9);
+        assert_eq!(func.call(1, 6, 3), 3);
+        assert_eq!(func.call(2, 6, 3), 18);
+        assert_eq!(func.call(3, 6, 3), 5);
+        assert_eq!(func.call(4, 6, 3), 3);
+        assert_eq!(func.call(5, 6, 3), 7);
+        assert_eq!(func.call(-1, 6, 3), 3);
+        assert_eq!(func.call(-2, 6, 3), 3);
+        assert_eq!(func.call(i32::MAX, 6, 3), 3);
+
+        let mut func: JitFunction<unsafe extern "C" fn(u64, u64) -> u64> = contxt.get_jit_function("sparse")?;
+        assert_eq!(func.call(1, 5), 10);
+        assert_eq!(func.call(100, 5), 25);
+        assert_eq!(func.call(1000, 5), 10);
+        assert_eq!(func.call(100_000, 5), 0);
+        assert_eq!(func.call(3_000_000_000, 5), 25);
+        assert_eq!(func.call(u64::MAX, 5), 0);
+        assert_eq!(func.call(7, 5), 25);
+        assert_eq!(func.call(8, 5), 8);
+
+        let mut func: JitFunction<unsafe extern "C" fn(u8, u64) -> u64> = contxt.get_jit_function("small")?;
+        assert_eq!(func.call(1, 5), 10);
+        assert_eq!(func.call(255, 5), 10);
+        assert_eq!(func.call(2, 5), 5);
+    }
+
+    let path = std::env::temp_dir().join("rllvm_switch.o");
+    contxt.write(path.to_str().unwrap())?;
+
+    let data = std::fs::read(&path)?;
+    std::fs::remove_file(&path)?;
+
+    let file = object::File::parse(&*data)?;
+
+    assert!(file.symbols().any(|sym| sym.name().is_ok_and(|name| name.starts_with(".Ldense."))));
+    assert!(file.sections().any(|section| section.relocations().count() > 0));
+
+    Ok(())
+}
+
+#[test]
+fn immediates() -> Result<(), Box<dyn Error>> {
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let func = contxt.add_function("int", vec![Type::i64], Type::i64);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+
+    func.push( x + 5 );
+    func.push( x * 3 );
+    func.push( 100 - x );
+    func.push( x ^ 0x0F );
+    func.push( Return::new(*(x + 0x1_0000_0000_i64)) );
+
+    let func = contxt.add_function("byte", vec![Type::u8], Type::u8);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+
+    func.push( 3 * x );
+    func.push( Return::new(*(x - 1_i64)) );
+
+    let func = contxt.add_function("float", vec![Type::f64], Type::f64);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+
+    func.push( x * 2.5 );
+    func.push( x + 1.0 );
+    func.push( 1.0 / x );
+    func.push( Return::new(*(8.0_f64 - x)) );
+
+    let func = contxt.add_function("single", vec![Type::f32], Type::f32);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+
+    // the i32/f32 operators
+    func.push( x - 0.5_f32 );
+    func.push( 1.0_f32 + x );
+    func.push( x / 0.5_f32 );
+    func.push( Return::new(*(x * 4_i32)) );
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(i64) -> i64> = contxt.get_jit_function("int")?;
+        assert_eq!(func.call(2), ((100 - (2 + 5) * 3) ^ 0x0F) + 0x1_0000_0000);
+
+        let mut func: JitFunction<unsafe extern "C" fn(u8) -> u8> = contxt.get_jit_function("byte")?;
+        assert_eq!(func.call(100), 43);
+
+        let mut func: JitFunction<unsafe extern "C" fn(f64) -> f64> = contxt.get_jit_function("float")?;
+        assert_eq!(func.call(1.0), 8.0 - 1.0 / 3.5);
+
+        let mut func: JitFunction<unsafe extern "C" fn(f32) -> f32> = contxt.get_jit_function("single")?;
+        assert_eq!(func.call(1.5), 16.0);
+    }
+
+    let func = contxt.add_function("invalid", vec![Type::i32], Type::i32);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+
+    func.push( x + 1.5 );
+
+    assert!(unsafe { contxt.get_jit_function::<unsafe extern "C" fn(i32) -> i32>("invalid") }.is_err());
+
+    Ok(())
+}
+
+extern "C" fn shift(x: i128, n: u64) -> i128 {
+    x << n
+}
+
+#[test]
+fn wide_types() -> Result<(), Box<dyn Error>> {
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+    contxt.add_extern_at("shift", shift as *const () as usize);
+
+    let func = contxt.add_function("join", vec![Type::u64, Type::u64], Type::u128);
+    let asm = func.asm_func()?;
+
+    let lo = asm.arg(0).unwrap();
+    let hi = asm.arg(1).unwrap();
+    let slot = asm.alloca(Type::u128);
+
+    // values which don't fit into a register need a stack slot
+    assert!(asm.var(Type::u128).is_none());
+    assert!(asm.var(Type::void).is_none());
+    assert!(asm.var(Type::array_of(Type::u8, 4)).is_none());
+    let adr = asm.var(Type::ptr).unwrap();
+
+    func.push( GetElementPtr::new(adr, slot, None, 1, 0) );
+    func.push( Store::new(adr, lo) );
+    func.push( GetElementPtr::new(adr, slot, None, 1, 8) );
+    func.push( Store::new(adr, hi) );
+    func.push( Return::new(slot) );
+
+    let func = contxt.add_function("high", vec![Type::u8, Type::u128], Type::u64);
+    let asm = func.asm_func()?;
+
+    let value = asm.arg(1).unwrap();
+    let adr = asm.var(Type::ptr).unwrap();
+    let out = asm.var(Type::u64).unwrap();
+
+    func.push( GetElementPtr::new(adr, value, None, 1, 8) );
+    func.push( Return::new(*Load::new(out, adr)) );
+
+    let func = contxt.add_function("shift_by", vec![Type::i128, Type::u64], Type::i128);
+    let asm = func.asm_func()?;
+
+    let value = asm.arg(0).unwrap();
+    let n = asm.arg(1).unwrap();
+    let out = asm.alloca(Type::i128);
+
+    func.push( Return::new(*Call::new("shift", vec![value, n], Some(out))) );
+
+    let func = contxt.add_function("store", vec![Type::ptr, Type::usize], Type::void);
+    let asm = func.asm_func()?;
+
+    let adr = asm.arg(0).unwrap();
+    let value = asm.arg(1).unwrap();
+
+    func.push( Store::new(adr, value) );
+    func.push( Return::new(()) );
+
+    let func = contxt.add_function("offset", vec![Type::ptr, Type::isize, Type::usize], Type::isize);
+    let asm = func.asm_func()?;
+
+    let adr = asm.arg(0).unwrap();
+    let x = asm.arg(1).unwrap();
+    let y = asm.arg(2).unwrap();
+
+    func.push( Call::new("store", vec![adr, y], None) );
+    func.push( Return::new(*(x + y)) );
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(u64, u64) -> u128> = contxt.get_jit_function("join")?;
+        assert_eq!(func.call(1, 2), (2 << 64) | 1);
+
+        let mut func: JitFunction<unsafe extern "C" fn(u8, u128) -> u64> = contxt.get_jit_function("high")?;
+        assert_eq!(func.call(0, (7 << 64) | 3), 7);
+
+        let mut func: JitFunction<unsafe extern "C" fn(i128, u64) -> i128> = contxt.get_jit_function("shift_by")?;
+        assert_eq!(func.call(-3, 70), -3 << 70);
+
+        let mut value = 0_usize;
+        let mut func: JitFunction<unsafe extern "C" fn(*mut usize, isize, usize) -> isize> = contxt.get_jit_function("offset")?;
+        assert_eq!(func.call(&mut value, -5, 8), 3);
+        assert_eq!(value, 8);
+    }
+
+    let i686 = "i686-unknown-linux-gnu".parse::<target_lexicon::Triple>().unwrap();
+
+    assert_eq!(Type::usize.size(), 8);
+    assert_eq!(Type::usize.size_in(&i686), 4);
+    assert_eq!(Type::i128.size(), 16);
+    assert_eq!(Type::i128.align(), 16);
+    assert_eq!(Type::void.size(), 0);
+    assert!(!Type::u128.reg());
+
+    let func = contxt.add_function("named", vec![Type::i128, Type::isize], Type::void);
+    func.public();
+    func.maybe_renaming();
+
+    assert_eq!(func.name(), "_R0Z0Z5namedZ4i1285isizeZ4void");
+
+    Ok(())
+}
+
+#[test]
+fn simd() -> Result<(), Box<dyn Error>> {
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let f32x4 = Type::vector_of(Type::f32, 4);
+    let i32x4 = Type::vector_of(Type::i32, 4);
+    let u8x16 = Type::vector_of(Type::u8, 16);
+    let f64x2 = Type::vector_of(Type::f64, 2);
+
+    // out = a * b + a, returns the sum of the lanes
+    let func = contxt.add_function("fma", vec![Type::ptr, Type::ptr, Type::ptr], Type::f32);
+    let asm = func.asm_func()?;
+
+    let pa = asm.arg(0).unwrap();
+    let pb = asm.arg(1).unwrap();
+    let pout = asm.arg(2).unwrap();
+    let a = asm.var(f32x4).unwrap();
+    let b = asm.var(f32x4).unwrap();
+    let sum = asm.var(Type::f32).unwrap();
+
+    func.push( Load::new(a, pa) );
+    func.push( Load::new(b, pb) );
+    func.push( b * a );
+    func.push( b + a );
+    func.push( Store::new(pout, b) );
+    func.push( Reduce::new(ReduceOp::Add, sum, b) );
+    func.push( Return::new(sum) );
+
+    // shuffles, inserts and extracts lanes
+    let func = contxt.add_function("lanes", vec![Type::ptr, Type::ptr, Type::i32], Type::i32);
+    let asm = func.asm_func()?;
+
+    let pa = asm.arg(0).unwrap();
+    let pout = asm.arg(1).unwrap();
+    let x = asm.arg(2).unwrap();
+    let a = asm.var(i32x4).unwrap();
+    let b = asm.var(i32x4).unwrap();
+    let out = asm.var(Type::i32).unwrap();
+
+    func.push( Load::new(a, pa) );
+    func.push( Shuffle::new(b, a, a, vec![3, 2, 1, 0]) );
+    func.push( InsertElement::new(b, x, 1) );
+    func.push( Shuffle::new(a, a, b, vec![0, 4, 1, 5]) );
+    func.push( Store::new(pout, a) );
+    func.push( ExtractElement::new(out, b, 1) );
+    func.push( Reduce::new(ReduceOp::Max, out, a) );
+    func.push( Return::new(out) );
+
+    // wrapping bytes
+    let func = contxt.add_function("bytes", vec![Type::ptr], Type::u8);
+    let asm = func.asm_func()?;
+
+    let pa = asm.arg(0).unwrap();
+    let a = asm.var(u8x16).unwrap();
+    let b = asm.var(u8x16).unwrap();
+    let min = asm.var(Type::u8).unwrap();
+    let sum = asm.var(Type::u8).unwrap();
+
+    func.push( Load::new(a, pa) );
+    func.push( Load::new(b, pa) );
+    func.push( a + b );
+    func.push( Reduce::new(ReduceOp::Min, min, a) );
+    func.push( Reduce::new(ReduceOp::Add, sum, a) );
+    func.push( Return::new(*(sum ^ min)) );
+
+    let func = contxt.add_function("halfs", vec![Type::ptr], Type::f64);
+    let asm = func.asm_func()?;
+
+    let pa = asm.arg(0).unwrap();
+    let a = asm.var(f64x2).unwrap();
+    let b = asm.var(f64x2).unwrap();
+    let out = asm.var(Type::f64).unwrap();
+
+    func.push( Load::new(a, pa) );
+    func.push( Shuffle::new(b, a, a, vec![1, 0]) );
+    func.push( a / b );
+    func.push( ExtractElement::new(out, a, 1) );
+    func.push( Return::new(out) );
+
+    unsafe {
+        let a = [1.0_f32, 2.0, 3.0, 4.0];
+        let b = [2.0_f32, 2.0, 0.5, -1.0];
+        let mut out = [0.0_f32; 4];
+
+        let mut func: JitFunction<unsafe extern "C" fn(*const f32, *const f32, *mut f32) -> f32> = contxt.get_jit_function("fma")?;
+        assert_eq!(func.call(a.as_ptr(), b.as_ptr(), out.as_mut_ptr()), 13.5);
+        assert_eq!(out, [3.0, 6.0, 4.5, 0.0]);
+
+        let a = [10_i32, -20, 30, -40];
+        let mut out = [0_i32; 4];
+
+        let mut func: JitFunction<unsafe extern "C" fn(*const i32, *mut i32, i32) -> i32> = contxt.get_jit_function("lanes")?;
+        assert_eq!(func.call(a.as_ptr(), out.as_mut_ptr(), 7), 10);
+        assert_eq!(out, [10, -40, -20, 7]);
+        assert_eq!(func.call(a.as_ptr(), out.as_mut_ptr(), 99), 99);
+
+        let a: Vec<u8> = (120..136).collect();
+
+        let mut func: JitFunction<unsafe extern "C" fn(*const u8) -> u8> = contxt.get_jit_function("bytes")?;
+        let lanes: Vec<u8> = a.iter().map(|x| x.wrapping_add(*x)).collect();
+        let sum = lanes.iter().fold(0_u8, |sum, x| sum.wrapping_add(*x));
+        assert_eq!(func.call(a.as_ptr()), sum ^ lanes.iter().min().unwrap());
+
+        let a = [3.0_f64, 12.0];
+
+        let mut func: JitFunction<unsafe extern "C" fn(*const f64) -> f64> = contxt.get_jit_function("halfs")?;
+        assert_eq!(func.call(a.as_ptr()), 4.0);
+    }
+
+    // there is no instruction for multiplying bytes
+    let func = contxt.add_function("invalid", vec![Type::ptr], Type::void);
+    let asm = func.asm_func()?;
+
+    let a = asm.var(u8x16).unwrap();
+    func.push( a * a );
+
+    assert!(unsafe { contxt.get_jit_function::<unsafe extern "C" fn()>("invalid") }.is_err());
+
+    Ok(())
+}
+
+#[test]
+fn simd_avx() -> Result<(), Box<dyn Error>> {
+    if !std::arch::is_x86_feature_detected!("avx") {
+        return Ok(());
+    }
+
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let f32x8 = Type::vector_of(Type::f32, 8);
+
+    let func = contxt.add_function("none", vec![], Type::void);
+    assert!(func.asm_func()?.var(f32x8).is_none());
+
+    contxt.enable_avx();
+
+    let func = contxt.add_function("wide", vec![Type::ptr, Type::f32], Type::f32);
+    let asm = func.asm_func()?;
+
+    let pa = asm.arg(0).unwrap();
+    let x = asm.arg(1).unwrap();
+    let a = asm.var(f32x8).unwrap();
+    let b = asm.var(f32x8).unwrap();
+    let out = asm.var(Type::f32).unwrap();
+
+    func.push( Load::new(a, pa) );
+    func.push( Load::new(b, pa) );
+    func.push( a * b );
+    func.push( InsertElement::new(a, x, 6) );
+    func.push( Store::new(pa, a) );
+    func.push( ExtractElement::new(out, a, 7) );
+    func.push( Reduce::new(ReduceOp::Max, x, a) );
+    func.push( Return::new(*(x + out)) );
+
+    unsafe {
+        let mut a = [1.0_f32, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0];
+
+        let mut func: JitFunction<unsafe extern "C" fn(*mut f32, f32) -> f32> = contxt.get_jit_function("wide")?;
+        assert_eq!(func.call(a.as_mut_ptr(), 100.0), 164.0);
+        assert_eq!(a, [1.0, 4.0, 9.0, 16.0, 25.0, 36.0, 100.0, 64.0]);
+    }
+
+    Ok(())
+}
+
+fn rmw(contxt: &mut Context, name: &str, op: AtomicOp, typ: Type, flip: bool) -> Result<(), Box<dyn Error>> {
+    let func = contxt.add_function(name, vec![Type::ptr, typ], typ);
+    let asm = func.asm_func()?;
+
+    let ptr = asm.arg(0).unwrap();
+    let x = asm.arg(1).unwrap();
+    let a = asm.var(typ).unwrap();
+    let b = asm.var(typ).unwrap();
+
+    // the second variable is stored in rax (on the system v abi) which is also used by cmpxchg
+    let (out, value) = if flip { (b, a) } else { (a, b) };
+
+    func.push( value & 0 );
+    func.push( value + x );
+    func.push( AtomicRMW::new(op, out, ptr, value, AtomicOrdering::SeqCst) );
+    func.push( Return::new(out) );
+
+    Ok(())
+}
+
+#[test]
+fn atomics() -> Result<(), Box<dyn Error>> {
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let ops = [AtomicOp::Add, AtomicOp::Sub, AtomicOp::And, AtomicOp::Or, AtomicOp::Xor, AtomicOp::Xchg, AtomicOp::Min, AtomicOp::Max];
+
+    for (nr, op) in ops.iter().enumerate() {
+        rmw(&mut contxt, &format!("rmw{}", nr), *op, Type::i64, nr % 2 == 1)?;
+    }
+
+    rmw(&mut contxt, "umax", AtomicOp::Max, Type::u32, false)?;
+
+    let func = contxt.add_function("cas", vec![Type::ptr, Type::i32, Type::i32], Type::i32);
+    let asm = func.asm_func()?;
+
+    let ptr = asm.arg(0).unwrap();
+    let expected = asm.arg(1).unwrap();
+    let new = asm.arg(2).unwrap();
+    let old = asm.var(Type::i32).unwrap();
+    let success = asm.var(Type::bool).unwrap();
+
+    func.push( CmpXchg::new(old, Some(success), ptr, expected, new, AtomicOrdering::AcqRel) );
+    func.push( Fence::new(AtomicOrdering::SeqCst) );
+    func.push( AtomicStore::new(ptr, new, AtomicOrdering::SeqCst) );
+    func.push( AtomicLoad::new(new, ptr, AtomicOrdering::Acquire) );
+    func.push( ZExt::new(old, success) );
+    func.push( Return::new(*(old + new)) );
+
+    unsafe {
+        let results = [22, 2, 8, 14, 6, 10, -3, 12];
+
+        for (nr, result) in results.iter().enumerate() {
+            let value = AtomicI64::new(12);
+
+            let mut func: JitFunction<unsafe extern "C" fn(*const AtomicI64, i64) -> i64> = contxt.get_jit_function(&format!("rmw{}", nr))?;
+            let operand = if nr >= 6 { -3 } else { 10 };
+
+            assert_eq!(func.call(&value, operand), 12);
+            assert_eq!(value.load(Ordering::SeqCst), *result);
+        }
+
+        let value = AtomicU32::new(12);
+
+        let mut func: JitFunction<unsafe extern "C" fn(*const AtomicU32, u32) -> u32> = contxt.get_jit_function("umax")?;
+        assert_eq!(func.call(&value, 0xFFFF_FFFD), 12);
+        assert_eq!(value.load(Ordering::SeqCst), 0xFFFF_FFFD);
+
+        let value = AtomicI32::new(5);
+
+        let mut func: JitFunction<unsafe extern "C" fn(*const AtomicI32, i32, i32) -> i32> = contxt.get_jit_function("cas")?;
+        assert_eq!(func.call(&value, 4, 100), 100);
+        assert_eq!(func.call(&value, 100, 7), 8);
+        assert_eq!(value.load(Ordering::SeqCst), 7);
+    }
+
+    let func = contxt.add_function("float", vec![Type::ptr, Type::f64], Type::void);
+    let asm = func.asm_func()?;
+
+    let ptr = asm.arg(0).unwrap();
+    let x = asm.arg(1).unwrap();
+
+    func.push( AtomicStore::new(ptr, x, AtomicOrdering::Relaxed) );
+
+    assert!(unsafe { contxt.get_jit_function::<unsafe extern "C" fn()>("float") }.is_err());
+
+    Ok(())
+}
+
+fn unary(contxt: &mut Context, name: &str, ir: fn(VarGen, VarGen) -> Box<Call>, typ: Type) -> Result<(), Box<dyn Error>> {
+    let func = contxt.add_function(name, vec![typ], typ);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+    let out = asm.var(typ).unwrap();
+
+    func.push( ir(out, x) );
+    func.push( Return::new(out) );
+
+    Ok(())
+}
+
+fn bit_intrinsics(features: bool, bmi1: bool) -> Result<(), Box<dyn Error>> {
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    if features {
+        contxt.enable_popcnt();
+        contxt.enable_lzcnt();
+    }
+
+    if bmi1 {
+        contxt.enable_bmi1();
+    }
+
+    let ops: [fn(VarGen, VarGen) -> Box<Call>; 4] = [intrinsics::ctpop, intrinsics::ctlz, intrinsics::cttz, intrinsics::bswap];
+
+    for (nr, op) in ops.iter().enumerate() {
+        unary(&mut contxt, &format!("u64_{}", nr), *op, Type::u64)?;
+        unary(&mut contxt, &format!("u16_{}", nr), *op, Type::u16)?;
+    }
+
+    let func = contxt.add_function("rot", vec![Type::u32, Type::u8], Type::u32);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+    let n = asm.arg(1).unwrap();
+    let out = asm.var(Type::u32).unwrap();
+
+    func.push( intrinsics::rotl(out, x, n) );
+    func.push( intrinsics::rotr(x, out, out) );
+    func.push( intrinsics::max(out, x, out) );
+    func.push( Return::new(out) );
+
+    let func = contxt.add_function("min", vec![Type::i16, Type::i16], Type::i16);
+    let asm = func.asm_func()?;
+
+    let a = asm.arg(0).unwrap();
+    let b = asm.arg(1).unwrap();
+
+    func.push( intrinsics::min(b, a, b) );
+    func.push( Return::new(b) );
+
+    unsafe {
+        let u64_ops: [fn(u64) -> u64; 4] = [|x| x.count_ones() as u64, |x| x.leading_zeros() as u64, |x| x.trailing_zeros() as u64, u64::swap_bytes];
+        let u16_ops: [fn(u16) -> u16; 4] = [|x| x.count_ones() as u16, |x| x.leading_zeros() as u16, |x| x.trailing_zeros() as u16, u16::swap_bytes];
+
+        for nr in 0..4 {
+            let mut func: JitFunction<unsafe extern "C" fn(u64) -> u64> = contxt.get_jit_function(&format!("u64_{}", nr))?;
+
+            for x in [0, 1, 0x8000_0000_0000_0000, 0x0123_4567_89AB_CDEF, u64::MAX] {
+                assert_eq!(func.call(x), u64_ops[nr](x));
+            }
+
+            let mut func: JitFunction<unsafe extern "C" fn(u16) -> u16> = contxt.get_jit_function(&format!("u16_{}", nr))?;
+
+            for x in [0, 1, 0x8000, 0x0F0A, u16::MAX] {
+                assert_eq!(func.call(x), u16_ops[nr](x));
+            }
+        }
+
+        let mut func: JitFunction<unsafe extern "C" fn(u32, u8) -> u32> = contxt.get_jit_function("rot")?;
+
+        // the rotated value is rotated back by itself
+        for (x, n) in [(0x8000_0001_u32, 1_u8), (0x1234_5678, 36), (7, 0)] {
+            let out = x.rotate_left(n as u32);
+            assert_eq!(func.call(x, n), out.rotate_right(out).max(out));
+        }
+
+        let mut func: JitFunction<unsafe extern "C" fn(i16, i16) -> i16> = contxt.get_jit_function("min")?;
+        assert_eq!(func.call(-5, 3), -5);
+        assert_eq!(func.call(7, -300), -300);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn intrinsics() -> Result<(), Box<dyn Error>> {
+    bit_intrinsics(false, false)?;
+
+    // tzcnt needs BMI1 (lzcnt doesn't imply it)
+    if std::arch::is_x86_feature_detected!("popcnt") && std::arch::is_x86_feature_detected!("lzcnt") {
+        bit_intrinsics(true, false)?;
+
+        if std::arch::is_x86_feature_detected!("bmi1") {
+            bit_intrinsics(true, true)?;
+        }
+    }
+
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    for name in ["memcpy", "memmove", "memset"] {
+        let value = if name == "memset" { Type::u8 } else { Type::ptr };
+
+        let func = contxt.add_function(name, vec![Type::ptr, value, Type::u32], Type::void);
+        let asm = func.asm_func()?;
+
+        let dst = asm.arg(0).unwrap();
+        let src = asm.arg(1).unwrap();
+        let len = asm.arg(2).unwrap();
+
+        func.push( match name {
+            "memcpy" => intrinsics::memcpy(dst, src, len),
+            "memmove" => intrinsics::memmove(dst, src, len),
+            _ => intrinsics::memset(dst, src, len),
+        } );
+        func.push( Return::new(()) );
+    }
+
+    let func = contxt.add_function("float", vec![Type::f64, Type::f64, Type::f64], Type::f64);
+    let asm = func.asm_func()?;
+
+    let a = asm.arg(0).unwrap();
+    let b = asm.arg(1).unwrap();
+    let c = asm.arg(2).unwrap();
+    let out = asm.var(Type::f64).unwrap();
+
+    // calls fma of the libc (because the fma instructions aren't enabled)
+    func.push( intrinsics::fma(out, a, b, c) );
+    func.push( intrinsics::fabs(out, out) );
+    func.push( intrinsics::sqrt(out, out) );
+    func.push( intrinsics::max(out, out, a) );
+    func.push( Return::new(out) );
+
+    let func = contxt.add_function("single", vec![Type::f32, Type::f32], Type::f32);
+    let asm = func.asm_func()?;
+
+    let a = asm.arg(0).unwrap();
+    let b = asm.arg(1).unwrap();
+
+    func.push( intrinsics::min(b, a, b) );
+    func.push( intrinsics::fabs(b, b) );
+    func.push( Return::new(b) );
+
+    unsafe {
+        let mut memcpy: JitFunction<unsafe extern "C" fn(*mut u8, *const u8, u32)> = contxt.get_jit_function("memcpy")?;
+        let mut memmove: JitFunction<unsafe extern "C" fn(*mut u8, *const u8, u32)> = contxt.get_jit_function("memmove")?;
+        let mut memset: JitFunction<unsafe extern "C" fn(*mut u8, u8, u32)> = contxt.get_jit_function("memset")?;
+
+        let mut buf = [1_u8, 2, 3, 4, 5, 6, 7, 8];
+        let ptr = buf.as_mut_ptr();
+
+        memcpy.call(ptr, [9_u8, 9].as_ptr(), 2);
+        assert_eq!(buf, [9, 9, 3, 4, 5, 6, 7, 8]);
+
+        memmove.call(ptr.add(2), ptr, 5);
+        assert_eq!(buf, [9, 9, 9, 9, 3, 4, 5, 8]);
+
+        memmove.call(ptr, ptr.add(3), 5);
+        assert_eq!(buf, [9, 3, 4, 5, 8, 4, 5, 8]);
+
+        memset.call(ptr.add(1), 0, 6);
+        assert_eq!(buf, [9, 0, 0, 0, 0, 0, 0, 8]);
+
+        let mut func: JitFunction<unsafe extern "C" fn(f64, f64, f64) -> f64> = contxt.get_jit_function("float")?;
+        assert_eq!(func.call(-2.0, 10.0, 4.0), 4.0);
+        assert_eq!(func.call(-0.1, -10.0, -1.0), (-0.1_f64).mul_add(-10.0, -1.0).abs().sqrt());
+
+        let mut func: JitFunction<unsafe extern "C" fn(f32, f32) -> f32> = contxt.get_jit_function("single")?;
+        assert_eq!(func.call(-1.5, 3.0), 1.5);
+    }
+
+    if std::arch::is_x86_feature_detected!("fma") {
+        contxt.enable_fma();
+
+        let func = contxt.add_function("fused", vec![Type::f32, Type::f32, Type::f32], Type::f32);
+        let asm = func.asm_func()?;
+
+        let a = asm.arg(0).unwrap();
+        let b = asm.arg(1).unwrap();
+        let c = asm.arg(2).unwrap();
+
+        func.push( intrinsics::fma(c, a, b, c) );
+        func.push( Return::new(c) );
+
+        unsafe {
+            let mut func: JitFunction<unsafe extern "C" fn(f32, f32, f32) -> f32> = contxt.get_jit_function("fused")?;
+            assert_eq!(func.call(0.1, 10.0, -1.0), 0.1_f32.mul_add(10.0, -1.0));
+        }
+    }
+
+    let func = contxt.add_function("invalid", vec![Type::f64], Type::f64);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+
+    func.push( intrinsics::ctpop(x, x) );
+
+    assert!(unsafe { contxt.get_jit_function::<unsafe extern "C" fn()>("invalid") }.is_err());
+
+    Ok(())
+}
+
+fn with_overflow(contxt: &mut Context, typ: Type) -> Result<(), Box<dyn Error>> {
+    for op in ["add", "sub", "mul"] {
+        let func = contxt.add_function(&format!("{}_{}", op, typ.name()), vec![Type::ptr, typ, typ], Type::bool);
+        let asm = func.asm_func()?;
+
+        let ptr = asm.arg(0).unwrap();
+        let a = asm.arg(1).unwrap();
+        let b = asm.arg(2).unwrap();
+        let out = asm.var(typ).unwrap();
+        let overflow = asm.var(Type::bool).unwrap();
+
+        func.push( match op {
+            "add" => AddWithOverflow::new(out, Some(overflow), a, b) as Box<dyn Compile>,
+            "sub" => SubWithOverflow::new(out, Some(overflow), a, b),
+            _ => MulWithOverflow::new(b, Some(overflow), a, b),
+        } );
+
+        func.push( Store::new(ptr, if op == "mul" { b } else { out }) );
+        func.push( Return::new(overflow) );
+    }
+
+    Ok(())
+}
+
+macro_rules! check_overflow {
+    ($contxt:expr, $typ:ty, $values:expr) => {
+        for op in ["add", "sub", "mul"] {
+            let name = format!("{}_{}", op, stringify!($typ));
+            let mut func: JitFunction<unsafe extern "C" fn(*mut $typ, $typ, $typ) -> bool> = $contxt.get_jit_function(&name)?;
+
+            for a in $values {
+                for b in $values {
+                    let (result, overflow) = match op {
+                        "add" => a.overflowing_add(b),
+                        "sub" => a.overflowing_sub(b),
+                        _ => a.overflowing_mul(b),
+                    };
+
+                    let mut out = 0;
+
+                    assert_eq!(func.call(&mut out, a, b), overflow, "{} {} {}", name, a, b);
+                    assert_eq!(out, result, "{} {} {}", name, a, b);
+                }
+            }
+        }
+    };
+}
+
+#[test]
+fn overflow() -> Result<(), Box<dyn Error>> {
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    for typ in [Type::i8, Type::u8, Type::i16, Type::u16, Type::i32, Type::u32, Type::i64, Type::u64] {
+        with_overflow(&mut contxt, typ)?;
+    }
+
+    let func = contxt.add_function("checked", vec![Type::i32, Type::i32], Type::i32);
+    let asm = func.asm_func()?;
+
+    let a = asm.arg(0).unwrap();
+    let b = asm.arg(1).unwrap();
+
+    func.push( MulWithOverflow::new_trap(a, a, b, "trap") );
+    func.push( AddWithOverflow::new_trap(a, a, b, "trap") );
+    func.push( Return::new(a) );
+
+    func.add_block("trap");
+    func.position_at_end("trap")?;
+    func.push( Return::new(-1) );
+
+    unsafe {
+        check_overflow!(contxt, i8, [0_i8, 1, -1, 2, 100, -100, i8::MAX, i8::MIN]);
+        check_overflow!(contxt, u8, [0_u8, 1, 2, 15, 16, 100, u8::MAX]);
+        check_overflow!(contxt, i16, [0_i16, 1, -1, 255, -256, i16::MAX, i16::MIN]);
+        check_overflow!(contxt, u16, [0_u16, 1, 255, 256, u16::MAX]);
+        check_overflow!(contxt, i32, [0_i32, 1, -1, 65536, -65536, i32::MAX, i32::MIN]);
+        check_overflow!(contxt, u32, [0_u32, 1, 65535, 65536, u32::MAX]);
+        check_overflow!(contxt, i64, [0_i64, 1, -1, 1 << 32, -(1 << 32), i64::MAX, i64::MIN]);
+        check_overflow!(contxt, u64, [0_u64, 1, 1 << 32, u64::MAX]);
+
+        let mut func: JitFunction<unsafe extern "C" fn(i32, i32) -> i32> = contxt.get_jit_function("checked")?;
+        assert_eq!(func.call(6, 7), 49);
+        assert_eq!(func.call(65536, 65536), -1);
+        assert_eq!(func.call(i32::MAX, 1), -1);
+    }
+
+    Ok(())
+}
+
+static TRAPS: std::sync::Mutex<Vec<(String, u32, usize)>> = std::sync::Mutex::new(vec![]);
+
+extern "C" fn trap_handler(func: *const std::ffi::c_char, code: u32, at: usize) {
+    let func = unsafe { std::ffi::CStr::from_ptr(func) }.to_string_lossy().to_string();
+
+    TRAPS.lock().unwrap().push((func, code, at));
+}
+
+#[test]
+fn traps() -> Result<(), Box<dyn Error>> {
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let func = contxt.add_function("crash", vec![], Type::void);
+    func.push( Trap::new(1) );
+
+    let code = func.compile_ir()?.compile()?;
+    assert!(code.windows(2).any(|bytes| bytes == [0x0F, 0x0B])); // ud2
+
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+    contxt.set_trap_handler(trap_handler);
+
+    let func = contxt.add_function("div", vec![Type::i64, Type::i64], Type::i64);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+    let y = asm.arg(1).unwrap();
+    let z = asm.var(Type::i64).unwrap();
+    let zero = asm.var(Type::bool).unwrap();
+
+    func.push( z & 0 );
+    func.push( y.equal(z, zero) );
+    func.push( CondBr::new(zero, "zero", "ok") );
+
+    func.add_block("zero");
+    func.position_at_end("zero")?;
+    func.push( Trap::new(1) );
+
+    func.add_block("ok");
+    func.position_at_end("ok")?;
+    func.push( Return::new(*(x / y)) );
+
+    let func = contxt.add_function("never", vec![], Type::f64);
+    func.push( Unreachable::new() );
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(i64, i64) -> i64> = contxt.get_jit_function("div")?;
+        assert_eq!(func.call(10, 2), 5);
+        assert_eq!(func.call(10, 0), 0);
+
+        let mut func: JitFunction<unsafe extern "C" fn() -> f64> = contxt.get_jit_function("never")?;
+        assert_eq!(func.call(), 0.0);
+    }
+
+    assert_eq!(*TRAPS.lock().unwrap(), [("div".to_string(), 1, 3), ("never".to_string(), Unreachable::CODE, 0)]);
+
+    Ok(())
+}
+
+#[test]
+fn tail_calls() -> Result<(), Box<dyn Error>> {
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    // would need gigabytes of stack with normal calls
+    let func = contxt.add_function("sum", vec![Type::u64, Type::u64], Type::u64);
+    let asm = func.asm_func()?;
+
+    let n = asm.arg(0).unwrap();
+    let acc = asm.arg(1).unwrap();
+    let zero = asm.var(Type::u64).unwrap();
+    let cond = asm.var(Type::bool).unwrap();
+
+    func.push( zero & 0 );
+    func.push( n.equal(zero, cond) );
+    func.push( CondBr::new(cond, "done", "next") );
+
+    func.add_block("done");
+    func.position_at_end("done")?;
+    func.push( Return::new(acc) );
+
+    func.add_block("next");
+    func.position_at_end("next")?;
+    func.push( acc + n );
+    func.push( n - 1 );
+    func.push( TailCall::new("sum", vec![n, acc]) );
+
+    // ten arguments, so some are passed on the stack (with every calling convention)
+    let args = vec![Type::i64; 10];
+
+    let func = contxt.add_function("rotate", args.clone(), Type::i64);
+    let asm = func.asm_func()?;
+
+    let a = asm.arg(0).unwrap();
+    let b = asm.arg(1).unwrap();
+    let c = asm.arg(2).unwrap();
+
+    func.push( TailCall::new("last", vec![b, c, a, b, c, a, b, c, a, b]) );
+
+    let func = contxt.add_function("last", args.clone(), Type::i64);
+    let asm = func.asm_func()?;
+
+    let a = asm.arg(0).unwrap();
+    let b = asm.arg(1).unwrap();
+    let c = asm.arg(2).unwrap();
+    let out = asm.var(Type::i64).unwrap();
+
+    func.push( Call::new("tenth", vec![a, b, c, a, b, c, a, b, c, a], Some(out)) );
+    func.push( Return::new(out) );
+
+    contxt.add_extern_at("tenth", tenth as *const () as usize);
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(u64, u64) -> u64> = contxt.get_jit_function("sum")?;
+        assert_eq!(func.call(10_000_000, 0), 10_000_000 * 10_000_001 / 2);
+
+        let mut func: JitFunction<unsafe extern "C" fn(i64, i64, i64, i64, i64, i64, i64, i64, i64, i64) -> i64> = contxt.get_jit_function("rotate")?;
+        assert_eq!(func.call(1, 2, 3, 0, 0, 0, 0, 0, 0, 0), 2312);
+    }
+
+    // the stack arguments don't fit into the ones of the caller
+    let func = contxt.add_function("invalid", vec![], Type::i64);
+    let asm = func.asm_func()?;
+
+    let a = asm.var(Type::i64).unwrap();
+
+    func.push( TailCall::new("last", vec![a; 10]) );
+
+    assert!(unsafe { contxt.get_jit_function::<unsafe extern "C" fn()>("invalid") }.is_err());
+
+    Ok(())
+}
+
+extern "C" fn tenth(a: i64, b: i64, c: i64, _: i64, _: i64, _: i64, _: i64, _: i64, _: i64, last: i64) -> i64 {
+    a * 1000 + b * 100 + c * 10 + last
+}
+
+#[test]
+fn variadic() -> Result<(), Box<dyn Error>> {
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+    contxt.add_extern("snprintf");
+
+    let func = contxt.add_function("format", vec![Type::ptr, Type::u64, Type::ptr, Type::i8, Type::f32, Type::u16, Type::f64], Type::i32);
+    let asm = func.asm_func()?;
+
+    let args: Vec<VarGen> = (0..7).map(|nr| asm.arg(nr).unwrap()).collect();
+    let out = asm.var(Type::i32).unwrap();
+
+    func.push( Call::new_variadic("snprintf", args, 3, Some(out)) );
+    func.push( Return::new(out) );
+
+    // more arguments than registers, so the promoted ones are also passed on the stack
+    let func = contxt.add_function("many", vec![Type::ptr, Type::ptr, Type::f32, Type::i16], Type::i32);
+    let asm = func.asm_func()?;
+
+    let buf = asm.arg(0).unwrap();
+    let fmt = asm.arg(1).unwrap();
+    let x = asm.arg(2).unwrap();
+    let n = asm.arg(3).unwrap();
+    let size = asm.var(Type::u64).unwrap();
+    let out = asm.var(Type::i32).unwrap();
+
+    let mut args = vec![buf, size, fmt];
+    args.extend([x; 10]);
+    args.extend([n; 6]);
+
+    func.push( size & 0 );
+    func.push( size + 100 );
+    func.push( Call::new_variadic("snprintf", args, 3, Some(out)) );
+    func.push( Return::new(out) );
+
+    #[cfg(unix)]
+    unsafe {
+        let mut buf = [0_u8; 100];
+
+        let mut func: JitFunction<unsafe extern "C" fn(*mut u8, u64, *const u8, i8, f32, u16, f64) -> i32> = contxt.get_jit_function("format")?;
+        let len = func.call(buf.as_mut_ptr(), 100, c"%d %.1f %u %.2f".as_ptr() as *const u8, -5, 1.5, 65535, 2.25);
+
+        assert_eq!(&buf[..len as usize], b"-5 1.5 65535 2.25");
+
+        let mut func: JitFunction<unsafe extern "C" fn(*mut u8, *const u8, f32, i16) -> i32> = contxt.get_jit_function("many")?;
+        let len = func.call(buf.as_mut_ptr(), c"%g %g %g %g %g %g %g %g %g %g %d %d %d %d %d %d".as_ptr() as *const u8, 0.5, -300);
+
+        assert_eq!(&buf[..len as usize], b"0.5 0.5 0.5 0.5 0.5 0.5 0.5 0.5 0.5 0.5 -300 -300 -300 -300 -300 -300");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn inline_asm() -> Result<(), Box<dyn Error>> {
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    // the high half of a * b plus c (c lives in a clobbered register with some calling conventions)
+    let func = contxt.add_function("mulhi", vec![Type::u64, Type::u64, Type::u64], Type::u64);
+    let asm = func.asm_func()?;
+
+    let a = asm.arg(0).unwrap();
+    let b = asm.arg(1).unwrap();
+    let c = asm.arg(2).unwrap();
+    let out = asm.var(Type::u64).unwrap();
+
+    func.push( InlineAsm::new(
+        |asm| asm.mul(rcx), 
+        vec![(Register::RAX, a), (Register::RCX, b)], 
+        vec![(out, Register::RDX)], 
+        vec![Register::RAX, Register::RDX],
+    ) );
+    func.push( out + c );
+    func.push( Return::new(out) );
+
+    let func = contxt.add_function("intel", vec![Type::u32, Type::u32, Type::ptr], Type::u32);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+    let y = asm.arg(1).unwrap();
+    let ptr = asm.arg(2).unwrap();
+    let out = asm.var(Type::u32).unwrap();
+
+    func.push( InlineAsm::intel(
+        "lea eax, [rcx + rdx*4 + 8]
+        imul eax, eax, 3 # times three
+        lock add qword ptr [r9], 0x10; mov r8d, -1 // clobbered",
+        vec![(Register::ECX, x), (Register::EDX, y), (Register::R9, ptr)],
+        vec![(out, Register::EAX)],
+        vec![Register::R8],
+    ) );
+    func.push( Return::new(out) );
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn(u64, u64, u64) -> u64> = contxt.get_jit_function("mulhi")?;
+        assert_eq!(func.call(u64::MAX, 16, 1), 16);
+
+        let mut value = 5_u64;
+
+        let mut func: JitFunction<unsafe extern "C" fn(u32, u32, *mut u64) -> u32> = contxt.get_jit_function("intel")?;
+        assert_eq!(func.call(1, 2, &mut value), 51);
+        assert_eq!(value, 21);
+    }
+
+    // callee saved registers are restored when returning (and before tail calls)
+    let func = contxt.add_function("clobber", vec![], Type::void);
+    func.push( InlineAsm::intel("mov rbx, 1; mov r12, 2", vec![], vec![], vec![Register::RBX, Register::R12]) );
+    func.push( Return::new(()) );
+
+    let func = contxt.add_function("tail_clobber", vec![], Type::void);
+    func.push( InlineAsm::intel("mov rbx, 3", vec![], vec![], vec![Register::RBX]) );
+    func.push( TailCall::new("clobber", vec![]) );
+
+    let func = contxt.add_function("keep", vec![], Type::u64);
+    let asm = func.asm_func()?;
+
+    let out = asm.var(Type::u64).unwrap();
+
+    func.push( InlineAsm::intel("mov rbx, 40; mov r12, 2", vec![], vec![], vec![Register::RBX, Register::R12]) );
+    func.push( Call::new("clobber", vec![], None) );
+    func.push( Call::new("tail_clobber", vec![], None) );
+    func.push( InlineAsm::intel("lea rax, [rbx + r12]", vec![], vec![(out, Register::RAX)], vec![]) );
+    func.push( Return::new(out) );
+
+    unsafe {
+        let mut func: JitFunction<unsafe extern "C" fn() -> u64> = contxt.get_jit_function("keep")?;
+        assert_eq!(func.call(), 42);
+    }
+
+    // the frame pointer can't be changed
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let func = contxt.add_function("invalid", vec![], Type::void);
+    func.push( InlineAsm::intel("xor ebp, ebp", vec![], vec![], vec![Register::RBP]) );
+
+    assert!(unsafe { contxt.get_jit_function::<unsafe extern "C" fn()>("invalid") }.is_err());
+
+    for code in ["add [rax], 1", "foo rax", "mov rax, [rbx*3]", "add eax, rbx"] {
+        let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+        let func = contxt.add_function("invalid", vec![], Type::u64);
+        func.push( InlineAsm::intel(code, vec![], vec![], vec![]) );
+
+        assert!(unsafe { contxt.get_jit_function::<unsafe extern "C" fn()>("invalid") }.is_err(), "{}", code);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn select() -> Result<(), Box<dyn Error>> {
+    // floats are blended with avx (and branched over without it)
+    for avx in [false, true] {
+        if avx && !std::arch::is_x86_feature_detected!("avx") {
+            continue;
+        }
+
+        let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+        if avx {
+            contxt.enable_avx();
+        }
+
+        for (name, typ) in [("clamp", Type::i32), ("fclamp", Type::f64)] {
+            let func = contxt.add_function(name, vec![typ, typ, typ], typ);
+            let asm = func.asm_func()?;
+
+            let x = asm.arg(0).unwrap();
+            let lo = asm.arg(1).unwrap();
+            let hi = asm.arg(2).unwrap();
+            let cond = asm.var(Type::bool).unwrap();
+
+            func.push( x.lt(lo, cond) );
+            func.push( Select::new(x, cond, lo, x) );
+            func.push( x.gt(hi, cond) );
+            func.push( Select::new(x, cond, hi, x) );
+            func.push( Return::new(x) );
+        }
+
+        // out is the first value and the condition isn't a bool
+        for (name, typ) in [("pick", Type::u8), ("fpick", Type::f32)] {
+            let func = contxt.add_function(name, vec![typ, typ, Type::u64], typ);
+            let asm = func.asm_func()?;
+
+            let a = asm.arg(0).unwrap();
+            let b = asm.arg(1).unwrap();
+            let flag = asm.arg(2).unwrap();
+
+            func.push( Select::new(a, flag, a, b) );
+            func.push( Return::new(a) );
+        }
+
+        let f32x4 = Type::vector_of(Type::f32, 4);
+
+        let func = contxt.add_function("lanes", vec![Type::ptr, Type::ptr, Type::bool], Type::void);
+        let asm = func.asm_func()?;
+
+        let pa = asm.arg(0).unwrap();
+        let pb = asm.arg(1).unwrap();
+        let cond = asm.arg(2).unwrap();
+        let a = asm.var(f32x4).unwrap();
+        let b = asm.var(f32x4).unwrap();
+        let out = asm.var(f32x4).unwrap();
+
+        func.push( Load::new(a, pa) );
+        func.push( Load::new(b, pb) );
+        func.push( Select::new(out, cond, a, b) );
+        func.push( Store::new(pa, out) );
+        func.push( Return::new(()) );
+
+        unsafe {
+            let mut func: JitFunction<unsafe extern "C" fn(i32, i32, i32) -> i32> = contxt.get_jit_function("clamp")?;
+            assert_eq!(func.call(-5, -2, 10), -2);
+            assert_eq!(func.call(5, -2, 10), 5);
+            assert_eq!(func.call(50, -2, 10), 10);
+
+            let mut func: JitFunction<unsafe extern "C" fn(f64, f64, f64) -> f64> = contxt.get_jit_function("fclamp")?;
+            assert_eq!(func.call(-0.5, 0.0, 1.0), 0.0);
+            assert_eq!(func.call(0.25, 0.0, 1.0), 0.25);
+            assert_eq!(func.call(1.5, 0.0, 1.0), 1.0);
+
+            let mut func: JitFunction<unsafe extern "C" fn(u8, u8, u64) -> u8> = contxt.get_jit_function("pick")?;
+            assert_eq!(func.call(1, 2, 1 << 40), 1);
+            assert_eq!(func.call(1, 2, 0), 2);
+            assert_eq!(func.call(1, 2, -5_i64 as u64), 1);
+
+            let mut func: JitFunction<unsafe extern "C" fn(f32, f32, u64) -> f32> = contxt.get_jit_function("fpick")?;
+            assert_eq!(func.call(1.5, 2.5, 1 << 40), 1.5);
+            assert_eq!(func.call(1.5, 2.5, 0), 2.5);
+            assert_eq!(func.call(1.5, 2.5, -5_i64 as u64), 1.5);
+
+            let mut a = [1.0_f32, 2.0, 3.0, 4.0];
+            let b = [5.0_f32, 6.0, 7.0, 8.0];
+
+            let mut func: JitFunction<unsafe extern "C" fn(*mut f32, *const f32, bool)> = contxt.get_jit_function("lanes")?;
+            func.call(a.as_mut_ptr(), b.as_ptr(), true);
+            assert_eq!(a, [1.0, 2.0, 3.0, 4.0]);
+            func.call(a.as_mut_ptr(), b.as_ptr(), false);
+            assert_eq!(a, b);
+        }
+    }
+
+    // stack slots aren't supported
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let func = contxt.add_function("invalid", vec![Type::bool, Type::i64], Type::i64);
+    let asm = func.asm_func()?;
+
+    let cond = asm.arg(0).unwrap();
+    let x = asm.arg(1).unwrap();
+    let slot = asm.alloca(Type::i64);
+
+    func.push( Select::new(x, cond, slot, x) );
+    func.push( Return::new(x) );
+
+    assert!(unsafe { contxt.get_jit_function::<unsafe extern "C" fn()>("invalid") }.is_err());
+
+    Ok(())
+}
+
+#[test]
+fn recompile() -> Result<(), Box<dyn Error>> {
+    let mut contxt = Context::new(target_lexicon::Triple::host())?;
+
+    let func = contxt.add_function("scale", vec![Type::f64], Type::f64);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+
+    func.push( x * 2.5_f64 );
+    func.push( Return::new(*(x + 1.0_f64)) );
+
+    // every call compiles the ir again
+    for _ in 0..2 {
+        unsafe {
+            let mut func: JitFunction<unsafe extern "C" fn(f64) -> f64> = contxt.get_jit_function("scale")?;
+            assert_eq!(func.call(2.0), 6.0);
+        }
+    }
+
+    let path = std::env::temp_dir().join("rllvm_recompile.o");
+    contxt.write(path.to_str().unwrap())?;
+
+    let data = std::fs::read(&path)?;
+    std::fs::remove_file(&path)?;
+
+    let file = object::File::parse(&*data)?;
+    let constants: Vec<_> = file.symbols().filter_map(|sym| sym.name().ok()).filter(|name| name.starts_with(".Lscale.")).collect();
+
+    // the constants of the earlier compilations aren't kept
+    assert_eq!(constants.len(), 2, "{:?}", constants);
+
+    Ok(())
+}
+
+#[test]
+fn print_ir() -> Result<(), Box<dyn Error>> {
+    // a fixed target, so the registers of the variables are known
+    let mut contxt = Context::new("x86_64-unknown-linux-gnu".parse().unwrap())?;
+    contxt.add_extern("abs");
+    contxt.add_global("limit", Type::i64, None, false);
+
+    let func = contxt.add_function("clamp", vec![Type::i64, Type::ptr], Type::i64);
+    let asm = func.asm_func()?;
+
+    let x = asm.arg(0).unwrap();
+    let ptr = asm.arg(1).unwrap();
+    let hi = asm.var(Type::i64).unwrap();
+    let cond = asm.var(Type::bool).unwrap();
+    let v = asm.var(Type::vector_of(Type::f32, 4)).unwrap();
+
+    func.push( Load::new(hi, ptr) );
+    func.push( x.gt(hi, cond) );
+    func.push( CondBr::new(cond, "high", "done") );
+
+    func.add_block("high");
+    func.position_at_end("high")?;
+    func.push( Select::new(x, cond, hi, x) );
+    func.push( Br::new("done") );
+
+    func.add_block("done");
+    func.position_at_end("done")?;
+    func.push( Load::new(v, ptr) );
+    func.push( Call::new("abs", vec![x], Some(x)) );
+    func.push( Return::new(x) );
+
+    assert_eq!(contxt.to_string(), r#"declare @abs
+@limit = constant i64 zeroinitializer
+
+define i64 @clamp(i64 %rdi, ptr %rsi) {
+entry:
+  %r10 = load i64, ptr %rsi
+  %al = icmp sgt i64 %rdi, %r10
+  br bool %al, label %high, label %done
+high:
+  %rdi = select bool %al, i64 %r10, i64 %rdi
+  br label %done
+done:
+  %xmm8 = load <4 x f32>, ptr %rsi
+  %rdi = call i64 @abs(i64 %rdi)
+  ret i64 %rdi
+}
+"#);
+
+    Ok(())
+}
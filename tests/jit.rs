@@ -1,4 +1,4 @@
-use rllvm::contxt::{jit::JitFunction, link::{JitLinker, Link}};
+use rllvm::contxt::{jit::JitFunction, link::{JitLinker, Link, LinkKind}};
 
 #[test]
 pub fn jit_function() {
@@ -28,7 +28,7 @@ fn jit_linker() {
         0xc3,                           // ret
     ], false);
 
-    linker.relocs.push( Link { from: "main".into(), to: "test".into(), at: 1, size: 4, replace: false});
+    linker.relocs.push( Link { from: "main".into(), to: "test".into(), at: 1, size: 4, replace: false, kind: LinkKind::Relative});
 
     let mut func: JitFunction<unsafe extern "C" fn() -> u32> = unsafe { linker.engine() };
     